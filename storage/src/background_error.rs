@@ -0,0 +1,72 @@
+use std::{fmt, sync::Arc};
+
+use parking_lot::{Condvar, Mutex};
+
+use crate::error::{LiteDbError, LiteDbResult};
+
+/// The first error hit by a background flush or compaction.
+///
+/// The tables may be missing the work that failed, so once an error is
+/// recorded the background work stops and writes fail with it. Callers
+/// waiting for a flush are woken up by both the flush and the error.
+#[derive(Clone, Default)]
+pub(crate) struct BackgroundError {
+    inner: Arc<BackgroundErrorInner>,
+}
+
+#[derive(Default)]
+struct BackgroundErrorInner {
+    error: Mutex<Option<Arc<LiteDbError>>>,
+    progress: Condvar,
+}
+
+impl BackgroundError {
+    /// Records the error unless another one was recorded first.
+    pub fn record(&self, err: LiteDbError) {
+        let mut error = self.inner.error.lock();
+        if error.is_none() {
+            *error = Some(Arc::new(err));
+        }
+        self.inner.progress.notify_all();
+    }
+
+    /// Fails with the recorded error, if any.
+    pub fn check(&self) -> LiteDbResult<()> {
+        Self::check_error(&self.inner.error.lock())
+    }
+
+    /// Wakes the callers of `wait_until` up so that they check their condition again.
+    pub fn notify(&self) {
+        let _error = self.inner.error.lock();
+        self.inner.progress.notify_all();
+    }
+
+    /// Waits until `done` holds, or fails as soon as an error is recorded.
+    ///
+    /// `done` must only change before a `notify`.
+    pub fn wait_until(&self, done: impl Fn() -> bool) -> LiteDbResult<()> {
+        let mut error = self.inner.error.lock();
+        loop {
+            Self::check_error(&error)?;
+            if done() {
+                return Ok(());
+            }
+            self.inner.progress.wait(&mut error);
+        }
+    }
+
+    fn check_error(error: &Option<Arc<LiteDbError>>) -> LiteDbResult<()> {
+        match error {
+            Some(err) => Err(LiteDbError::Background(err.clone())),
+            None => Ok(()),
+        }
+    }
+}
+
+impl fmt::Debug for BackgroundError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BackgroundError")
+            .field("error", &self.inner.error.lock())
+            .finish()
+    }
+}
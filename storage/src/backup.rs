@@ -0,0 +1,551 @@
+use std::{
+    collections::BTreeSet,
+    fs::{self, File, OpenOptions},
+    io::{self, BufReader, BufWriter, ErrorKind, Read, Write},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use bincode::{Decode, Encode};
+use crc::{Crc, CRC_32_ISCSI};
+use fs2::FileExt;
+
+use crate::{
+    error::{LiteDbError, LiteDbResult},
+    utils::{create_temp_dir_for, decode_from_reader, encode_into_writer},
+    wal_archive::{replay_archived_wals, RestoreTarget},
+    LiteDb,
+};
+
+const SHARED_DIR_NAME: &str = "shared";
+const META_DIR_NAME: &str = "meta";
+const CHECKPOINT_DIR_NAME: &str = "checkpoint";
+const TEMP_FILE_EXTENSION: &str = "tmp";
+const LOCK_FILE_NAME: &str = "LOCK";
+
+/// A file of a backup, stored once in the shared directory for every backup holding it.
+#[derive(Debug, Clone, Encode, Decode)]
+struct BackupFile {
+    /// Name of the file in the database directory.
+    name: String,
+    /// Name of the file in the shared directory, derived from its content.
+    shared_name: String,
+    crc: u32,
+    size: u64,
+}
+
+#[derive(Debug, Encode, Decode)]
+struct BackupMeta {
+    /// Seconds since the unix epoch.
+    timestamp: u64,
+    files: Vec<BackupFile>,
+}
+
+/// Describes a backup kept by a `BackupEngine`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BackupInfo {
+    pub backup_id: u64,
+    /// Seconds since the unix epoch.
+    pub timestamp: u64,
+    pub num_files: usize,
+    pub size_bytes: u64,
+}
+
+/// Keeps numbered backups of a database in a directory.
+///
+/// The files of a backup are stored by content, so the ss_tables left
+/// unchanged between backups are stored once. Backups can be created
+/// concurrently, purges wait for them to complete.
+pub struct BackupEngine {
+    dir: PathBuf,
+}
+
+impl BackupEngine {
+    pub fn open<P: AsRef<Path>>(dir: P) -> LiteDbResult<Self> {
+        let dir = PathBuf::from(dir.as_ref());
+        fs::create_dir_all(dir.join(SHARED_DIR_NAME))?;
+        fs::create_dir_all(dir.join(META_DIR_NAME))?;
+        Ok(Self { dir })
+    }
+
+    /// Backs up a checkpoint of the database, returns the id of the new backup.
+    ///
+    /// Several backups can be created at once, each one reserves its id first.
+    pub fn create_backup(&self, db: &LiteDb) -> LiteDbResult<u64> {
+        let _lock = self.lock(false)?;
+        let backup_id = self.reserve_backup_id()?;
+        let checkpoint_path = self.dir.join(format!("{CHECKPOINT_DIR_NAME}_{backup_id}"));
+        let result = self.write_backup(db, backup_id, &checkpoint_path);
+        if result.is_err() {
+            let _ = fs::remove_dir_all(&checkpoint_path);
+            let _ = fs::remove_file(
+                self.meta_file_path(backup_id)
+                    .with_extension(TEMP_FILE_EXTENSION),
+            );
+        }
+        result.map(|_| backup_id)
+    }
+
+    fn write_backup(
+        &self,
+        db: &LiteDb,
+        backup_id: u64,
+        checkpoint_path: &Path,
+    ) -> LiteDbResult<()> {
+        if checkpoint_path.exists() {
+            fs::remove_dir_all(checkpoint_path)?;
+        }
+        db.checkpoint(checkpoint_path)?;
+
+        let mut files = vec![];
+        for entry_result in fs::read_dir(checkpoint_path)? {
+            let path = entry_result?.path();
+            let name = path
+                .file_name()
+                .expect("Expected a file name")
+                .to_string_lossy()
+                .to_string();
+            let (crc, size) = file_checksum(&path)?;
+            let shared_name = shared_file_name(&name, crc, size);
+            let shared_path = self.shared_file_path(&shared_name);
+            // a concurrent backup renaming the same file over it leaves the same content
+            if !shared_path.exists() {
+                fs::rename(&path, &shared_path)?;
+            }
+            files.push(BackupFile {
+                name,
+                shared_name,
+                crc,
+                size,
+            });
+        }
+        fs::remove_dir_all(checkpoint_path)?;
+
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_secs());
+        self.write_meta(backup_id, &BackupMeta { timestamp, files })
+    }
+
+    /// Returns the backups from oldest to newest.
+    pub fn get_backup_info(&self) -> LiteDbResult<Vec<BackupInfo>> {
+        self.backup_ids()?
+            .into_iter()
+            .map(|backup_id| {
+                let meta = self.read_meta(backup_id)?;
+                Ok(BackupInfo {
+                    backup_id,
+                    timestamp: meta.timestamp,
+                    num_files: meta.files.len(),
+                    size_bytes: meta.files.iter().map(|file| file.size).sum(),
+                })
+            })
+            .collect()
+    }
+
+    /// Checks the checksum of every file of the backup.
+    pub fn verify_backup(&self, backup_id: u64) -> LiteDbResult<()> {
+        let meta = self.read_meta(backup_id)?;
+        for file in &meta.files {
+            let shared_path = self.shared_file_path(&file.shared_name);
+            let checksum = match shared_path.exists() {
+                true => Some(file_checksum(&shared_path)?),
+                false => None,
+            };
+            if checksum != Some((file.crc, file.size)) {
+                return Err(LiteDbError::CorruptedBackup {
+                    backup_id,
+                    file: file.name.clone(),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Deletes all but the `num_backups_to_keep` newest backups.
+    pub fn purge_old_backups(&self, num_backups_to_keep: usize) -> LiteDbResult<()> {
+        // the shared files of the backups being created are not listed anywhere yet
+        let _lock = self.lock(true)?;
+        let backup_ids = self.backup_ids()?;
+        let num_backups_to_delete = backup_ids.len().saturating_sub(num_backups_to_keep);
+        for backup_id in backup_ids.into_iter().take(num_backups_to_delete) {
+            fs::remove_file(self.meta_file_path(backup_id))?;
+        }
+        self.garbage_collect()
+    }
+
+    /// Restores the backup into `db_dir`, which must not exist yet.
+    pub fn restore_db_from_backup<P: AsRef<Path>>(
+        &self,
+        backup_id: u64,
+        db_dir: P,
+    ) -> LiteDbResult<()> {
+        self.restore(backup_id, db_dir.as_ref(), |_| Ok(()))
+    }
+
+    /// Copies the backup into a temp dir, lets `prepare` finish it, then moves it to `db_path`.
+    fn restore(
+        &self,
+        backup_id: u64,
+        db_path: &Path,
+        prepare: impl FnOnce(&Path) -> LiteDbResult<()>,
+    ) -> LiteDbResult<()> {
+        if db_path.exists() {
+            return Err(LiteDbError::Io(io::Error::new(
+                ErrorKind::AlreadyExists,
+                format!("Restore directory {} already exists", db_path.display()),
+            )));
+        }
+        self.verify_backup(backup_id)?;
+        let meta = self.read_meta(backup_id)?;
+
+        // the database only shows up once complete, only its own temp dir is removed
+        let temp_path = create_temp_dir_for(db_path)?;
+        let result = self
+            .copy_files(&meta, &temp_path)
+            .and_then(|_| prepare(&temp_path))
+            .and_then(|_| Ok(fs::rename(&temp_path, db_path)?));
+        if result.is_err() {
+            let _ = fs::remove_dir_all(&temp_path);
+        }
+        result
+    }
+
+    /// Copies the files of the backup into `dir`.
+    fn copy_files(&self, meta: &BackupMeta, dir: &Path) -> LiteDbResult<()> {
+        for file in &meta.files {
+            let target_path = dir.join(&file.name);
+            fs::copy(self.shared_file_path(&file.shared_name), &target_path)?;
+            File::open(&target_path)?.sync_all()?;
+        }
+        Ok(())
+    }
+
+    /// Restores the backup into `db_dir`, then replays the writes archived since
+    /// up to `target`. `archive_dir` is the `archive` directory of the backed up database.
+    /// Nothing is restored when the archive misses some of those writes, or when the
+    /// backup is newer than `target`.
+    pub fn restore_db_to_point_in_time<P: AsRef<Path>, A: AsRef<Path>>(
+        &self,
+        backup_id: u64,
+        archive_dir: A,
+        target: RestoreTarget,
+        db_dir: P,
+    ) -> LiteDbResult<()> {
+        self.restore(backup_id, db_dir.as_ref(), |temp_path| {
+            replay_archived_wals(archive_dir.as_ref(), temp_path, &target)
+        })
+    }
+
+    pub fn restore_db_from_latest_backup<P: AsRef<Path>>(&self, db_dir: P) -> LiteDbResult<()> {
+        let backup_id = self
+            .backup_ids()?
+            .last()
+            .copied()
+            .ok_or(LiteDbError::NoBackup)?;
+        self.restore_db_from_backup(backup_id, db_dir)
+    }
+
+    /// Removes the shared files no backup refers to anymore.
+    fn garbage_collect(&self) -> LiteDbResult<()> {
+        let mut referenced_files = BTreeSet::new();
+        for backup_id in self.backup_ids()? {
+            for file in self.read_meta(backup_id)?.files {
+                referenced_files.insert(file.shared_name);
+            }
+        }
+        for entry_result in fs::read_dir(self.dir.join(SHARED_DIR_NAME))? {
+            let path = entry_result?.path();
+            let name = path.file_name().map(|name| name.to_string_lossy());
+            if !matches!(name, Some(name) if referenced_files.contains(name.as_ref())) {
+                fs::remove_file(&path)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn backup_ids(&self) -> LiteDbResult<Vec<u64>> {
+        let mut backup_ids = vec![];
+        for entry_result in fs::read_dir(self.dir.join(META_DIR_NAME))? {
+            let path = entry_result?.path();
+            // metadata being written is named after its backup with a temp extension
+            if let Some(backup_id) = path
+                .file_name()
+                .and_then(|name| name.to_string_lossy().parse().ok())
+            {
+                backup_ids.push(backup_id);
+            }
+        }
+        backup_ids.sort_unstable();
+        Ok(backup_ids)
+    }
+
+    /// Locks the backup directory until the returned file is dropped. Backups share
+    /// the lock, purges take it alone.
+    ///
+    /// Every call opens the lock file anew, `flock` does not keep two holders of the
+    /// same open file apart.
+    fn lock(&self, exclusive: bool) -> LiteDbResult<File> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(self.dir.join(LOCK_FILE_NAME))?;
+        match exclusive {
+            true => FileExt::lock_exclusive(&file)?,
+            false => FileExt::lock_shared(&file)?,
+        }
+        Ok(file)
+    }
+
+    /// Returns an id above all the backups, those being written included, and
+    /// reserves it by creating the temp file of its metadata.
+    fn reserve_backup_id(&self) -> LiteDbResult<u64> {
+        let mut backup_id = 1;
+        for entry_result in fs::read_dir(self.dir.join(META_DIR_NAME))? {
+            let path = entry_result?.path();
+            if let Some(id) = path
+                .file_stem()
+                .and_then(|stem| stem.to_string_lossy().parse::<u64>().ok())
+            {
+                backup_id = backup_id.max(id + 1);
+            }
+        }
+        loop {
+            let temp_file_path = self
+                .meta_file_path(backup_id)
+                .with_extension(TEMP_FILE_EXTENSION);
+            match OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(temp_file_path)
+            {
+                Ok(_) => return Ok(backup_id),
+                Err(err) if err.kind() == ErrorKind::AlreadyExists => backup_id += 1,
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+
+    fn read_meta(&self, backup_id: u64) -> LiteDbResult<BackupMeta> {
+        let file = File::open(self.meta_file_path(backup_id)).map_err(|err| match err.kind() {
+            ErrorKind::NotFound => LiteDbError::NoBackup,
+            _ => err.into(),
+        })?;
+        decode_from_reader(&mut BufReader::new(file))
+    }
+
+    fn write_meta(&self, backup_id: u64, meta: &BackupMeta) -> LiteDbResult<()> {
+        let meta_file_path = self.meta_file_path(backup_id);
+        let temp_file_path = meta_file_path.with_extension(TEMP_FILE_EXTENSION);
+        let file = File::create(&temp_file_path)?;
+        let mut writer = BufWriter::new(&file);
+        encode_into_writer(meta, &mut writer)?;
+        writer.flush()?;
+        drop(writer);
+        file.sync_all()?;
+        fs::rename(&temp_file_path, &meta_file_path)?;
+        Ok(())
+    }
+
+    fn meta_file_path(&self, backup_id: u64) -> PathBuf {
+        self.dir.join(META_DIR_NAME).join(backup_id.to_string())
+    }
+
+    fn shared_file_path(&self, shared_name: &str) -> PathBuf {
+        self.dir.join(SHARED_DIR_NAME).join(shared_name)
+    }
+}
+
+/// Names a shared file after the database file, its checksum and its size.
+fn shared_file_name(name: &str, crc: u32, size: u64) -> String {
+    match name.split_once('.') {
+        Some((stem, extension)) => format!("{stem}_{crc}_{size}.{extension}"),
+        None => format!("{name}_{crc}_{size}"),
+    }
+}
+
+/// Returns the crc32 and the size of the file content.
+fn file_checksum(path: &Path) -> LiteDbResult<(u32, u64)> {
+    let crc = Crc::<u32>::new(&CRC_32_ISCSI);
+    let mut digest = crc.digest();
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut buffer = [0; 8192];
+    let mut size = 0;
+    loop {
+        let num_bytes = reader.read(&mut buffer)?;
+        if num_bytes == 0 {
+            break;
+        }
+        digest.update(&buffer[..num_bytes]);
+        size += num_bytes as u64;
+    }
+    Ok((digest.finalize(), size))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, OpenOptions};
+    use std::io::Write;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::thread;
+
+    use tempfile::tempdir;
+
+    use crate::{
+        backup::{BackupEngine, SHARED_DIR_NAME},
+        error::LiteDbError,
+        options::LiteDbOptions,
+        LiteDb,
+    };
+
+    #[test]
+    fn test_backup_engine() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+        let db_path = temp_dir.path().join("data");
+        let backup_engine = BackupEngine::open(temp_dir.path().join("backup"))?;
+        let db = LiteDb::open(&db_path, LiteDbOptions::for_test())?;
+        for i in 0..1000 {
+            let k = format!("k_{:01$}", i, 4);
+            db.set(k.as_bytes(), b"v")?;
+        }
+        assert_eq!(backup_engine.create_backup(&db)?, 1);
+        for i in 0..100 {
+            let k = format!("k_{:01$}", i, 4);
+            db.set(k.as_bytes(), b"v2")?;
+        }
+        assert_eq!(backup_engine.create_backup(&db)?, 2);
+        drop(db);
+
+        // the ss_tables left unchanged are shared between both backups
+        let backup_infos = backup_engine.get_backup_info()?;
+        assert_eq!(backup_infos.len(), 2);
+        let num_files: usize = backup_infos.iter().map(|info| info.num_files).sum();
+        let num_shared_files = fs::read_dir(backup_engine.dir.join(SHARED_DIR_NAME))?.count();
+        assert!(num_shared_files < num_files);
+        backup_engine.verify_backup(1)?;
+        backup_engine.verify_backup(2)?;
+
+        // only the files of the newest backup are left after a purge
+        backup_engine.purge_old_backups(1)?;
+        let backup_infos = backup_engine.get_backup_info()?;
+        assert_eq!(backup_infos.len(), 1);
+        assert_eq!(backup_infos[0].backup_id, 2);
+        let num_shared_files = fs::read_dir(backup_engine.dir.join(SHARED_DIR_NAME))?.count();
+        assert_eq!(num_shared_files, backup_infos[0].num_files);
+
+        // an unrelated directory next to the restored database is left alone
+        let unrelated_path = temp_dir.path().join("restore.tmp");
+        fs::create_dir(&unrelated_path)?;
+        fs::write(unrelated_path.join("file"), b"unrelated")?;
+        let restore_path = temp_dir.path().join("restore");
+        backup_engine.restore_db_from_latest_backup(&restore_path)?;
+        assert_eq!(fs::read(unrelated_path.join("file"))?, b"unrelated");
+        let db = LiteDb::open(&restore_path, LiteDbOptions::for_test())?;
+        for i in 0..1000 {
+            let k = format!("k_{:01$}", i, 4);
+            let expected_value = if i < 100 {
+                b"v2".to_vec()
+            } else {
+                b"v".to_vec()
+            };
+            assert_eq!(db.get(k.as_bytes())?, Some(expected_value));
+        }
+
+        // a damaged shared file fails the verification
+        let shared_path = fs::read_dir(backup_engine.dir.join(SHARED_DIR_NAME))?
+            .next()
+            .unwrap()?
+            .path();
+        OpenOptions::new()
+            .append(true)
+            .open(shared_path)?
+            .write_all(b"garbage")?;
+        assert!(matches!(
+            backup_engine.verify_backup(2),
+            Err(LiteDbError::CorruptedBackup { backup_id: 2, .. })
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_concurrent_backups() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+        let backup_engine = BackupEngine::open(temp_dir.path().join("backup"))?;
+        let db = LiteDb::open(temp_dir.path().join("data"), LiteDbOptions::for_test())?;
+        for i in 0..500 {
+            let k = format!("k_{:01$}", i, 4);
+            db.set(k.as_bytes(), b"v")?;
+        }
+
+        let mut backup_ids = thread::scope(|scope| {
+            let handles: Vec<_> = (0..4)
+                .map(|_| scope.spawn(|| backup_engine.create_backup(&db)))
+                .collect();
+            handles
+                .into_iter()
+                .map(|handle| handle.join().expect("Expected the backup to complete"))
+                .collect::<Result<Vec<_>, _>>()
+        })?;
+        drop(db);
+        backup_ids.sort_unstable();
+        assert_eq!(backup_ids, vec![1, 2, 3, 4]);
+        for backup_id in backup_ids {
+            backup_engine.verify_backup(backup_id)?;
+        }
+        // only the shared and meta directories and the lock file are left
+        assert_eq!(fs::read_dir(&backup_engine.dir)?.count(), 3);
+
+        let restore_path = temp_dir.path().join("restore");
+        backup_engine.restore_db_from_backup(3, &restore_path)?;
+        let db = LiteDb::open(&restore_path, LiteDbOptions::for_test())?;
+        for i in 0..500 {
+            let k = format!("k_{:01$}", i, 4);
+            assert_eq!(db.get(k.as_bytes())?, Some(b"v".to_vec()));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_purge_during_backups() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+        let backup_engine = BackupEngine::open(temp_dir.path().join("backup"))?;
+        let db = LiteDb::open(temp_dir.path().join("data"), LiteDbOptions::for_test())?;
+        let done = AtomicBool::new(false);
+
+        let create_backups = || -> anyhow::Result<()> {
+            for round in 0..10 {
+                for i in 0..100 {
+                    let k = format!("k_{:01$}", i, 4);
+                    db.set(k.as_bytes(), format!("v{round}").as_bytes())?;
+                }
+                let backup_id = backup_engine.create_backup(&db)?;
+                // the purge may have removed it since, never its files while it exists
+                match backup_engine.verify_backup(backup_id) {
+                    Ok(()) | Err(LiteDbError::NoBackup) => (),
+                    Err(err) => return Err(err.into()),
+                }
+            }
+            Ok(())
+        };
+        thread::scope(|scope| -> anyhow::Result<()> {
+            let purge_handle = scope.spawn(|| -> anyhow::Result<()> {
+                while !done.load(Ordering::SeqCst) {
+                    backup_engine.purge_old_backups(1)?;
+                }
+                Ok(())
+            });
+            let result = create_backups();
+            done.store(true, Ordering::SeqCst);
+            purge_handle.join().unwrap()?;
+            result
+        })?;
+
+        backup_engine.purge_old_backups(1)?;
+        let backup_id = backup_engine.get_backup_info()?[0].backup_id;
+        assert_eq!(backup_id, 10);
+        backup_engine.verify_backup(backup_id)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,288 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs::{self, File},
+    io::{BufWriter, ErrorKind, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use bincode::{Decode, Encode};
+use crossbeam_skiplist::SkipSet;
+use memmap2::Mmap;
+
+use crate::{
+    error::{LiteDbError, LiteDbResult},
+    ss_table::SSTable,
+    utils::{crc32, decode, encode_into_writer},
+    RefValue, Value,
+};
+
+const BLOB_FILE_EXTENSION: &str = "blob";
+/// First byte of a value stored in an ss_table, tells what follows.
+const INLINE_VALUE_TAG: u8 = 0;
+const BLOB_INDEX_TAG: u8 = 1;
+/// Share of dead bytes from which compactions relocate the live values of a blob file.
+pub(crate) const BLOB_GC_GARBAGE_RATIO: f64 = 0.5;
+
+/// A blob file is named after the ss_table that wrote it: its id and level.
+///
+/// The merged table of a compaction takes the id of a table of the level
+/// below, the level keeps their blob files apart.
+pub(crate) type BlobFileId = (u64, usize);
+
+pub(crate) fn is_blob_file(path: &Path) -> bool {
+    path.is_file()
+        && path
+            .extension()
+            .map(|ext| ext == BLOB_FILE_EXTENSION)
+            .unwrap_or(false)
+}
+
+pub(crate) fn blob_file_path(dir: &Path, (id, level): BlobFileId) -> PathBuf {
+    dir.join(format!("{:01$}_{level}.{BLOB_FILE_EXTENSION}", id, 20))
+}
+
+/// Points at a value kept in a blob file.
+#[derive(Clone, Copy, Debug, Encode, Decode, PartialEq, Eq)]
+pub(crate) struct BlobIndex {
+    pub file_id: BlobFileId,
+    pub offset: u64,
+    pub len: u64,
+    pub checksum: u32,
+}
+
+/// Encodes a value kept in the ss_table itself.
+pub(crate) fn inline_value(value: RefValue) -> Value {
+    let mut stored = Vec::with_capacity(value.len() + 1);
+    stored.push(INLINE_VALUE_TAG);
+    stored.extend_from_slice(value);
+    stored
+}
+
+/// Encodes a pointer to a value kept in a blob file.
+pub(crate) fn blob_value(index: &BlobIndex) -> LiteDbResult<Value> {
+    let mut stored = vec![BLOB_INDEX_TAG];
+    encode_into_writer(index, &mut stored)?;
+    Ok(stored)
+}
+
+/// Returns the blob index a stored value points with, None for an inline value.
+pub(crate) fn blob_index(stored: RefValue) -> LiteDbResult<Option<BlobIndex>> {
+    match stored.first() {
+        Some(&INLINE_VALUE_TAG) => Ok(None),
+        Some(&BLOB_INDEX_TAG) => Ok(Some(decode(&stored[1..])?.0)),
+        _ => Err(LiteDbError::CorruptedData),
+    }
+}
+
+/// Reads the value a blob index points at.
+pub(crate) fn read_blob(
+    blob_files: &BTreeMap<BlobFileId, Mmap>,
+    index: &BlobIndex,
+    verify_checksum: bool,
+) -> LiteDbResult<Value> {
+    let blob_file = blob_files
+        .get(&index.file_id)
+        .ok_or(LiteDbError::CorruptedData)?;
+    let value = blob_file
+        .get(index.offset as usize..(index.offset + index.len) as usize)
+        .ok_or(LiteDbError::CorruptedData)?;
+    if verify_checksum && crc32(&[], value) != index.checksum {
+        return Err(LiteDbError::CorruptedData);
+    }
+    Ok(value.to_vec())
+}
+
+/// Maps the blob files an ss_table of `dir` points into.
+///
+/// Readers keep the mappings, a blob file deleted after a compaction stays
+/// readable until the last ss_table pointing into it is dropped.
+pub(crate) fn map_blob_files<'a, I: IntoIterator<Item = &'a BlobFileId>>(
+    dir: &Path,
+    file_ids: I,
+) -> LiteDbResult<BTreeMap<BlobFileId, Mmap>> {
+    let mut blob_files = BTreeMap::new();
+    for file_id in file_ids {
+        let file = File::open(blob_file_path(dir, *file_id))?;
+        let mmap = unsafe { Mmap::map(&file)? };
+        blob_files.insert(*file_id, mmap);
+    }
+    Ok(blob_files)
+}
+
+/// Returns the blob files the ss_tables point into.
+pub(crate) fn referenced_blob_files(ss_tables: &SkipSet<Arc<SSTable>>) -> BTreeSet<BlobFileId> {
+    let mut file_ids = BTreeSet::new();
+    for entry in ss_tables.iter() {
+        file_ids.extend(entry.value().blob_references().keys().copied());
+    }
+    file_ids
+}
+
+/// Deletes the blob files of `dir` no ss_table points into, like the ones
+/// written by a flush or a compaction that did not complete.
+pub(crate) fn remove_unreferenced_blob_files<'a, I: IntoIterator<Item = &'a BlobFileId>>(
+    dir: &Path,
+    referenced_file_ids: I,
+) -> LiteDbResult<()> {
+    let referenced_paths = referenced_file_ids
+        .into_iter()
+        .map(|file_id| blob_file_path(dir, *file_id))
+        .collect::<Vec<_>>();
+    for entry_result in fs::read_dir(dir)? {
+        let path = entry_result?.path();
+        if is_blob_file(&path) && !referenced_paths.contains(&path) {
+            remove_blob_file(&path)?;
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn remove_blob_file(path: &Path) -> LiteDbResult<()> {
+    match fs::remove_file(path) {
+        Err(err) if err.kind() != ErrorKind::NotFound => Err(err.into()),
+        _ => Ok(()),
+    }
+}
+
+/// Appends the large values of an ss_table being built to its blob file.
+pub(crate) struct BlobFileWriter {
+    file_id: BlobFileId,
+    writer: BufWriter<File>,
+    size: u64,
+}
+
+impl BlobFileWriter {
+    pub fn create(dir: &Path, file_id: BlobFileId) -> LiteDbResult<Self> {
+        // a leftover of an interrupted build is not referenced, it is overwritten
+        let file = File::create(blob_file_path(dir, file_id))?;
+        Ok(Self {
+            file_id,
+            writer: BufWriter::new(file),
+            size: 0,
+        })
+    }
+
+    pub fn append(&mut self, value: RefValue) -> LiteDbResult<BlobIndex> {
+        self.writer.write_all(value)?;
+        let index = BlobIndex {
+            file_id: self.file_id,
+            offset: self.size,
+            len: value.len() as u64,
+            checksum: crc32(&[], value),
+        };
+        self.size += value.len() as u64;
+        Ok(index)
+    }
+
+    /// Syncs the blob file, it must be durable before an ss_table points into it.
+    pub fn finish(self) -> LiteDbResult<()> {
+        let file = self.writer.into_inner().map_err(|err| err.into_error())?;
+        file.sync_all()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::Path, sync::Arc};
+
+    use crossbeam_skiplist::SkipSet;
+    use tempfile::tempdir;
+
+    use crate::{
+        blob::{blob_file_path, is_blob_file},
+        compactor::{compact, SizeTieredCompactor},
+        error::LiteDbResult,
+        manifest::Manifest,
+        options::LiteDbOptions,
+        ss_table::{SSTable, SSTableOptions},
+        utils::AtomicOperationExecutor,
+        LiteDb, ReadOptions, Scannable,
+    };
+
+    fn ss_table_options() -> SSTableOptions {
+        SSTableOptions {
+            min_blob_size: Some(100),
+            ..SSTableOptions::for_test()
+        }
+    }
+
+    fn create_ss_table(path: &Path, id: u64, keys: &[usize], value: &[u8]) -> Arc<SSTable> {
+        let entries = keys.iter().map(|i| (format!("k_{:01$}", i, 2), value));
+        SSTable::create_for_test(path, id, entries, &ss_table_options()).unwrap()
+    }
+
+    #[test]
+    fn test_blob_files() -> LiteDbResult<()> {
+        let temp_dir = tempdir()?;
+        let db_path = temp_dir.path().join("data");
+        let db = LiteDb::open(
+            &db_path,
+            LiteDbOptions {
+                min_blob_size: Some(100),
+                ..LiteDbOptions::for_test()
+            },
+        )?;
+        db.set(b"small", b"v")?;
+        db.set(b"large", &[b'v'; 1000])?;
+        db.flush()?;
+        let num_blob_files = fs::read_dir(&db_path)?
+            .filter(|entry| is_blob_file(&entry.as_ref().unwrap().path()))
+            .count();
+        assert_eq!(num_blob_files, 1);
+        assert_eq!(db.get(b"small")?, Some(b"v".to_vec()));
+        assert_eq!(db.get(b"large")?, Some(vec![b'v'; 1000]));
+        drop(db);
+
+        // 8 of the 10 values of the first blob file get overwritten
+        let tables_path = temp_dir.path().join("tables");
+        fs::create_dir_all(&tables_path)?;
+        let ss_tables = SkipSet::new();
+        let all_keys = (0..10).collect::<Vec<_>>();
+        ss_tables.insert(create_ss_table(&tables_path, 1, &all_keys, &[b'a'; 200]));
+        ss_tables.insert(create_ss_table(
+            &tables_path,
+            2,
+            &all_keys[..8],
+            &[b'b'; 200],
+        ));
+        ss_tables.insert(create_ss_table(&tables_path, 3, &[20], b"c"));
+        let executor = AtomicOperationExecutor::new();
+        compact(
+            &ss_tables,
+            &SizeTieredCompactor::new(3),
+            &executor,
+            &ss_table_options(),
+            &Manifest::new(tables_path.clone()),
+        )?;
+
+        // the live values of the mostly dead blob file are relocated, then it is deleted
+        assert!(!blob_file_path(&tables_path, (1, 0)).exists());
+        assert!(blob_file_path(&tables_path, (2, 0)).exists());
+        assert!(blob_file_path(&tables_path, (3, 1)).exists());
+        let ss_table = ss_tables.front().unwrap().value().clone();
+        let read_options = ReadOptions {
+            verify_checksums: true,
+            ..ReadOptions::default()
+        };
+        let entries = ss_table
+            .scan_with_options(&None, &None, &read_options)
+            .collect::<LiteDbResult<Vec<_>>>()?;
+        assert_eq!(entries.len(), 11);
+        for (i, (_, value)) in entries.iter().enumerate() {
+            let expected_value: &[u8] = match i {
+                0..=7 => &[b'b'; 200],
+                8 | 9 => &[b'a'; 200],
+                _ => b"c",
+            };
+            assert_eq!(value.as_slice(), expected_value);
+        }
+        let reopened = Arc::new(SSTable::open(ss_table.path().to_path_buf())?);
+        assert_eq!(
+            SSTable::get(reopened, b"k_09", &read_options)?,
+            Some(vec![b'a'; 200])
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,90 @@
+use std::{fmt, sync::Arc};
+
+use parking_lot::Mutex;
+
+use crate::{error::LiteDbResult, lru_cache::LruCache, ss_table::SSTableSparseIndex};
+
+/// Identifies a block: the cache id of its ss_table and its offset in the file.
+pub(crate) type BlockKey = (u64, usize);
+
+/// Keeps the most recently used blocks of ss_tables in memory, within a budget.
+///
+/// Only the partitions of partitioned indexes go through it. The cache can be
+/// shared by several databases, blocks are evicted least recently used first.
+#[derive(Clone)]
+pub struct BlockCache {
+    blocks: Arc<Mutex<LruCache<BlockKey, Arc<SSTableSparseIndex>>>>,
+}
+
+impl BlockCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            blocks: Arc::new(Mutex::new(LruCache::new(capacity))),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.blocks.lock().capacity()
+    }
+
+    /// Returns the bytes held by the cached blocks.
+    pub fn usage(&self) -> usize {
+        self.blocks.lock().usage()
+    }
+
+    /// Returns the cached block, or loads and caches it.
+    pub(crate) fn get_or_load<F>(
+        &self,
+        key: BlockKey,
+        load: F,
+    ) -> LiteDbResult<Arc<SSTableSparseIndex>>
+    where
+        F: FnOnce() -> LiteDbResult<SSTableSparseIndex>,
+    {
+        if let Some(block) = self.blocks.lock().get(&key) {
+            return Ok(block);
+        }
+        // loaded without the lock, concurrent misses may load the same block twice
+        let block = Arc::new(load()?);
+        let charge = block.size_bytes();
+        self.blocks.lock().insert(key, block.clone(), charge);
+        Ok(block)
+    }
+}
+
+impl fmt::Debug for BlockCache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BlockCache")
+            .field("capacity", &self.capacity())
+            .field("usage", &self.usage())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{block_cache::BlockCache, error::LiteDbResult, ss_table::SSTableSparseIndex};
+
+    fn block(key: &str) -> LiteDbResult<SSTableSparseIndex> {
+        Ok(SSTableSparseIndex::from(vec![(key.as_bytes().to_vec(), 0)]))
+    }
+
+    #[test]
+    fn test_block_cache() -> LiteDbResult<()> {
+        let block_size = block("a")?.size_bytes();
+        let cache = BlockCache::new(2 * block_size);
+        let cache_id = 1;
+        cache.get_or_load((cache_id, 0), || block("a"))?;
+        cache.get_or_load((cache_id, 1), || block("b"))?;
+        assert_eq!(cache.usage(), 2 * block_size);
+
+        // the least recently used block is evicted first
+        cache.get_or_load((cache_id, 0), || panic!("Expected a cached block."))?;
+        cache.get_or_load((cache_id, 2), || block("c"))?;
+        assert_eq!(cache.usage(), 2 * block_size);
+        cache.get_or_load((cache_id, 0), || panic!("Expected a cached block."))?;
+        let reloaded = cache.get_or_load((cache_id, 1), || block("d"))?;
+        assert_eq!(*reloaded, block("d")?);
+        Ok(())
+    }
+}
@@ -1,29 +1,267 @@
+use std::f64::consts::LN_2;
+
 use bincode::{Decode, Encode};
-use bloomfilter::Bloom;
 
-use crate::Key;
+use crate::RefKey;
+
+/// Size of a filter block, one cache line.
+const BLOCK_SIZE_BYTES: usize = 64;
+const BLOCK_SIZE_BITS: u32 = (BLOCK_SIZE_BYTES * 8) as u32;
 
-#[derive(Encode, Decode)]
-pub struct BloomFilterState {
+/// A cache-line-blocked bloom filter.
+///
+/// Each key is mapped to a single 64 bytes block and all of its probes
+/// land in that block, so a lookup touches only one cache line.
+/// The filter is sized from the real number of keys it will hold.
+#[derive(Debug, Clone, Encode, Decode)]
+pub(crate) struct BloomFilter {
     bytes: Vec<u8>,
-    bitmap_bits: u64,
-    k_num: u32,
-    sip_keys: [(u64, u64); 2],
+    num_probes: u32,
+}
+
+impl BloomFilter {
+    /// Creates a filter for `num_keys` keys using `bits_per_key` bits each.
+    pub fn new(num_keys: usize, bits_per_key: f64) -> Self {
+        // ln(2) * bits_per_key minimizes the false positive rate
+        let num_probes = (bits_per_key * LN_2).round() as u32;
+        Self {
+            bytes: vec![0; Self::num_blocks(num_keys, bits_per_key) * BLOCK_SIZE_BYTES],
+            num_probes: num_probes.clamp(1, 30),
+        }
+    }
+
+    /// Returns the number of bits per key needed to reach a false positive rate.
+    pub fn bits_per_key_for_fp_rate(fp_rate: f64) -> usize {
+        let bits = -fp_rate.ln() / (LN_2 * LN_2);
+        bits.ceil().max(1.0) as usize
+    }
+
+    fn num_blocks(num_keys: usize, bits_per_key: f64) -> usize {
+        let num_bits = ((num_keys as f64) * bits_per_key.max(0.0)).ceil() as usize;
+        num_bits.saturating_sub(1) / BLOCK_SIZE_BITS as usize + 1
+    }
+
+    pub fn set(&mut self, key: RefKey) {
+        let (block_offset, mut h, delta) = self.locate(key);
+        for _ in 0..self.num_probes {
+            let bit = h % BLOCK_SIZE_BITS;
+            self.bytes[block_offset + (bit / 8) as usize] |= 1 << (bit % 8);
+            h = h.wrapping_add(delta);
+        }
+    }
+
+    pub fn check(&self, key: RefKey) -> bool {
+        let (block_offset, mut h, delta) = self.locate(key);
+        for _ in 0..self.num_probes {
+            let bit = h % BLOCK_SIZE_BITS;
+            if self.bytes[block_offset + (bit / 8) as usize] & (1 << (bit % 8)) == 0 {
+                return false;
+            }
+            h = h.wrapping_add(delta);
+        }
+        true
+    }
+
+    /// Returns the block offset, the first probe and the probe delta of a key.
+    fn locate(&self, key: RefKey) -> (usize, u32, u32) {
+        let hash = hash64(key);
+        let num_blocks = (self.bytes.len() / BLOCK_SIZE_BYTES) as u64;
+        // map the upper half of the hash onto [0, num_blocks) without a division
+        let block = (((hash >> 32) * num_blocks) >> 32) as usize;
+        let h = hash as u32;
+        let delta = h.rotate_right(17);
+        (block * BLOCK_SIZE_BYTES, h, delta)
+    }
+}
+
+/// The number of entries and ss_tables (sorted runs) of a level.
+#[derive(Debug, Default, Clone, Copy)]
+pub(crate) struct LevelStats {
+    pub num_entries: usize,
+    pub num_tables: usize,
+}
+
+impl LevelStats {
+    /// Aggregates `(level, num_entries)` pairs of ss_tables by level.
+    pub fn collect<I: IntoIterator<Item = (usize, usize)>>(tables: I) -> Vec<LevelStats> {
+        let mut levels: Vec<LevelStats> = Vec::new();
+        for (level, num_entries) in tables {
+            if levels.len() <= level {
+                levels.resize(level + 1, LevelStats::default());
+            }
+            levels[level].num_entries += num_entries;
+            levels[level].num_tables += 1;
+        }
+        levels
+    }
+
+    /// The average number of entries of a sorted run in the level.
+    fn run_size(&self) -> f64 {
+        self.num_entries as f64 / self.num_tables.max(1) as f64
+    }
 }
 
-impl From<&Bloom<Key>> for BloomFilterState {
-    fn from(bloom: &Bloom<Key>) -> Self {
+/// Decides how many bloom filter bits per key each level gets.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct BloomFilterPolicy {
+    bits_per_key: usize,
+    memory_budget_bytes: Option<usize>,
+}
+
+impl BloomFilterPolicy {
+    pub fn new(bits_per_key: usize, memory_budget_bytes: Option<usize>) -> Self {
         Self {
-            bytes: bloom.bitmap(),
-            bitmap_bits: bloom.number_of_bits(),
-            k_num: bloom.number_of_hash_functions(),
-            sip_keys: bloom.sip_keys(),
+            bits_per_key,
+            memory_budget_bytes,
+        }
+    }
+
+    /// Returns the bits per key of every level.
+    ///
+    /// Without a memory budget, all levels get the same `bits_per_key`.
+    /// With a budget, bits are assigned as in the Monkey paper: the sum of
+    /// false positive rates over all sorted runs is minimized, which gives
+    /// each run a false positive rate proportional to its size.
+    /// Small upper levels get more bits, large lower levels get fewer.
+    pub fn bits_per_key_per_level(&self, levels: &[LevelStats]) -> Vec<f64> {
+        let memory_budget_bytes = match self.memory_budget_bytes {
+            Some(memory_budget_bytes) => memory_budget_bytes,
+            None => return vec![self.bits_per_key as f64; levels.len()],
+        };
+
+        let budget_bits = (memory_budget_bytes * 8) as f64;
+        let mut bits_per_level = vec![0.0; levels.len()];
+        let mut active: Vec<bool> = levels.iter().map(|l| l.num_entries > 0).collect();
+        loop {
+            // fp_i = lambda * run_size_i, find lambda so that the budget is spent
+            let mut total_entries = 0.0;
+            let mut weighted_ln_run_size = 0.0;
+            for (level, stats) in levels.iter().enumerate() {
+                if active[level] {
+                    total_entries += stats.num_entries as f64;
+                    weighted_ln_run_size += stats.num_entries as f64 * stats.run_size().ln();
+                }
+            }
+            if total_entries == 0.0 {
+                return bits_per_level;
+            }
+            let neg_ln_lambda = (budget_bits * LN_2 * LN_2 + weighted_ln_run_size) / total_entries;
+
+            // levels with a false positive rate of 1 or more get no filter at all
+            let mut saturated = false;
+            for (level, stats) in levels.iter().enumerate() {
+                bits_per_level[level] = 0.0;
+                if !active[level] {
+                    continue;
+                }
+                let bits = (neg_ln_lambda - stats.run_size().ln()) / (LN_2 * LN_2);
+                if bits <= 0.0 {
+                    active[level] = false;
+                    saturated = true;
+                } else {
+                    bits_per_level[level] = bits;
+                }
+            }
+            if !saturated {
+                return bits_per_level;
+            }
         }
     }
+
+    /// Returns the bits per key for a new table of `num_entries` entries at `level`,
+    /// given the `(level, num_entries)` of the other tables.
+    pub fn bits_per_key_for_new_table<I: IntoIterator<Item = (usize, usize)>>(
+        &self,
+        tables: I,
+        level: usize,
+        num_entries: usize,
+    ) -> f64 {
+        let tables = tables
+            .into_iter()
+            .chain(std::iter::once((level, num_entries)));
+        let levels = LevelStats::collect(tables);
+        self.bits_per_key_per_level(&levels)[level]
+    }
 }
 
-impl From<BloomFilterState> for Bloom<Key> {
-    fn from(state: BloomFilterState) -> Self {
-        Bloom::from_existing(&state.bytes, state.bitmap_bits, state.k_num, state.sip_keys)
+/// MurmurHash64A, stable across platforms and releases since it gets persisted.
+fn hash64(key: &[u8]) -> u64 {
+    const SEED: u64 = 0xbc9f_1d34_c2b5_42a1;
+    const M: u64 = 0xc6a4_a793_5bd1_e995;
+    const R: u32 = 47;
+
+    let mut h = SEED ^ (key.len() as u64).wrapping_mul(M);
+    let mut chunks = key.chunks_exact(8);
+    for chunk in &mut chunks {
+        let mut k = u64::from_le_bytes(chunk.try_into().unwrap());
+        k = k.wrapping_mul(M);
+        k ^= k >> R;
+        k = k.wrapping_mul(M);
+        h ^= k;
+        h = h.wrapping_mul(M);
+    }
+
+    let remainder = chunks.remainder();
+    if !remainder.is_empty() {
+        for (i, byte) in remainder.iter().enumerate() {
+            h ^= (*byte as u64) << (8 * i);
+        }
+        h = h.wrapping_mul(M);
+    }
+
+    h ^= h >> R;
+    h = h.wrapping_mul(M);
+    h ^= h >> R;
+    h
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BloomFilter, BloomFilterPolicy, LevelStats};
+
+    #[test]
+    fn test_bloom_filter() {
+        let mut bloom_filter = BloomFilter::new(10_000, 10.0);
+        assert_eq!(bloom_filter.bytes.len(), 12_544);
+        for i in 0..10_000 {
+            bloom_filter.set(format!("k_{}", i).as_bytes());
+        }
+        for i in 0..10_000 {
+            assert!(bloom_filter.check(format!("k_{}", i).as_bytes()));
+        }
+
+        let num_false_positives = (0..10_000)
+            .filter(|i| bloom_filter.check(format!("unknown_{}", i).as_bytes()))
+            .count();
+        assert!(num_false_positives < 300);
+
+        assert_eq!(BloomFilter::bits_per_key_for_fp_rate(0.01), 10);
+    }
+
+    #[test]
+    fn test_monkey_allocation() {
+        let levels = LevelStats::collect(vec![(0, 1_000), (0, 1_000), (1, 10_000), (2, 100_000)]);
+        let total_entries = 112_000;
+
+        let uniform = BloomFilterPolicy::new(10, None);
+        assert_eq!(uniform.bits_per_key_per_level(&levels), vec![10.0; 3]);
+
+        // same memory as 10 bits per key everywhere
+        let monkey = BloomFilterPolicy::new(10, Some(total_entries * 10 / 8));
+        let bits = monkey.bits_per_key_per_level(&levels);
+        assert!(bits[0] > bits[1]);
+        assert!(bits[1] > bits[2]);
+        let spent_bits: f64 = levels
+            .iter()
+            .zip(bits.iter())
+            .map(|(level, bits)| level.num_entries as f64 * bits)
+            .sum();
+        assert!((spent_bits - (total_entries * 10) as f64).abs() < 1.0);
+
+        // a tiny budget only goes to the small levels
+        let monkey = BloomFilterPolicy::new(10, Some(1_000));
+        let bits = monkey.bits_per_key_per_level(&levels);
+        assert!(bits[0] > 0.0);
+        assert_eq!(bits[2], 0.0);
     }
 }
@@ -1,5 +1,6 @@
 use std::{
-    collections::BTreeSet,
+    collections::{BTreeMap, BTreeSet},
+    fs,
     sync::Arc,
     thread::{self, JoinHandle},
     time::Duration,
@@ -11,7 +12,21 @@ use crossbeam::{
 };
 use crossbeam_skiplist::SkipSet;
 
-use crate::{error::LiteDbResult, ss_table::SSTable, utils::AtomicOperationExecutor};
+use crate::{
+    background_error::BackgroundError,
+    blob::{
+        blob_file_path, blob_index, referenced_blob_files, remove_blob_file, BlobFileId,
+        BLOB_GC_GARBAGE_RATIO,
+    },
+    bloom_filter::LevelStats,
+    error::LiteDbResult,
+    iterator::CombineIterator,
+    manifest::Manifest,
+    ss_table::{ss_table_file_path, SSTable, SSTableBuilder, SSTableOptions},
+    utils::AtomicOperationExecutor,
+};
+
+type SSTableSet = BTreeSet<Arc<SSTable>>;
 
 pub(crate) trait CompactionPolicy: Sync + Send {
     /// Evaluate a set of ss_tables and returns merge-able groups of ss_tables.
@@ -26,9 +41,19 @@ pub enum CompactorPolicyConfig {
     SizeTiered,
 }
 
+impl CompactorPolicyConfig {
+    /// Number of ss_tables a level holds before they get merged.
+    pub(crate) fn min_merge_width(&self) -> usize {
+        match self {
+            CompactorPolicyConfig::SizeTiered => 4,
+        }
+    }
+}
+
 // #[derive(Default)]
 pub(crate) struct Compactor {
     kill_signal_sender: Sender<()>,
+    trigger_sender: Sender<()>,
     task_handle: Option<JoinHandle<()>>,
 }
 
@@ -36,45 +61,48 @@ impl Compactor {
     pub fn start(
         ss_tables: Arc<SkipSet<Arc<SSTable>>>,
         atomic_operation_executor: Arc<AtomicOperationExecutor>,
+        ss_table_options: SSTableOptions,
         compactor_policy: &CompactorPolicyConfig,
+        manifest: Arc<Manifest>,
+        background_error: BackgroundError,
     ) -> LiteDbResult<Self> {
         let policy = Compactor::create_policy(compactor_policy)?;
         let (kill_signal_sender, kill_signal_receiver) = bounded(1);
+        let (trigger_sender, trigger_receiver) = bounded(1);
         let ticker = tick(policy.next_schedule());
-        let task_handle = thread::spawn(move || {
-            loop {
-                select! {
-                    recv(ticker) -> _ => (),
-                    recv(kill_signal_receiver) -> _ => break,
-                };
-                let candidate_ss_tables = ss_tables
-                    .iter()
-                    .map(|entry| entry.value().clone())
-                    .collect::<Vec<_>>();
-
-                let compaction_groups = policy.evaluate(candidate_ss_tables);
-                if compaction_groups.is_empty() {
-                    continue;
-                }
-
-                //TODO: perform merge operations and publish
-                let (new_tables, old_tables) = do_compaction(compaction_groups);
-                atomic_operation_executor.perform(|| {
-                    for table in &old_tables {
-                        ss_tables.remove(table);
-                    }
-                    for table in &new_tables {
-                        ss_tables.insert(table.clone());
-                    }
-                });
+        let task_handle = thread::spawn(move || loop {
+            select! {
+                recv(ticker) -> _ => (),
+                recv(trigger_receiver) -> _ => (),
+                recv(kill_signal_receiver) -> _ => break,
+            };
+            // a failed compaction may have left files behind, none is attempted anymore
+            if background_error.check().is_err() {
+                continue;
+            }
+            if let Err(err) = compact(
+                &ss_tables,
+                policy.as_ref(),
+                &atomic_operation_executor,
+                &ss_table_options,
+                &manifest,
+            ) {
+                background_error.record(err);
             }
         });
         Ok(Self {
             kill_signal_sender,
+            trigger_sender,
             task_handle: Some(task_handle),
         })
     }
 
+    /// Requests a compaction round without waiting for the next schedule.
+    pub fn trigger(&self) {
+        // a pending request already covers this one
+        let _ = self.trigger_sender.try_send(());
+    }
+
     pub fn stop(&mut self) {
         self.kill_signal_sender.send(()).unwrap();
         let task_handle = self.task_handle.take().unwrap();
@@ -86,28 +114,245 @@ impl Compactor {
         policy_config: &CompactorPolicyConfig,
     ) -> LiteDbResult<Arc<dyn CompactionPolicy>> {
         let policy = match policy_config {
-            CompactorPolicyConfig::SizeTiered => SizeTieredCompactor,
+            CompactorPolicyConfig::SizeTiered => {
+                SizeTieredCompactor::new(policy_config.min_merge_width())
+            }
         };
         Ok(Arc::new(policy))
     }
 }
 
+/// Runs one round of compaction and publishes the result.
+///
+/// The merged tables are written under new names and the inputs are only
+/// deleted once the manifest lists the merged tables in their place.
+///
+/// The merged tables get the bloom filter bits of their level in the new
+/// shape of the tree. The other tables keep their filters until they get
+/// merged in turn, resizing them would copy their data.
+pub(crate) fn compact(
+    ss_tables: &SkipSet<Arc<SSTable>>,
+    policy: &dyn CompactionPolicy,
+    atomic_operation_executor: &AtomicOperationExecutor,
+    ss_table_options: &SSTableOptions,
+    manifest: &Manifest,
+) -> LiteDbResult<()> {
+    let candidate_ss_tables = ss_tables
+        .iter()
+        .map(|entry| entry.value().clone())
+        .collect::<Vec<_>>();
+
+    let compaction_groups = policy.evaluate(candidate_ss_tables.clone());
+    if compaction_groups.is_empty() {
+        return Ok(());
+    }
+
+    // shape of the tree once the groups are merged
+    let compacted_ids = compaction_groups
+        .iter()
+        .flatten()
+        .map(|table| table.id())
+        .collect::<BTreeSet<_>>();
+    let remaining_tables = candidate_ss_tables
+        .into_iter()
+        .filter(|table| !compacted_ids.contains(&table.id()))
+        .collect::<Vec<_>>();
+    let levels = LevelStats::collect(
+        remaining_tables
+            .iter()
+            .map(|table| (table.level(), table.num_entries()))
+            .chain(compaction_groups.iter().map(|group| {
+                let num_entries = group.iter().map(|table| table.num_entries()).sum();
+                (group[0].level() + 1, num_entries)
+            })),
+    );
+    let bits_per_level = ss_table_options
+        .bloom_filter_policy
+        .bits_per_key_per_level(&levels);
+
+    // merged tables delete files readers may still have to open
+    for table in compaction_groups.iter().flatten() {
+        table.pin()?;
+    }
+    let (new_tables, old_tables) = do_compaction(
+        compaction_groups,
+        &remaining_tables,
+        &bits_per_level,
+        ss_table_options,
+    )?;
+    let live_blob_files = atomic_operation_executor.perform(|| {
+        for table in &old_tables {
+            ss_tables.remove(table);
+        }
+        for table in &new_tables {
+            ss_tables.insert(table.clone());
+        }
+        referenced_blob_files(ss_tables)
+    });
+    manifest.persist(ss_tables, atomic_operation_executor)?;
+
+    // checkpoints link the files of the tables they saw published
+    let deletion_guard = manifest.lock_deletions();
+    for table in old_tables.iter() {
+        fs::remove_file(table.path())?;
+    }
+    // readers still holding the old tables keep their blob files mapped
+    for table in old_tables.iter() {
+        let dir = table
+            .path()
+            .parent()
+            .expect("Expected a database directory");
+        for file_id in table.blob_references().keys() {
+            if !live_blob_files.contains(file_id) {
+                remove_blob_file(&blob_file_path(dir, *file_id))?;
+            }
+        }
+    }
+    drop(deletion_guard);
+    Ok(())
+}
+
+/// Merges every group into a single ss_table on the next level.
+///
+/// The merged table takes the id of the newest table of its group, this
+/// keeps ids ordered by recency since a level only holds data older than
+/// the level above it.
+///
+/// Values of the blob files that get mostly dead are relocated to the blob
+/// file of the merged table, other pointers into blob files are copied as is.
+// tables are ordered by id, the block cache they hold does not change it
+#[allow(clippy::mutable_key_type)]
 fn do_compaction(
-    _compaction_groups: Vec<Vec<Arc<SSTable>>>,
-) -> (BTreeSet<Arc<SSTable>>, BTreeSet<Arc<SSTable>>) {
-    let new_tables: BTreeSet<Arc<SSTable>> = BTreeSet::new();
-    let old_tables: BTreeSet<Arc<SSTable>> = BTreeSet::new();
+    compaction_groups: Vec<Vec<Arc<SSTable>>>,
+    remaining_tables: &[Arc<SSTable>],
+    bits_per_level: &[f64],
+    ss_table_options: &SSTableOptions,
+) -> LiteDbResult<(SSTableSet, SSTableSet)> {
+    let mut new_tables = SSTableSet::new();
+    let mut old_tables = SSTableSet::new();
+
+    let all_tables = remaining_tables
+        .iter()
+        .chain(compaction_groups.iter().flatten())
+        .cloned()
+        .collect::<Vec<_>>();
+    for mut group in compaction_groups {
+        // from oldest to newest
+        group.sort();
+        let newest_table = group.last().expect("Expected a non empty group.").clone();
+        let level = newest_table.level() + 1;
+        let expected_num_entries = group.iter().map(|table| table.num_entries()).sum();
+        let dir = newest_table
+            .path()
+            .parent()
+            .expect("Expected a database directory");
+        let mut builder = SSTableBuilder::new(
+            ss_table_file_path(dir, newest_table.id(), level),
+            newest_table.id(),
+            level,
+            expected_num_entries,
+            bits_per_level[level],
+            ss_table_options,
+        )?;
+
+        let other_tables = all_tables
+            .iter()
+            .filter(|table| !group.contains(table))
+            .cloned()
+            .collect::<Vec<_>>();
+        let collected_blob_files = blob_files_to_collect(&group, &other_tables)?;
+        let iterators = group
+            .iter()
+            .map(|table| SSTable::scan_stored(table.clone()))
+            .collect();
+        for result in CombineIterator::try_new(iterators)? {
+            let (key, value) = result?;
+            match blob_index(&value)? {
+                Some(index) if collected_blob_files.contains(&index.file_id) => {
+                    let table = group
+                        .iter()
+                        .find(|table| table.blob_references().contains_key(&index.file_id))
+                        .expect("Expected a table pointing into the blob file");
+                    builder.add(&key, &table.read_blob(&index, true)?)?;
+                }
+                _ => builder.add_stored(&key, &value)?,
+            }
+        }
+
+        new_tables.insert(Arc::new(builder.finish()?));
+        old_tables.extend(group);
+    }
 
-    // TODO:
+    Ok((new_tables, old_tables))
+}
+
+/// Returns the blob files the group points into that are mostly dead once
+/// the group is merged, counting what the other tables point at as live.
+fn blob_files_to_collect(
+    group: &[Arc<SSTable>],
+    other_tables: &[Arc<SSTable>],
+) -> LiteDbResult<BTreeSet<BlobFileId>> {
+    let mut blob_file_sizes = BTreeMap::new();
+    for table in group {
+        for file_id in table.blob_references().keys() {
+            if let Some(size) = table.blob_file_size(file_id)? {
+                blob_file_sizes.insert(*file_id, size);
+            }
+        }
+    }
+    if blob_file_sizes.is_empty() {
+        return Ok(BTreeSet::new());
+    }
+
+    let mut live_bytes: BTreeMap<BlobFileId, u64> = BTreeMap::new();
+    for table in other_tables {
+        for (file_id, num_bytes) in table.blob_references() {
+            *live_bytes.entry(*file_id).or_default() += num_bytes;
+        }
+    }
+    // only the newest value of a key survives the merge
+    let iterators = group
+        .iter()
+        .map(|table| SSTable::scan_stored(table.clone()))
+        .collect();
+    for result in CombineIterator::try_new(iterators)? {
+        let (_, value) = result?;
+        if let Some(index) = blob_index(&value)? {
+            *live_bytes.entry(index.file_id).or_default() += index.len;
+        }
+    }
 
-    (new_tables, old_tables)
+    Ok(blob_file_sizes
+        .into_iter()
+        .filter(|(file_id, size)| {
+            let live_bytes = live_bytes.get(file_id).copied().unwrap_or(0);
+            1.0 - live_bytes as f64 / *size as f64 >= BLOB_GC_GARBAGE_RATIO
+        })
+        .map(|(file_id, _)| file_id)
+        .collect())
 }
 
-struct SizeTieredCompactor;
+/// Merges all the ss_tables of a level once it holds enough of them.
+pub(crate) struct SizeTieredCompactor {
+    min_merge_width: usize,
+}
+
+impl SizeTieredCompactor {
+    pub(crate) fn new(min_merge_width: usize) -> Self {
+        Self { min_merge_width }
+    }
+}
 
 impl CompactionPolicy for SizeTieredCompactor {
-    fn evaluate(&self, _ss_tables: Vec<Arc<SSTable>>) -> Vec<Vec<Arc<SSTable>>> {
-        vec![] // TODO:
+    fn evaluate(&self, ss_tables: Vec<Arc<SSTable>>) -> Vec<Vec<Arc<SSTable>>> {
+        let mut levels: BTreeMap<usize, Vec<Arc<SSTable>>> = BTreeMap::new();
+        for table in ss_tables {
+            levels.entry(table.level()).or_default().push(table);
+        }
+        levels
+            .into_values()
+            .filter(|tables| tables.len() >= self.min_merge_width)
+            .collect()
     }
 
     fn next_schedule(&self) -> Duration {
@@ -115,3 +360,92 @@ impl CompactionPolicy for SizeTieredCompactor {
         Duration::from_secs(60 * 10)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{path::Path, sync::Arc};
+
+    use crossbeam_skiplist::SkipSet;
+
+    use crate::{
+        bloom_filter::BloomFilterPolicy,
+        error::LiteDbResult,
+        manifest::{read_manifest, ss_table_file_name, Manifest},
+        ss_table::{SSTable, SSTableOptions},
+        utils::AtomicOperationExecutor,
+        Scannable,
+    };
+
+    use super::{compact, SizeTieredCompactor};
+
+    fn ss_table_options() -> SSTableOptions {
+        SSTableOptions {
+            bloom_filter_policy: BloomFilterPolicy::new(10, Some(64)),
+            range_filter: true,
+            ..SSTableOptions::for_test()
+        }
+    }
+
+    fn create_ss_table(path: &Path, id: u64, data: Vec<(&str, &str)>) -> Arc<SSTable> {
+        SSTable::create_for_test(path, id, data, &ss_table_options()).unwrap()
+    }
+
+    #[test]
+    fn test_size_tiered_compaction() -> LiteDbResult<()> {
+        let temp_dir = tempfile::tempdir()?;
+        let ss_tables = SkipSet::new();
+        ss_tables.insert(create_ss_table(
+            temp_dir.path(),
+            1,
+            vec![("a", "a"), ("b", "b")],
+        ));
+        ss_tables.insert(create_ss_table(temp_dir.path(), 2, vec![("b", "b1")]));
+        ss_tables.insert(create_ss_table(
+            temp_dir.path(),
+            3,
+            vec![("a", "a1"), ("c", "c")],
+        ));
+
+        let policy = SizeTieredCompactor::new(3);
+        let executor = AtomicOperationExecutor::new();
+        let manifest = Manifest::new(temp_dir.path().to_path_buf());
+        compact(
+            &ss_tables,
+            &policy,
+            &executor,
+            &ss_table_options(),
+            &manifest,
+        )?;
+
+        assert_eq!(ss_tables.len(), 1);
+        let ss_table = ss_tables.front().unwrap().value().clone();
+        assert_eq!(ss_table.id(), 3);
+        assert_eq!(ss_table.level(), 1);
+        // the inputs are deleted, the manifest lists the merged table in their place
+        assert_eq!(std::fs::read_dir(temp_dir.path())?.count(), 2);
+        assert_eq!(
+            read_manifest(temp_dir.path())?,
+            Some([ss_table_file_name(&ss_table)].into_iter().collect())
+        );
+
+        let actual = ss_table
+            .scan(&None, &None)
+            .map(|result| {
+                let (k, v) = result.unwrap();
+                (String::from_utf8(k).unwrap(), String::from_utf8(v).unwrap())
+            })
+            .collect::<Vec<_>>();
+        let expected = vec![
+            ("a".to_string(), "a1".to_string()),
+            ("b".to_string(), "b1".to_string()),
+            ("c".to_string(), "c".to_string()),
+        ];
+        assert_eq!(expected, actual);
+
+        // the merged table survives a reopen
+        let reopened = SSTable::open(ss_table.path().to_path_buf())?;
+        assert_eq!(reopened.level(), 1);
+        assert_eq!(reopened.num_entries(), 3);
+        Ok(())
+    }
+}
@@ -1,24 +1,35 @@
 use std::{
-    sync::Arc,
+    sync::{
+        atomic::{AtomicBool, Ordering as AtomicOrdering},
+        Arc,
+    },
     thread::{self, JoinHandle},
     time::Duration,
 };
 
 use crossbeam::{
-    channel::{bounded, tick, Sender},
+    channel::{bounded, never, tick, Receiver, Sender},
     select,
 };
 use crossbeam_skiplist::SkipSet;
+use parking_lot::{RwLock, RwLockReadGuard, RwLockWriteGuard};
 
 use crate::{
-    error::LiteDbResult, mem_table::MemTable, ss_table::SSTable, utils::AtomicOperationExecutor,
+    background_error::BackgroundError,
+    error::LiteDbResult,
+    manifest::Manifest,
+    mem_table::{MemTable, MemTableOptions},
+    ss_table::{SSTable, SSTableOptions},
+    utils::AtomicOperationExecutor,
 };
 
 pub(crate) trait MemTableControllerPolicy: Sync + Send {
     /// Evaluate a set of mem_table for its maturity
     fn is_mature(&self, mem_table: &MemTable) -> bool;
-    /// Returns the duration left till next evaluation
-    fn next_schedule(&self) -> Duration;
+    /// Returns the duration left till next evaluation, if any.
+    // mem_tables signal the controller as soon as they are full,
+    // the schedule only matters for the maturity that comes with time.
+    fn next_schedule(&self) -> Option<Duration>;
 }
 
 #[non_exhaustive]
@@ -28,79 +39,292 @@ pub enum MemTableControllerPolicyConfig {
         max_entries: usize,
         max_size_bytes: usize,
     },
+    /// Like `SizeTiered`, also flushes a non-empty mem_table once it gets `max_age` old.
+    SizeTieredWithMaxAge {
+        max_entries: usize,
+        max_size_bytes: usize,
+        max_age: Duration,
+    },
+}
+
+/// Lets a mem_table wake the controller up as soon as it is full.
+#[derive(Clone, Debug)]
+pub(crate) struct RotationTrigger {
+    max_entries: usize,
+    max_size_bytes: usize,
+    sender: Sender<()>,
+    flush_requested: Arc<AtomicBool>,
+}
+
+/// The controller side of a RotationTrigger.
+pub(crate) struct RotationListener {
+    receiver: Receiver<()>,
+    flush_requested: Arc<AtomicBool>,
+}
+
+impl RotationListener {
+    /// Returns whether a flush was requested since the last call.
+    fn take_flush_request(&self) -> bool {
+        self.flush_requested.swap(false, AtomicOrdering::SeqCst)
+    }
+}
+
+impl RotationTrigger {
+    pub fn new(
+        mem_table_controller_policy: &MemTableControllerPolicyConfig,
+    ) -> (Self, RotationListener) {
+        let (max_entries, max_size_bytes) = match mem_table_controller_policy {
+            MemTableControllerPolicyConfig::SizeTiered {
+                max_entries,
+                max_size_bytes,
+            }
+            | MemTableControllerPolicyConfig::SizeTieredWithMaxAge {
+                max_entries,
+                max_size_bytes,
+                ..
+            } => (*max_entries, *max_size_bytes),
+        };
+        // a pending signal already covers the following ones
+        let (sender, receiver) = bounded(1);
+        let flush_requested = Arc::new(AtomicBool::new(false));
+        let trigger = Self {
+            max_entries,
+            max_size_bytes,
+            sender,
+            flush_requested: flush_requested.clone(),
+        };
+        let listener = RotationListener {
+            receiver,
+            flush_requested,
+        };
+        (trigger, listener)
+    }
+
+    pub fn notify_if_full(&self, mem_table: &MemTable) {
+        if mem_table.is_full(self.max_entries, self.max_size_bytes) {
+            let _ = self.sender.try_send(());
+        }
+    }
+
+    /// Rotates & flushes the current mem_table even though it is not mature.
+    pub fn request_flush(&self) {
+        self.flush_requested.store(true, AtomicOrdering::SeqCst);
+        let _ = self.sender.try_send(());
+    }
 }
 
+/// Rotates the current mem_table once mature and flushes the previous ones.
+///
+/// Rotation and flushes run on their own threads, so that a slow flush
+/// does not keep a full mem_table from being rotated while a write buffer
+/// is free. Once `max_write_buffer_number` mem_tables exist, the current one
+/// is only rotated after a flush.
 pub(crate) struct MemTableController {
     kill_signal_sender: Sender<()>,
-    task_handle: Option<JoinHandle<()>>,
+    task_handles: Vec<JoinHandle<()>>,
+    rotation_lock: Arc<RwLock<()>>,
+    mem_tables: Arc<SkipSet<Arc<MemTable>>>,
+    mem_table_options: MemTableOptions,
 }
 
 impl MemTableController {
+    #[allow(clippy::too_many_arguments)]
     pub fn start(
         mem_tables: Arc<SkipSet<Arc<MemTable>>>,
         ss_tables: Arc<SkipSet<Arc<SSTable>>>,
         atomic_operation_executor: Arc<AtomicOperationExecutor>,
-        bloom_filter_size_bytes: usize,
-        bloom_filter_item_count: usize,
-        sparse_index_range_size: usize,
+        mem_table_options: MemTableOptions,
+        ss_table_options: SSTableOptions,
         mem_table_controller_policy: &MemTableControllerPolicyConfig,
+        max_write_buffer_number: usize,
+        rotation_listener: RotationListener,
+        manifest: Arc<Manifest>,
+        background_error: BackgroundError,
     ) -> LiteDbResult<Self> {
         let policy = MemTableController::create_policy(mem_table_controller_policy)?;
-        let (kill_signal_sender, kill_signal_receiver) = bounded(1);
-        let ticker = tick(policy.next_schedule());
-        let task_handle = thread::spawn(move || {
-            loop {
+        let (kill_signal_sender, kill_signal_receiver) = bounded(2);
+        // a pending signal already covers the following ones
+        let (flush_sender, flush_receiver) = bounded(1);
+        let (flushed_sender, flushed_receiver) = bounded(1);
+        let ticker = match policy.next_schedule() {
+            Some(duration) => tick(duration),
+            None => never(),
+        };
+        let rotation_lock = Arc::new(RwLock::new(()));
+
+        let rotation_task_handle = {
+            let mem_tables = mem_tables.clone();
+            let mem_table_options = mem_table_options.clone();
+            let rotation_lock = rotation_lock.clone();
+            let kill_signal_receiver = kill_signal_receiver.clone();
+            let background_error = background_error.clone();
+            let receiver = rotation_listener.receiver.clone();
+            // Swaps the current_mem_table with a new_mem_table when mature.
+            let rotate = move || -> LiteDbResult<()> {
+                // every write buffer is in use, writes wait until a flush frees one
+                if mem_tables.len() >= max_write_buffer_number {
+                    return Ok(());
+                }
+                let current_mem_table = mem_tables.back().unwrap().value().clone();
+                let flush_requested =
+                    rotation_listener.take_flush_request() && current_mem_table.num_entries() > 0;
+                if flush_requested || policy.is_mature(&current_mem_table) {
+                    let _rotation_guard = rotation_lock.write();
+                    // an ingestion may have rotated it meanwhile, the next id is then taken
+                    if mem_tables.back().unwrap().value().id() == current_mem_table.id() {
+                        let dir = current_mem_table.dir();
+                        let id = current_mem_table.id();
+                        let new_mem_table = MemTable::open(dir, id + 1, &mem_table_options)?;
+                        mem_tables.insert(Arc::new(new_mem_table));
+                    }
+                }
+                Ok(())
+            };
+            thread::spawn(move || loop {
+                // no mem_table is rotated once a flush failed, writes fail anyway
+                if background_error.check().is_ok() {
+                    if let Err(err) = rotate() {
+                        background_error.record(err);
+                    }
+                }
+                // ingestions rotate mem_tables from outside the task, they get flushed too
+                let _ = flush_sender.try_send(());
+
                 select! {
                     recv(ticker) -> _ => (),
+                    recv(receiver) -> _ => (),
+                    recv(flushed_receiver) -> _ => (),
+                    recv(kill_signal_receiver) -> _ => break,
+                };
+            })
+        };
+
+        let flush_task_handle = {
+            let mem_tables = mem_tables.clone();
+            // callers waiting for a flush are woken up once it is published
+            let flush_waiters = background_error.clone();
+            // Persists the mem_tables no longer written to, oldest first & publishes them.
+            let flush = move || -> LiteDbResult<()> {
+                while mem_tables.len() > 1 {
+                    let mem_table = mem_tables.front().unwrap().value().clone();
+                    // writes skipping the wal leave nothing to replay
+                    if mem_table.num_entries() == 0 {
+                        let _deletion_guard = manifest.lock_deletions();
+                        mem_table.close()?;
+                        mem_tables.remove(&mem_table);
+                        let _ = flushed_sender.try_send(());
+                        flush_waiters.notify();
+                        continue;
+                    }
+                    let bloom_bits_per_key = ss_table_options
+                        .bloom_filter_policy
+                        .bits_per_key_for_new_table(
+                            ss_tables
+                                .iter()
+                                .map(|entry| (entry.value().level(), entry.value().num_entries())),
+                            0,
+                            mem_table.num_entries(),
+                        );
+                    let ss_table =
+                        mem_table.write_ss_table(bloom_bits_per_key, &ss_table_options)?;
+                    atomic_operation_executor.perform(|| {
+                        mem_tables.remove(&mem_table);
+                        ss_tables.insert(ss_table.clone());
+                    });
+                    // a write buffer is free, the current mem_table may be rotated
+                    let _ = flushed_sender.try_send(());
+                    // the wal is only dropped once a reopen would find the ss_table
+                    manifest.persist(&ss_tables, &atomic_operation_executor)?;
+                    let deletion_guard = manifest.lock_deletions();
+                    mem_table.close()?;
+                    drop(deletion_guard);
+                    flush_waiters.notify();
+                }
+                Ok(())
+            };
+            thread::spawn(move || loop {
+                select! {
+                    recv(flush_receiver) -> _ => (),
                     recv(kill_signal_receiver) -> _ => break,
                 };
-                //let mut mem_table_lock = current_mem_table.write();
-                let current_mem_table = mem_tables.front().unwrap();
-                if !policy.is_mature(&current_mem_table) {
-                    continue;
+                // the mem_table that failed to flush is kept, nothing newer gets flushed
+                if background_error.check().is_ok() {
+                    if let Err(err) = flush() {
+                        background_error.record(err);
+                    }
                 }
+            })
+        };
 
-                // Swap to current_mem_table with new_mem_table
-                let dir = current_mem_table.dir();
-                let id = current_mem_table.id();
-                let new_mem_table = MemTable::open(dir, id + 1).unwrap();
-                mem_tables.insert(Arc::new(new_mem_table));
-
-                // Persist current_mem_table & publish it.
-                let ss_table = current_mem_table
-                    .save(
-                        bloom_filter_size_bytes,
-                        bloom_filter_item_count,
-                        sparse_index_range_size,
-                    )
-                    .unwrap();
-                atomic_operation_executor.perform(|| {
-                    mem_tables.clone().remove(current_mem_table.as_ref());
-                    ss_tables.clone().insert(ss_table.clone());
-                });
-            }
-        });
         Ok(Self {
             kill_signal_sender,
-            task_handle: Some(task_handle),
+            task_handles: vec![rotation_task_handle, flush_task_handle],
+            rotation_lock,
+            mem_tables,
+            mem_table_options,
         })
     }
 
+    /// Keeps the current mem_table from being rotated while the guard is held,
+    /// so that a write never lands in a mem_table that is being flushed.
+    pub fn pin_current_mem_table(&self) -> RwLockReadGuard<'_, ()> {
+        self.rotation_lock.read()
+    }
+
+    /// Holds every write back while the guard is held, the writes in flight complete first.
+    pub fn hold_writes(&self) -> RwLockWriteGuard<'_, ()> {
+        self.rotation_lock.write()
+    }
+
+    /// Rotates the current mem_table right away, leaving `num_ids` unused ids
+    /// before the new one. Returns the first of them.
+    ///
+    /// `check` runs first with the writes held back, the rotation only happens
+    /// when it succeeds. The previous mem_table is flushed as usual.
+    pub fn rotate_reserving_ids(
+        &self,
+        num_ids: u64,
+        check: impl FnOnce() -> LiteDbResult<()>,
+    ) -> LiteDbResult<u64> {
+        let _rotation_guard = self.rotation_lock.write();
+        check()?;
+        let current_mem_table = self.mem_tables.back().unwrap().value().clone();
+        let first_id = current_mem_table.id() + 1;
+        let new_mem_table = MemTable::open(
+            current_mem_table.dir(),
+            first_id + num_ids,
+            &self.mem_table_options,
+        )?;
+        self.mem_tables.insert(Arc::new(new_mem_table));
+        // wakes the controller up, the new mem_table being empty it is not rotated again
+        current_mem_table.request_flush();
+        Ok(first_id)
+    }
+
     pub fn stop(&mut self) {
-        self.kill_signal_sender.send(()).unwrap();
-        let task_handle = self.task_handle.take().unwrap();
-        task_handle.join().unwrap();
+        // every task takes a single signal before it stops
+        for _ in 0..self.task_handles.len() {
+            self.kill_signal_sender.send(()).unwrap();
+        }
+        for task_handle in self.task_handles.drain(..) {
+            task_handle.join().unwrap();
+        }
     }
 
     /// Creates a policy from a MemTableControllerPolicyConfig.
-    fn create_policy(
+    pub(crate) fn create_policy(
         mem_table_controller_policy: &MemTableControllerPolicyConfig,
     ) -> LiteDbResult<Arc<dyn MemTableControllerPolicy>> {
         let policy = match mem_table_controller_policy {
             MemTableControllerPolicyConfig::SizeTiered {
                 max_entries,
                 max_size_bytes,
-            } => SizeTieredMemTableController::new(*max_entries, *max_size_bytes),
+            } => SizeTieredMemTableController::new(*max_entries, *max_size_bytes, None),
+            MemTableControllerPolicyConfig::SizeTieredWithMaxAge {
+                max_entries,
+                max_size_bytes,
+                max_age,
+            } => SizeTieredMemTableController::new(*max_entries, *max_size_bytes, Some(*max_age)),
         };
         Ok(Arc::new(policy))
     }
@@ -109,23 +333,149 @@ impl MemTableController {
 struct SizeTieredMemTableController {
     max_entries: usize,
     max_size_bytes: usize,
+    max_age: Option<Duration>,
 }
 
 impl SizeTieredMemTableController {
-    pub(crate) fn new(max_entries: usize, max_size_bytes: usize) -> Self {
+    pub(crate) fn new(
+        max_entries: usize,
+        max_size_bytes: usize,
+        max_age: Option<Duration>,
+    ) -> Self {
         Self {
             max_entries,
             max_size_bytes,
+            max_age,
         }
     }
 }
 
 impl MemTableControllerPolicy for SizeTieredMemTableController {
     fn is_mature(&self, mem_table: &MemTable) -> bool {
-        mem_table.is_full(self.max_entries, self.max_size_bytes)
+        let too_old = matches!(
+            self.max_age,
+            Some(max_age) if mem_table.num_entries() > 0 && mem_table.age() >= max_age
+        );
+        too_old || mem_table.is_full(self.max_entries, self.max_size_bytes)
+    }
+
+    fn next_schedule(&self) -> Option<Duration> {
+        // checking at a fraction of the max_age bounds how late a flush can be
+        self.max_age.map(|max_age| max_age / 4)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::Arc,
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use crossbeam_skiplist::SkipSet;
+    use tempfile::tempdir;
+
+    use crate::{
+        background_error::BackgroundError,
+        controller::{MemTableController, MemTableControllerPolicyConfig, RotationTrigger},
+        manifest::Manifest,
+        mem_table::{MemTable, MemTableOptions},
+        options::WriteOptions,
+        ss_table::{SSTable, SSTableOptions},
+        utils::AtomicOperationExecutor,
+    };
+
+    fn wait_for_flush(ss_tables: &SkipSet<Arc<SSTable>>, num_ss_tables: usize) -> bool {
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while ss_tables.len() < num_ss_tables && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(5));
+        }
+        ss_tables.len() >= num_ss_tables
     }
 
-    fn next_schedule(&self) -> Duration {
-        Duration::from_secs(3)
+    #[test]
+    fn test_mem_table_rotation() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+        let dir = temp_dir.path().to_path_buf();
+        let policy_config = MemTableControllerPolicyConfig::SizeTieredWithMaxAge {
+            max_entries: 10,
+            max_size_bytes: 1_000_000,
+            max_age: Duration::from_millis(200),
+        };
+        let (rotation_trigger, rotation_listener) = RotationTrigger::new(&policy_config);
+        let mem_table_options = MemTableOptions {
+            rotation_trigger: Some(rotation_trigger),
+            ..MemTableOptions::default()
+        };
+        let mem_tables = Arc::new(SkipSet::new());
+        mem_tables.insert(Arc::new(MemTable::open(
+            dir.clone(),
+            0,
+            &mem_table_options,
+        )?));
+        let ss_tables = Arc::new(SkipSet::new());
+        let manifest = Arc::new(Manifest::new(dir.clone()));
+        let mut controller = MemTableController::start(
+            mem_tables.clone(),
+            ss_tables.clone(),
+            Arc::new(AtomicOperationExecutor::new()),
+            mem_table_options,
+            SSTableOptions::for_test(),
+            &policy_config,
+            3,
+            rotation_listener,
+            manifest.clone(),
+            BackgroundError::default(),
+        )?;
+
+        // a full mem_table is flushed right away
+        for i in 0..10 {
+            let _pin_guard = controller.pin_current_mem_table();
+            let k = format!("k_{i}");
+            let current_mem_table = mem_tables.back().unwrap();
+            current_mem_table.set(k.as_bytes(), b"v", &WriteOptions::default())?;
+        }
+        assert!(wait_for_flush(&ss_tables, 1));
+        assert_eq!(mem_tables.len(), 1);
+        assert_eq!(mem_tables.back().unwrap().num_entries(), 0);
+
+        // a mem_table far from full is flushed once old enough
+        {
+            let _pin_guard = controller.pin_current_mem_table();
+            let current_mem_table = mem_tables.back().unwrap();
+            current_mem_table.set(b"k", b"v", &WriteOptions::default())?;
+        }
+        assert!(wait_for_flush(&ss_tables, 2));
+
+        // full mem_tables keep getting rotated while a flush is stuck, up to the write buffers
+        let deletion_guard = manifest.lock_deletions();
+        let mut num_rotations = 0;
+        for _ in 0..5 {
+            for i in 0..10 {
+                let _pin_guard = controller.pin_current_mem_table();
+                let k = format!("k_{i}");
+                let current_mem_table = mem_tables.back().unwrap();
+                current_mem_table.set(k.as_bytes(), b"v", &WriteOptions::default())?;
+            }
+            let deadline = Instant::now() + Duration::from_secs(1);
+            while mem_tables.back().unwrap().num_entries() > 0 && Instant::now() < deadline {
+                thread::sleep(Duration::from_millis(5));
+            }
+            if mem_tables.back().unwrap().num_entries() > 0 {
+                break;
+            }
+            num_rotations += 1;
+        }
+        // the stuck flush may have published its mem_table, that one leaves a write buffer
+        assert!(num_rotations >= 2);
+        assert_eq!(mem_tables.len(), 3);
+        assert_eq!(mem_tables.back().unwrap().num_entries(), 10);
+        drop(deletion_guard);
+        assert!(wait_for_flush(&ss_tables, 3 + num_rotations));
+        assert_eq!(mem_tables.len(), 1);
+
+        controller.stop();
+        Ok(())
     }
 }
@@ -0,0 +1,411 @@
+use crate::{error::LiteDbResult, KVIterator, Key, RefKey, RefValue, TOMBSTONE};
+
+/// A bidirectional, seekable position over an ordered set of key/value entries.
+///
+/// A freshly created cursor is not positioned; call one of the `seek*`
+/// methods before reading. Calling `next` or `prev` on an invalid cursor
+/// leaves it invalid.
+pub trait Cursor {
+    /// Returns true when the cursor is positioned on an entry.
+    fn valid(&self) -> bool;
+    /// Positions the cursor on the first entry.
+    fn seek_to_first(&mut self) -> LiteDbResult<()>;
+    /// Positions the cursor on the last entry.
+    fn seek_to_last(&mut self) -> LiteDbResult<()>;
+    /// Positions the cursor on the first entry with a key at or after `key`.
+    fn seek(&mut self, key: RefKey) -> LiteDbResult<()>;
+    /// Positions the cursor on the last entry with a key at or before `key`.
+    fn seek_for_prev(&mut self, key: RefKey) -> LiteDbResult<()>;
+    /// Moves to the next entry.
+    fn next(&mut self) -> LiteDbResult<()>;
+    /// Moves to the previous entry.
+    fn prev(&mut self) -> LiteDbResult<()>;
+    /// Returns the key of the current entry, the cursor must be valid.
+    fn key(&self) -> RefKey<'_>;
+    /// Returns the value of the current entry, the cursor must be valid.
+    fn value(&self) -> RefValue<'_>;
+}
+
+impl Cursor for KVIterator {
+    fn valid(&self) -> bool {
+        match self {
+            KVIterator::MemTable(iter) => iter.valid(),
+            KVIterator::SSTable(iter) => iter.valid(),
+        }
+    }
+
+    fn seek_to_first(&mut self) -> LiteDbResult<()> {
+        match self {
+            KVIterator::MemTable(iter) => iter.seek_to_first(),
+            KVIterator::SSTable(iter) => iter.seek_to_first(),
+        }
+    }
+
+    fn seek_to_last(&mut self) -> LiteDbResult<()> {
+        match self {
+            KVIterator::MemTable(iter) => iter.seek_to_last(),
+            KVIterator::SSTable(iter) => iter.seek_to_last(),
+        }
+    }
+
+    fn seek(&mut self, key: RefKey) -> LiteDbResult<()> {
+        match self {
+            KVIterator::MemTable(iter) => iter.seek(key),
+            KVIterator::SSTable(iter) => iter.seek(key),
+        }
+    }
+
+    fn seek_for_prev(&mut self, key: RefKey) -> LiteDbResult<()> {
+        match self {
+            KVIterator::MemTable(iter) => iter.seek_for_prev(key),
+            KVIterator::SSTable(iter) => iter.seek_for_prev(key),
+        }
+    }
+
+    fn next(&mut self) -> LiteDbResult<()> {
+        match self {
+            KVIterator::MemTable(iter) => Cursor::next(iter),
+            KVIterator::SSTable(iter) => Cursor::next(iter),
+        }
+    }
+
+    fn prev(&mut self) -> LiteDbResult<()> {
+        match self {
+            KVIterator::MemTable(iter) => iter.prev(),
+            KVIterator::SSTable(iter) => iter.prev(),
+        }
+    }
+
+    fn key(&self) -> RefKey<'_> {
+        match self {
+            KVIterator::MemTable(iter) => iter.key(),
+            KVIterator::SSTable(iter) => iter.key(),
+        }
+    }
+
+    fn value(&self) -> RefValue<'_> {
+        match self {
+            KVIterator::MemTable(iter) => iter.value(),
+            KVIterator::SSTable(iter) => iter.value(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Direction {
+    Forward,
+    Reverse,
+}
+
+/// A bidirectional version of `CombineIterator`.
+///
+/// Cursors are ordered from oldest to newest. When several cursors hold
+/// the same key, the entry of the newest (upper) cursor is exposed. Keys
+/// whose newest entry is a tombstone are skipped.
+pub(crate) struct CombineCursor {
+    cursors: Vec<KVIterator>,
+    current: Option<usize>,
+    direction: Direction,
+}
+
+impl CombineCursor {
+    pub(crate) fn new(cursors: Vec<KVIterator>) -> Self {
+        Self {
+            cursors,
+            current: None,
+            direction: Direction::Forward,
+        }
+    }
+
+    fn find_smallest(&mut self) {
+        let mut smallest: Option<usize> = None;
+        for (idx, cursor) in self.cursors.iter().enumerate() {
+            if !cursor.valid() {
+                continue;
+            }
+            match smallest {
+                // on equal keys, the later cursor wins because it is newer
+                Some(best) if cursor.key() > self.cursors[best].key() => (),
+                _ => smallest = Some(idx),
+            }
+        }
+        self.current = smallest;
+    }
+
+    fn find_largest(&mut self) {
+        let mut largest: Option<usize> = None;
+        for (idx, cursor) in self.cursors.iter().enumerate() {
+            if !cursor.valid() {
+                continue;
+            }
+            match largest {
+                // on equal keys, the later cursor wins because it is newer
+                Some(best) if cursor.key() < self.cursors[best].key() => (),
+                _ => largest = Some(idx),
+            }
+        }
+        self.current = largest;
+    }
+
+    fn is_deleted(&self) -> bool {
+        self.valid() && self.value() == TOMBSTONE
+    }
+
+    /// Moves forward until the cursor is on a live entry or invalid.
+    fn skip_deleted_forward(&mut self) -> LiteDbResult<()> {
+        while self.is_deleted() {
+            self.step_forward()?;
+        }
+        Ok(())
+    }
+
+    /// Moves backward until the cursor is on a live entry or invalid.
+    fn skip_deleted_backward(&mut self) -> LiteDbResult<()> {
+        while self.is_deleted() {
+            self.step_backward()?;
+        }
+        Ok(())
+    }
+
+    fn step_forward(&mut self) -> LiteDbResult<()> {
+        let key: Key = self.key().to_vec();
+
+        // When moving backward, the other cursors are positioned before `key`.
+        // Bring all of them at or after `key` first.
+        if self.direction == Direction::Reverse {
+            for cursor in self.cursors.iter_mut() {
+                cursor.seek(&key)?;
+            }
+            self.direction = Direction::Forward;
+        }
+
+        // skip `key` in every cursor holding it
+        for cursor in self.cursors.iter_mut() {
+            if cursor.valid() && cursor.key() == key.as_slice() {
+                Cursor::next(cursor)?;
+            }
+        }
+        self.find_smallest();
+        Ok(())
+    }
+
+    fn step_backward(&mut self) -> LiteDbResult<()> {
+        let key: Key = self.key().to_vec();
+
+        // When moving forward, the other cursors are positioned after `key`.
+        // Bring all of them at or before `key` first.
+        if self.direction == Direction::Forward {
+            for cursor in self.cursors.iter_mut() {
+                cursor.seek_for_prev(&key)?;
+            }
+            self.direction = Direction::Reverse;
+        }
+
+        // skip `key` in every cursor holding it
+        for cursor in self.cursors.iter_mut() {
+            if cursor.valid() && cursor.key() == key.as_slice() {
+                cursor.prev()?;
+            }
+        }
+        self.find_largest();
+        Ok(())
+    }
+}
+
+impl Cursor for CombineCursor {
+    fn valid(&self) -> bool {
+        self.current.is_some()
+    }
+
+    fn seek_to_first(&mut self) -> LiteDbResult<()> {
+        for cursor in self.cursors.iter_mut() {
+            cursor.seek_to_first()?;
+        }
+        self.direction = Direction::Forward;
+        self.find_smallest();
+        self.skip_deleted_forward()
+    }
+
+    fn seek_to_last(&mut self) -> LiteDbResult<()> {
+        for cursor in self.cursors.iter_mut() {
+            cursor.seek_to_last()?;
+        }
+        self.direction = Direction::Reverse;
+        self.find_largest();
+        self.skip_deleted_backward()
+    }
+
+    fn seek(&mut self, key: RefKey) -> LiteDbResult<()> {
+        for cursor in self.cursors.iter_mut() {
+            cursor.seek(key)?;
+        }
+        self.direction = Direction::Forward;
+        self.find_smallest();
+        self.skip_deleted_forward()
+    }
+
+    fn seek_for_prev(&mut self, key: RefKey) -> LiteDbResult<()> {
+        for cursor in self.cursors.iter_mut() {
+            cursor.seek_for_prev(key)?;
+        }
+        self.direction = Direction::Reverse;
+        self.find_largest();
+        self.skip_deleted_backward()
+    }
+
+    fn next(&mut self) -> LiteDbResult<()> {
+        if !self.valid() {
+            return Ok(());
+        }
+        self.step_forward()?;
+        self.skip_deleted_forward()
+    }
+
+    fn prev(&mut self) -> LiteDbResult<()> {
+        if !self.valid() {
+            return Ok(());
+        }
+        self.step_backward()?;
+        self.skip_deleted_backward()
+    }
+
+    fn key(&self) -> RefKey<'_> {
+        let idx = self.current.expect("Expected a valid cursor.");
+        self.cursors[idx].key()
+    }
+
+    fn value(&self) -> RefValue<'_> {
+        let idx = self.current.expect("Expected a valid cursor.");
+        self.cursors[idx].value()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::Path, sync::Arc};
+
+    use crate::{
+        error::LiteDbResult,
+        mem_table::{MemTable, MemTableOptions},
+        options::WriteOptions,
+        Scannable,
+    };
+
+    use super::{CombineCursor, Cursor};
+
+    fn create_mem_table(path: &Path, id: u64, data: Vec<(&str, &str)>) -> Arc<MemTable> {
+        let mem_table =
+            MemTable::open(path.to_path_buf(), id, &MemTableOptions::default()).unwrap();
+        for (k, v) in data {
+            mem_table
+                .set(k.as_bytes(), v.as_bytes(), &WriteOptions::default())
+                .unwrap();
+        }
+        Arc::new(mem_table)
+    }
+
+    fn entry(cursor: &CombineCursor) -> (String, String) {
+        (
+            String::from_utf8(cursor.key().to_vec()).unwrap(),
+            String::from_utf8(cursor.value().to_vec()).unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_combine_cursor() -> LiteDbResult<()> {
+        let temp_dir = tempfile::tempdir()?;
+
+        let mem1 = create_mem_table(temp_dir.path(), 1, vec![("a", "a"), ("b", "b")]);
+        let mem2 = create_mem_table(temp_dir.path(), 2, vec![("c", "c"), ("b", "b1")]);
+        let mem3 = create_mem_table(temp_dir.path(), 3, vec![("a", "a1"), ("e", "e")]);
+        let mut cursor = CombineCursor::new(vec![
+            mem1.scan(&None, &None),
+            mem2.scan(&None, &None),
+            mem3.scan(&None, &None),
+        ]);
+        assert!(!cursor.valid());
+
+        let expected = vec![
+            ("a".to_string(), "a1".to_string()),
+            ("b".to_string(), "b1".to_string()),
+            ("c".to_string(), "c".to_string()),
+            ("e".to_string(), "e".to_string()),
+        ];
+
+        let mut forward = vec![];
+        cursor.seek_to_first()?;
+        while cursor.valid() {
+            forward.push(entry(&cursor));
+            cursor.next()?;
+        }
+        assert_eq!(forward, expected);
+
+        let mut backward = vec![];
+        cursor.seek_to_last()?;
+        while cursor.valid() {
+            backward.push(entry(&cursor));
+            cursor.prev()?;
+        }
+        backward.reverse();
+        assert_eq!(backward, expected);
+
+        // change direction in the middle
+        cursor.seek(b"b")?;
+        assert_eq!(entry(&cursor), expected[1]);
+        cursor.next()?;
+        assert_eq!(entry(&cursor), expected[2]);
+        cursor.prev()?;
+        assert_eq!(entry(&cursor), expected[1]);
+        cursor.prev()?;
+        assert_eq!(entry(&cursor), expected[0]);
+        cursor.next()?;
+        assert_eq!(entry(&cursor), expected[1]);
+
+        cursor.seek_for_prev(b"d")?;
+        assert_eq!(entry(&cursor), expected[2]);
+        cursor.seek(b"d")?;
+        assert_eq!(entry(&cursor), expected[3]);
+        cursor.seek(b"f")?;
+        assert!(!cursor.valid());
+        Ok(())
+    }
+
+    #[test]
+    fn test_combine_cursor_skips_deleted() -> LiteDbResult<()> {
+        let temp_dir = tempfile::tempdir()?;
+
+        let mem1 = create_mem_table(temp_dir.path(), 1, vec![("a", "a"), ("b", "b"), ("c", "c")]);
+        let mem2 = create_mem_table(temp_dir.path(), 2, vec![("b", ""), ("d", "")]);
+        let mut cursor = CombineCursor::new(vec![mem1.scan(&None, &None), mem2.scan(&None, &None)]);
+
+        let expected = vec![
+            ("a".to_string(), "a".to_string()),
+            ("c".to_string(), "c".to_string()),
+        ];
+
+        let mut forward = vec![];
+        cursor.seek_to_first()?;
+        while cursor.valid() {
+            forward.push(entry(&cursor));
+            cursor.next()?;
+        }
+        assert_eq!(forward, expected);
+
+        let mut backward = vec![];
+        cursor.seek_to_last()?;
+        while cursor.valid() {
+            backward.push(entry(&cursor));
+            cursor.prev()?;
+        }
+        backward.reverse();
+        assert_eq!(backward, expected);
+
+        cursor.seek(b"b")?;
+        assert_eq!(entry(&cursor), expected[1]);
+        cursor.seek_for_prev(b"b")?;
+        assert_eq!(entry(&cursor), expected[0]);
+        cursor.seek(b"d")?;
+        assert!(!cursor.valid());
+        Ok(())
+    }
+}
@@ -0,0 +1,159 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, ErrorKind, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    process,
+};
+
+use fs2::FileExt;
+
+use crate::error::{LiteDbError, LiteDbResult};
+
+const LOCK_FILE_NAME: &str = "LOCK";
+
+/// A `flock` on the `LOCK` file of a database directory.
+///
+/// The lock is tied to the open file, so a second open fails even from the same process.
+/// Read-only instances share the lock, which keeps writers out.
+pub(crate) struct DirLock {
+    // none for a read-only instance of a directory without `LOCK` file
+    file: Option<File>,
+    exclusive: bool,
+}
+
+impl DirLock {
+    pub fn acquire(dir: &Path) -> LiteDbResult<Self> {
+        let path = Self::path(dir);
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            // the holder's pid is read back when the lock is taken
+            .truncate(false)
+            .open(&path)?;
+        if let Err(err) = file.try_lock_exclusive() {
+            return Err(Self::lock_error(err, path, &mut file));
+        }
+
+        // record the holder to tell who keeps the database locked
+        file.set_len(0)?;
+        file.seek(SeekFrom::Start(0))?;
+        write!(file, "{}", process::id())?;
+        file.sync_data()?;
+        Ok(Self {
+            file: Some(file),
+            exclusive: true,
+        })
+    }
+
+    /// Takes the lock alongside other read-only instances, the `LOCK` file is opened
+    /// read-only so that it works on read-only mounts.
+    ///
+    /// Directories written before locks existed have no `LOCK` file, nothing is
+    /// locked then rather than creating one.
+    pub fn acquire_shared(dir: &Path) -> LiteDbResult<Self> {
+        let path = Self::path(dir);
+        let mut file = match File::open(&path) {
+            Err(err) if err.kind() == ErrorKind::NotFound => {
+                return Ok(Self {
+                    file: None,
+                    exclusive: false,
+                })
+            }
+            file_result => file_result?,
+        };
+        if let Err(err) = FileExt::try_lock_shared(&file) {
+            return Err(Self::lock_error(err, path, &mut file));
+        }
+        Ok(Self {
+            file: Some(file),
+            exclusive: false,
+        })
+    }
+
+    pub fn path(dir: &Path) -> PathBuf {
+        dir.join(LOCK_FILE_NAME)
+    }
+
+    pub fn release(&self) -> LiteDbResult<()> {
+        let file = match &self.file {
+            Some(file) => file,
+            None => return Ok(()),
+        };
+        if self.exclusive {
+            // read-only instances holding the lock next are not known by pid
+            file.set_len(0)?;
+        }
+        FileExt::unlock(file)?;
+        Ok(())
+    }
+
+    fn lock_error(err: io::Error, path: PathBuf, file: &mut File) -> LiteDbError {
+        if err.raw_os_error() != fs2::lock_contended_error().raw_os_error() {
+            return err.into();
+        }
+        LiteDbError::Locked {
+            path,
+            pid: Self::read_holder_pid(file),
+        }
+    }
+
+    fn read_holder_pid(file: &mut File) -> Option<u32> {
+        let mut content = String::new();
+        file.read_to_string(&mut content).ok()?;
+        content.trim().parse().ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, process};
+
+    use tempfile::tempdir;
+
+    use crate::{dir_lock::DirLock, error::LiteDbError};
+
+    #[test]
+    fn test_dir_lock() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+        let lock = DirLock::acquire(temp_dir.path())?;
+        match DirLock::acquire(temp_dir.path()) {
+            Err(LiteDbError::Locked { path, pid }) => {
+                assert_eq!(path, DirLock::path(temp_dir.path()));
+                assert_eq!(pid, Some(process::id()));
+            }
+            _ => panic!("Expected the directory to be locked."),
+        }
+
+        lock.release()?;
+        DirLock::acquire(temp_dir.path())?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_dir_lock_shared() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+        let lock_path = DirLock::path(temp_dir.path());
+
+        // a directory without `LOCK` file is left untouched
+        let lock = DirLock::acquire_shared(temp_dir.path())?;
+        assert!(!lock_path.exists());
+        lock.release()?;
+
+        // a read-only `LOCK` file is enough to share the lock
+        let lock = DirLock::acquire(temp_dir.path())?;
+        lock.release()?;
+        drop(lock);
+        let mut permissions = fs::metadata(&lock_path)?.permissions();
+        permissions.set_readonly(true);
+        fs::set_permissions(&lock_path, permissions)?;
+        let shared_lock = DirLock::acquire_shared(temp_dir.path())?;
+        DirLock::acquire_shared(temp_dir.path())?;
+        assert!(matches!(
+            DirLock::acquire(temp_dir.path()),
+            Err(LiteDbError::Locked { .. }) | Err(LiteDbError::Io(_))
+        ));
+        shared_lock.release()?;
+        Ok(())
+    }
+}
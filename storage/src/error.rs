@@ -1,4 +1,4 @@
-use std::io;
+use std::{io, path::PathBuf, sync::Arc};
 
 use bincode::error::{DecodeError, EncodeError};
 use thiserror::Error;
@@ -17,6 +17,52 @@ pub enum LiteDbError {
     Io(io::Error),
     #[error("Policy error: `{0}`.")]
     PolicyError(String),
+    #[error("Cannot build an empty ss_table.")]
+    EmptySSTable,
+    #[error("Corrupted wal at offset {offset}: {reason}.")]
+    CorruptedWal { offset: u64, reason: &'static str },
+    #[error("Writes are stalled until flushes and compactions catch up.")]
+    WriteStall,
+    #[error("Database is locked through `{}`{}.", .path.display(), lock_holder(.pid))]
+    Locked { path: PathBuf, pid: Option<u32> },
+    #[error("The database is opened read-only.")]
+    ReadOnly,
+    #[error("Only secondary instances catch up with a primary.")]
+    NotSecondary,
+    #[error("The primary kept replacing its files during the catch-up.")]
+    PrimaryChanged,
+    #[error("Backup {backup_id} is corrupted, `{file}` does not match its checksum.")]
+    CorruptedBackup { backup_id: u64, file: String },
+    #[error("No such backup.")]
+    NoBackup,
+    #[error("Keys must be added in strictly ascending order.")]
+    UnsortedKeys,
+    #[error("The ingested file `{}` overlaps the data of the database.", .0.display())]
+    IngestionOverlap(PathBuf),
+    #[error(
+        "The wal archive misses wal {0}, the writes made since the backup cannot be restored."
+    )]
+    IncompleteWalArchive(u64),
+    #[error("The restore target is older than the backup.")]
+    RestoreTargetBeforeBackup,
+    #[error("A background flush or compaction failed: `{0}`.")]
+    Background(Arc<LiteDbError>),
+    #[error(
+        "`{}` is in format version {version}, only version {supported} is supported.",
+        .path.display()
+    )]
+    UnsupportedFormat {
+        path: PathBuf,
+        version: u32,
+        supported: u32,
+    },
+}
+
+fn lock_holder(pid: &Option<u32>) -> String {
+    match pid {
+        Some(pid) => format!(" by process {pid}"),
+        None => String::new(),
+    }
 }
 
 impl From<io::Error> for LiteDbError {
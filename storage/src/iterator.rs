@@ -98,14 +98,22 @@ impl Iterator for CombineIterator {
 mod tests {
     use std::{path::Path, sync::Arc};
 
-    use crate::{error::LiteDbResult, mem_table::MemTable, Scannable};
+    use crate::{
+        error::LiteDbResult,
+        mem_table::{MemTable, MemTableOptions},
+        options::WriteOptions,
+        Scannable,
+    };
 
     use super::CombineIterator;
 
     fn create_mem_table(path: &Path, id: u64, data: Vec<(&str, &str)>) -> Arc<MemTable> {
-        let mem_table = MemTable::open(path.to_path_buf(), id).unwrap();
+        let mem_table =
+            MemTable::open(path.to_path_buf(), id, &MemTableOptions::default()).unwrap();
         for (k, v) in data {
-            mem_table.set(k.as_bytes(), v.as_bytes()).unwrap();
+            mem_table
+                .set(k.as_bytes(), v.as_bytes(), &WriteOptions::default())
+                .unwrap();
         }
         Arc::new(mem_table)
     }
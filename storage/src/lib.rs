@@ -1,31 +1,67 @@
+mod background_error;
+mod backup;
 mod batching;
+mod blob;
+mod block_cache;
 mod bloom_filter;
 mod compactor;
 mod controller;
+mod cursor;
+mod dir_lock;
 mod error;
 mod iterator;
+mod lru_cache;
+mod manifest;
 mod mem_table;
+mod mem_table_rep;
 mod options;
+mod range_filter;
+mod snapshot;
 mod ss_table;
+mod sst_file_writer;
+mod table_cache;
 mod utils;
 mod wal;
+mod wal_archive;
+mod write_buffer_manager;
+mod write_controller;
 
+use background_error::BackgroundError;
+pub use backup::{BackupEngine, BackupInfo};
 use batching::BatchOperations;
+use blob::{blob_file_path, referenced_blob_files, remove_unreferenced_blob_files};
+pub use block_cache::BlockCache;
+use bloom_filter::BloomFilterPolicy;
 use compactor::Compactor;
 pub use compactor::CompactorPolicyConfig;
-use controller::MemTableController;
 pub use controller::MemTableControllerPolicyConfig;
+use controller::{MemTableController, RotationTrigger};
 use crossbeam_skiplist::SkipSet;
+use cursor::CombineCursor;
+pub use cursor::Cursor;
+use dir_lock::DirLock;
 use error::{LiteDbError, LiteDbResult};
 use iterator::CombineIterator;
-use mem_table::MemTable;
+use manifest::{read_manifest, ss_table_file_name, write_manifest, Manifest};
 use mem_table::MemTableIterator;
-pub use options::LiteDbOptions;
-use ss_table::{SSTable, SSTableIterator};
-use utils::AtomicOperationExecutor;
+pub use mem_table::MemoryUsage;
+use mem_table::{MemTable, MemTableOptions};
+pub use mem_table_rep::MemTableFactory;
+pub use options::{IngestOptions, LiteDbOptions, ReadOptions, WriteOptions};
+pub use snapshot::Snapshot;
+use ss_table::{ss_table_file_path, SSTable, SSTableCaches, SSTableIterator, SSTableOptions};
+pub use sst_file_writer::{ExternalSstFileInfo, SstFileWriter};
+use table_cache::TableCache;
+use utils::{create_temp_dir_for, AtomicOperationExecutor};
 use wal::is_mem_table_file;
+pub use wal::{WalRecoveryMode, WalRecoveryReport, WalSyncMode};
+pub use wal_archive::{RestoreTarget, WalArchiveOptions};
+pub use write_buffer_manager::WriteBufferManager;
+use write_controller::WriteController;
 
-use std::fs;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{self, File};
+use std::io::{self, ErrorKind, Read};
 use std::path::Path;
 use std::path::PathBuf;
 use std::sync::Arc;
@@ -49,78 +85,436 @@ impl Iterator for KVIterator {
 
     fn next(&mut self) -> Option<Self::Item> {
         match self {
-            KVIterator::MemTable(iter) => iter.next(),
-            KVIterator::SSTable(iter) => iter.next(),
+            KVIterator::MemTable(iter) => Iterator::next(iter),
+            KVIterator::SSTable(iter) => Iterator::next(iter),
         }
     }
 }
 
 pub(crate) trait Scannable {
-    fn scan(&self, from: &Option<Key>, to: &Option<Key>) -> KVIterator;
+    fn scan(&self, from: &Option<Key>, to: &Option<Key>) -> KVIterator {
+        self.scan_with_options(from, to, &ReadOptions::default())
+    }
+
+    fn scan_with_options(
+        &self,
+        from: &Option<Key>,
+        to: &Option<Key>,
+        read_options: &ReadOptions,
+    ) -> KVIterator;
+}
+
+/// Number of times a catch-up is retried while the primary replaces its files.
+const MAX_CATCH_UP_ATTEMPTS: usize = 10;
+/// Extension of the files being ingested until they get their final name.
+const INGEST_TEMP_FILE_EXTENSION: &str = "ingest.tmp";
+
+/// The tables found in a database directory.
+struct RecoveredTables {
+    mem_tables: SkipSet<Arc<MemTable>>,
+    ss_tables: SkipSet<Arc<SSTable>>,
+    wal_recovery_report: WalRecoveryReport,
+}
+
+/// A renumbered copy of an external file, staged under a temp name until a
+/// manifest lists it.
+struct StagedFile {
+    temp_path: PathBuf,
+    path: PathBuf,
+}
+
+impl StagedFile {
+    fn new(dir: &Path, id: u64) -> Self {
+        Self {
+            temp_path: dir.join(format!("{:01$}.{INGEST_TEMP_FILE_EXTENSION}", id, 20)),
+            path: ss_table_file_path(dir, id, 0),
+        }
+    }
+
+    /// Removes the staged file, under whichever name it has.
+    fn discard(&self) -> io::Result<()> {
+        for path in [&self.temp_path, &self.path] {
+            if path.exists() {
+                fs::remove_file(path)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Tells whether the file was staged by an ingestion.
+fn is_ingest_temp_file(path: &Path) -> bool {
+    path.to_string_lossy()
+        .ends_with(&format!(".{INGEST_TEMP_FILE_EXTENSION}"))
+}
+
+/// Returns the final path of a file staged by an ingestion.
+fn ingested_ss_table_path(temp_path: &Path) -> PathBuf {
+    let id: u64 = temp_path
+        .file_name()
+        .expect("Expected a file name")
+        .to_string_lossy()
+        .trim_end_matches(&format!(".{INGEST_TEMP_FILE_EXTENSION}"))
+        .parse()
+        .expect("Expected a valid ingested file id.");
+    StagedFile::new(
+        temp_path.parent().expect("Expected a database directory"),
+        id,
+    )
+    .path
+}
+
+/// Hard-links the file into `dir`, copies it when the link cannot be made.
+fn link_or_copy_file(path: &Path, dir: &Path) -> LiteDbResult<()> {
+    let target_path = dir.join(path.file_name().expect("Expected a file name"));
+    if fs::hard_link(path, &target_path).is_err() {
+        copy_file(path, dir)?;
+    }
+    Ok(())
+}
+
+/// Copies the file into `dir` and syncs the copy.
+fn copy_file(path: &Path, dir: &Path) -> LiteDbResult<()> {
+    let target_path = dir.join(path.file_name().expect("Expected a file name"));
+    fs::copy(path, &target_path)?;
+    File::open(&target_path)?.sync_all()?;
+    Ok(())
+}
+
+/// Copies the first `len` bytes of the file into `dir` and syncs the copy.
+fn copy_file_prefix(path: &Path, dir: &Path, len: u64) -> LiteDbResult<()> {
+    let target_path = dir.join(path.file_name().expect("Expected a file name"));
+    let mut target_file = File::create(&target_path)?;
+    io::copy(&mut File::open(path)?.take(len), &mut target_file)?;
+    target_file.sync_all()?;
+    Ok(())
+}
+
+/// Lists the ss_table & wal files of a database directory.
+fn list_table_files(path: &Path) -> LiteDbResult<BTreeSet<PathBuf>> {
+    let mut files = BTreeSet::new();
+    for entry_result in fs::read_dir(path)? {
+        let entry_path = entry_result?.path();
+        if is_ss_table_file(&entry_path) || is_mem_table_file(&entry_path) {
+            files.insert(entry_path);
+        }
+    }
+    Ok(files)
+}
+
+/// Returns the smallest key after `key`, the exclusive bound of a range ending at it.
+fn key_successor(key: RefKey) -> Key {
+    let mut successor = key.to_vec();
+    successor.push(0);
+    successor
+}
+
+/// Tells whether any of the tables holds a key in `[from, to)`, deletions included.
+fn any_holds_key_in<S: Scannable>(
+    tables: &[S],
+    from: &Option<Key>,
+    to: &Option<Key>,
+) -> LiteDbResult<bool> {
+    for table in tables {
+        if Iterator::next(&mut table.scan(from, to))
+            .transpose()?
+            .is_some()
+        {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Tells whether the error comes from the primary replacing its files during a catch-up.
+fn is_primary_change(err: &LiteDbError) -> bool {
+    match err {
+        LiteDbError::PrimaryChanged => true,
+        LiteDbError::Io(err) => err.kind() == ErrorKind::NotFound,
+        _ => false,
+    }
 }
 
 pub struct LiteDb {
     options: LiteDbOptions,
     /// An ordered list of MemTable.
     // All mem_tables are managed together.
-    // They are ordered like as list [current, flushing_1, flushing_2, ...]
+    // They are ordered by id like as list [flushing_1, flushing_2, ..., current]
     // The current (more recent) mem_table the one used to handle writes
-    // All subsequent mem_tables are those that are being flushed to ss_table.
+    // All preceding mem_tables are those that are being flushed to ss_table.
     // A flushing mem_table gets removed as soon as it's flushed to disk as ss_table.
     mem_tables: Arc<SkipSet<Arc<MemTable>>>,
     /// An ordered list of SSTable.
     ss_tables: Arc<SkipSet<Arc<SSTable>>>,
-    // atomic_operation_executor: Arc<AtomicOperationExecutor>,
-    mem_controller: MemTableController,
-    compactor: Compactor,
+    /// Publishes flushes & compactions, reads take their view of the tables under it.
+    atomic_operation_executor: Arc<AtomicOperationExecutor>,
+    /// Lists the ss_tables a reopen finds, rewritten after every publish.
+    manifest: Arc<Manifest>,
+    /// Read-only databases run neither controller nor compactor.
+    mem_controller: Option<MemTableController>,
+    compactor: Option<Compactor>,
+    write_controller: WriteController,
+    wal_recovery_report: WalRecoveryReport,
     path: PathBuf,
+    /// The directory of the primary a secondary instance catches up with.
+    primary_path: Option<PathBuf>,
+    /// Keeps other instances from opening the same directory, shared by read-only ones.
+    dir_lock: DirLock,
+    ss_table_caches: SSTableCaches,
+    /// Set once a background flush or compaction fails.
+    background_error: BackgroundError,
 }
 
 impl LiteDb {
     pub fn open<P: AsRef<Path>>(dir: P, options: LiteDbOptions) -> LiteDbResult<Self> {
         let path = PathBuf::from(dir.as_ref());
+        let background_error = BackgroundError::default();
+        let write_controller = WriteController::new(&options, background_error.clone())?;
         let atomic_operation_executor = Arc::new(AtomicOperationExecutor::new());
+        let manifest = Arc::new(Manifest::new(path.clone()));
+        let (rotation_trigger, rotation_listener) =
+            RotationTrigger::new(&options.mem_table_controller_policy);
+        let mem_table_options = MemTableOptions {
+            wal_sync_mode: options.wal_sync_mode,
+            wal_recovery_mode: options.wal_recovery_mode,
+            rotation_trigger: Some(rotation_trigger.clone()),
+            memtable_factory: options.memtable_factory,
+            write_buffer_manager: options.write_buffer_manager.clone(),
+            read_only: false,
+            wal_archive: options.wal_archive,
+        };
+        let ss_table_caches = Self::ss_table_caches(&options);
+        let ss_table_options = SSTableOptions {
+            bloom_filter_policy: BloomFilterPolicy::new(
+                options.bloom_bits_per_key,
+                options.bloom_filter_memory_budget,
+            ),
+            sparse_index_range_size: options.sparse_index_range_size,
+            range_filter: options.range_filter,
+            min_blob_size: options.min_blob_size,
+            index_partition_size: options.index_partition_size,
+            caches: ss_table_caches.clone(),
+        };
         if !path.exists() {
             fs::create_dir_all(&path)?;
+            let dir_lock = DirLock::acquire(&path)?;
             let mem_tables = Arc::new(SkipSet::new());
-            mem_tables.insert(Arc::new(MemTable::open(path.clone(), 0)?));
+            mem_tables.insert(Arc::new(MemTable::open(
+                path.clone(),
+                0,
+                &mem_table_options,
+            )?));
 
             let ss_tables = Arc::new(SkipSet::new());
+            manifest.persist(&ss_tables, &atomic_operation_executor)?;
 
+            if let Some(write_buffer_manager) = &options.write_buffer_manager {
+                write_buffer_manager.register(&mem_tables, rotation_trigger);
+            }
             let mem_controller = MemTableController::start(
                 mem_tables.clone(),
                 ss_tables.clone(),
                 atomic_operation_executor.clone(),
-                options.bloom_filter_size_bytes,
-                options.bloom_filter_item_count,
-                options.sparse_index_range_size,
+                mem_table_options,
+                ss_table_options.clone(),
                 &options.mem_table_controller_policy,
+                options.max_write_buffer_number,
+                rotation_listener,
+                manifest.clone(),
+                background_error.clone(),
             )?;
             let compactor = Compactor::start(
                 ss_tables.clone(),
                 atomic_operation_executor.clone(),
+                ss_table_options,
                 &options.compactor_policy,
+                manifest.clone(),
+                background_error.clone(),
             )?;
 
             return Ok(Self {
                 options,
                 mem_tables,
                 ss_tables,
-                mem_controller,
-                compactor,
+                atomic_operation_executor,
+                manifest,
+                mem_controller: Some(mem_controller),
+                compactor: Some(compactor),
+                write_controller,
+                wal_recovery_report: WalRecoveryReport::default(),
                 path,
+                primary_path: None,
+                dir_lock,
+                ss_table_caches,
+                background_error,
             });
         }
 
+        let dir_lock = DirLock::acquire(&path)?;
+
+        let RecoveredTables {
+            mem_tables,
+            ss_tables,
+            wal_recovery_report,
+        } = Self::recover_tables(
+            &path,
+            &mem_table_options,
+            &ss_table_caches,
+            &BTreeMap::new(),
+        )?;
+        remove_unreferenced_blob_files(&path, &referenced_blob_files(&ss_tables))?;
+        let mem_tables = Arc::new(mem_tables);
+        let ss_tables = Arc::new(ss_tables);
+        manifest.persist(&ss_tables, &atomic_operation_executor)?;
+        if let Some(write_buffer_manager) = &options.write_buffer_manager {
+            write_buffer_manager.register(&mem_tables, rotation_trigger);
+        }
+        let mem_controller = MemTableController::start(
+            mem_tables.clone(),
+            ss_tables.clone(),
+            atomic_operation_executor.clone(),
+            mem_table_options,
+            ss_table_options.clone(),
+            &options.mem_table_controller_policy,
+            options.max_write_buffer_number,
+            rotation_listener,
+            manifest.clone(),
+            background_error.clone(),
+        )?;
+        let compactor = Compactor::start(
+            ss_tables.clone(),
+            atomic_operation_executor.clone(),
+            ss_table_options,
+            &options.compactor_policy,
+            manifest.clone(),
+            background_error.clone(),
+        )?;
+        Ok(Self {
+            options,
+            mem_tables,
+            ss_tables,
+            atomic_operation_executor,
+            manifest,
+            mem_controller: Some(mem_controller),
+            compactor: Some(compactor),
+            write_controller,
+            wal_recovery_report,
+            path,
+            primary_path: None,
+            dir_lock,
+            ss_table_caches,
+            background_error,
+        })
+    }
+
+    /// Opens the database to read it without modifying its directory.
+    ///
+    /// The wals are replayed in memory, no flush nor compaction runs and writes fail
+    /// with `LiteDbError::ReadOnly`. Other read-only instances may open the directory
+    /// at the same time.
+    pub fn open_read_only<P: AsRef<Path>>(dir: P, options: LiteDbOptions) -> LiteDbResult<Self> {
+        let path = PathBuf::from(dir.as_ref());
+        let background_error = BackgroundError::default();
+        let write_controller = WriteController::new(&options, background_error.clone())?;
+        let mem_table_options = MemTableOptions {
+            wal_recovery_mode: options.wal_recovery_mode,
+            memtable_factory: options.memtable_factory,
+            read_only: true,
+            ..MemTableOptions::default()
+        };
+        let dir_lock = DirLock::acquire_shared(&path)?;
+        let ss_table_caches = Self::ss_table_caches(&options);
+        let RecoveredTables {
+            mem_tables,
+            ss_tables,
+            wal_recovery_report,
+        } = Self::recover_tables(
+            &path,
+            &mem_table_options,
+            &ss_table_caches,
+            &BTreeMap::new(),
+        )?;
+        Ok(Self {
+            options,
+            mem_tables: Arc::new(mem_tables),
+            ss_tables: Arc::new(ss_tables),
+            atomic_operation_executor: Arc::new(AtomicOperationExecutor::new()),
+            manifest: Arc::new(Manifest::new(path.clone())),
+            mem_controller: None,
+            compactor: None,
+            write_controller,
+            wal_recovery_report,
+            path,
+            primary_path: None,
+            dir_lock,
+            ss_table_caches,
+            background_error,
+        })
+    }
+
+    /// Returns the caches the ss_tables of the database go through.
+    fn ss_table_caches(options: &LiteDbOptions) -> SSTableCaches {
+        SSTableCaches {
+            block_cache: options.block_cache.clone(),
+            table_cache: options.max_open_files.map(TableCache::new),
+        }
+    }
+
+    /// Lists the ss_tables & replays the wals found in the directory.
+    ///
+    /// The ss_tables found in `opened_ss_tables`, by file name, are reused instead of opened again.
+    fn recover_tables(
+        path: &Path,
+        mem_table_options: &MemTableOptions,
+        ss_table_caches: &SSTableCaches,
+        opened_ss_tables: &BTreeMap<String, Arc<SSTable>>,
+    ) -> LiteDbResult<RecoveredTables> {
         // List all ss_tables & mem_tables
         let ss_tables = SkipSet::new();
-        let mem_tables = Arc::new(SkipSet::new());
-        let entries = fs::read_dir(dir.as_ref())?;
+        let mem_tables = SkipSet::new();
+        let mut wal_recovery_report = WalRecoveryReport::default();
+        // databases written before the manifest existed list no file
+        let mut unopened_files = read_manifest(path)?;
+        let entries = fs::read_dir(path)?;
         for entry_result in entries {
             let entry_path = entry_result?.path();
-            if is_ss_table_file(&entry_path) {
-                let ss_table = SSTable::open(entry_path.clone())?;
-                ss_tables.insert(Arc::new(ss_table));
+            let is_ingest_temp = is_ingest_temp_file(&entry_path);
+            if is_ss_table_file(&entry_path) || is_ingest_temp {
+                // a file staged by an ingestion is listed under its final name
+                let mut ss_table_path = match is_ingest_temp {
+                    true => ingested_ss_table_path(&entry_path),
+                    false => entry_path.clone(),
+                };
+                let file_name = ss_table_path
+                    .file_name()
+                    .unwrap()
+                    .to_string_lossy()
+                    .to_string();
+                let listed = match &mut unopened_files {
+                    Some(files) => files.remove(&file_name),
+                    None => !is_ingest_temp,
+                };
+                if listed {
+                    // the ingestion stopped once durable, its renames are completed
+                    if is_ingest_temp {
+                        match mem_table_options.read_only {
+                            true => ss_table_path = entry_path.clone(),
+                            false => fs::rename(&entry_path, &ss_table_path)?,
+                        }
+                    }
+                    let ss_table = match opened_ss_tables.get(&file_name) {
+                        Some(ss_table) => ss_table.clone(),
+                        None => Arc::new(SSTable::open_with_caches(
+                            ss_table_path,
+                            ss_table_caches.clone(),
+                        )?),
+                    };
+                    ss_tables.insert(ss_table);
+                } else if !mem_table_options.read_only {
+                    // the inputs of a published compaction, the output of an unpublished
+                    // one or a copy staged by an ingestion that did not complete
+                    fs::remove_file(&entry_path)?;
+                }
             }
 
             if is_mem_table_file(&entry_path) {
@@ -130,94 +524,228 @@ impl LiteDb {
                     .to_string_lossy()
                     .parse()
                     .expect("Expected a valid wal file id.");
-                let mem_table = MemTable::open(path.clone(), id)?;
+                let (mem_table, report) =
+                    MemTable::recover(path.to_path_buf(), id, mem_table_options)?;
+                wal_recovery_report.merge(&report);
                 mem_tables.insert(Arc::new(mem_table));
             }
         }
 
+        if let Some(file_name) = unopened_files.and_then(|files| files.into_iter().next()) {
+            return Err(io::Error::new(
+                ErrorKind::NotFound,
+                format!("The ss_table `{file_name}` listed in the manifest is missing."),
+            )
+            .into());
+        }
+
         // Create default mem_table if none is found
         if mem_tables.is_empty() {
-            mem_tables.insert(Arc::new(MemTable::open(path.clone(), 0)?));
+            mem_tables.insert(Arc::new(MemTable::open(
+                path.to_path_buf(),
+                0,
+                mem_table_options,
+            )?));
         }
 
-        let ss_tables = Arc::new(ss_tables);
-        let mem_controller = MemTableController::start(
-            mem_tables.clone(),
-            ss_tables.clone(),
-            atomic_operation_executor.clone(),
-            options.bloom_filter_size_bytes,
-            options.bloom_filter_item_count,
-            options.sparse_index_range_size,
-            &options.mem_table_controller_policy,
-        )?;
-        let compactor = Compactor::start(
-            ss_tables.clone(),
-            atomic_operation_executor.clone(),
-            &options.compactor_policy,
-        )?;
-        Ok(Self {
-            options,
+        Ok(RecoveredTables {
             mem_tables,
             ss_tables,
-            // atomic_operation_executor,
-            mem_controller,
-            compactor,
-            path,
+            wal_recovery_report,
         })
     }
 
+    /// Opens a read-only instance serving the database another process writes to.
+    ///
+    /// The instance reads the tables of `primary_dir` as of the last
+    /// `try_catch_up_with_primary`, `secondary_dir` only holds its lock.
+    pub fn open_as_secondary<P: AsRef<Path>, S: AsRef<Path>>(
+        primary_dir: P,
+        secondary_dir: S,
+        options: LiteDbOptions,
+    ) -> LiteDbResult<Self> {
+        let path = PathBuf::from(secondary_dir.as_ref());
+        fs::create_dir_all(&path)?;
+        let dir_lock = DirLock::acquire(&path)?;
+        // the primary deletes the files of compacted tables, they stay open
+        let ss_table_caches = SSTableCaches {
+            block_cache: options.block_cache.clone(),
+            table_cache: None,
+        };
+        let background_error = BackgroundError::default();
+        let db = Self {
+            write_controller: WriteController::new(&options, background_error.clone())?,
+            options,
+            mem_tables: Arc::new(SkipSet::new()),
+            ss_tables: Arc::new(SkipSet::new()),
+            atomic_operation_executor: Arc::new(AtomicOperationExecutor::new()),
+            manifest: Arc::new(Manifest::new(path.clone())),
+            mem_controller: None,
+            compactor: None,
+            wal_recovery_report: WalRecoveryReport::default(),
+            path,
+            primary_path: Some(PathBuf::from(primary_dir.as_ref())),
+            dir_lock,
+            ss_table_caches,
+            background_error,
+        };
+        db.try_catch_up_with_primary()?;
+        Ok(db)
+    }
+
+    /// Opens the ss_tables the primary added, drops the ones it removed and
+    /// replays the wals it wrote so far.
+    ///
+    /// Reads started before the catch-up keep their view of the tables.
+    pub fn try_catch_up_with_primary(&self) -> LiteDbResult<()> {
+        let primary_path = self
+            .primary_path
+            .as_ref()
+            .ok_or(LiteDbError::NotSecondary)?;
+        let mem_table_options = MemTableOptions {
+            // the primary may be in the middle of appending the last record
+            wal_recovery_mode: WalRecoveryMode::PointInTime,
+            memtable_factory: self.options.memtable_factory,
+            read_only: true,
+            ..MemTableOptions::default()
+        };
+
+        let opened_ss_tables: BTreeMap<_, _> = self
+            .ss_tables
+            .iter()
+            .map(|entry| (ss_table_file_name(entry.value()), entry.value().clone()))
+            .collect();
+
+        let mut attempt = 0;
+        let recovered_tables = loop {
+            attempt += 1;
+            match Self::recover_primary_tables(
+                primary_path,
+                &mem_table_options,
+                &self.ss_table_caches,
+                &opened_ss_tables,
+            ) {
+                Ok(recovered_tables) => break recovered_tables,
+                // a flush or a compaction of the primary replaced files along the way
+                Err(err) if is_primary_change(&err) && attempt < MAX_CATCH_UP_ATTEMPTS => (),
+                Err(err) => return Err(err),
+            }
+        };
+        let recovered_ss_table_files: BTreeSet<_> = recovered_tables
+            .ss_tables
+            .iter()
+            .map(|entry| ss_table_file_name(entry.value()))
+            .collect();
+        self.atomic_operation_executor.perform(|| {
+            while self.mem_tables.pop_front().is_some() {}
+            for mem_table in recovered_tables.mem_tables {
+                self.mem_tables.insert(mem_table);
+            }
+            // removed first, a compaction output shares its id with one of its inputs
+            for (file_name, ss_table) in &opened_ss_tables {
+                if !recovered_ss_table_files.contains(file_name) {
+                    self.ss_tables.remove(ss_table);
+                }
+            }
+            for ss_table in recovered_tables.ss_tables {
+                if !opened_ss_tables.contains_key(&ss_table_file_name(&ss_table)) {
+                    self.ss_tables.insert(ss_table);
+                }
+            }
+        });
+        Ok(())
+    }
+
+    /// Recovers the tables of the primary, failing if its files changed meanwhile.
+    fn recover_primary_tables(
+        primary_path: &Path,
+        mem_table_options: &MemTableOptions,
+        ss_table_caches: &SSTableCaches,
+        opened_ss_tables: &BTreeMap<String, Arc<SSTable>>,
+    ) -> LiteDbResult<RecoveredTables> {
+        let files = list_table_files(primary_path)?;
+        let recovered_tables = Self::recover_tables(
+            primary_path,
+            mem_table_options,
+            ss_table_caches,
+            opened_ss_tables,
+        )?;
+        if list_table_files(primary_path)? != files {
+            return Err(LiteDbError::PrimaryChanged);
+        }
+        Ok(recovered_tables)
+    }
+
     pub fn open_with_default<P: AsRef<Path>>(dir: P) -> LiteDbResult<Self> {
         Self::open(dir, LiteDbOptions::default())
     }
 
     pub fn set(&self, key: RefKey, value: RefValue) -> LiteDbResult<()> {
+        self.set_opt(key, value, &WriteOptions::default())
+    }
+
+    pub fn set_opt(
+        &self,
+        key: RefKey,
+        value: RefValue,
+        write_options: &WriteOptions,
+    ) -> LiteDbResult<()> {
+        let mem_controller = self.mem_controller()?;
+        self.wait_for_write_stall(write_options)?;
+        let _pin_guard = mem_controller.pin_current_mem_table();
         self.mem_tables
-            .front()
+            .back()
             .expect("Expected a valid mem_table")
-            .set(key, value)
+            .set(key, value, write_options)
     }
 
     pub fn get(&self, key: RefKey) -> LiteDbResult<Option<Value>> {
-        if let Ok(Some(value)) = self
-            .mem_tables
-            .front()
-            .expect("Expected a valid mem_table")
-            .get(key)
-        {
-            if value == TOMBSTONE {
-                return Ok(None);
-            }
-            return Ok(Some(value));
-        }
+        self.get_opt(key, &ReadOptions::default())
+    }
 
-        let ss_tables = {
-            let owned_key = key.to_owned();
-            self.ss_tables
-                .iter()
-                .map(|entry| entry.value().clone())
-                .filter(|ss_table| ss_table.potentially_contains_key(&owned_key))
-                .collect::<Vec<_>>()
+    pub fn get_opt(&self, key: RefKey, read_options: &ReadOptions) -> LiteDbResult<Option<Value>> {
+        let (mem_tables, ss_tables) = self.tables(read_options);
+
+        // from newest to oldest, the first hit is the latest value
+        let value = match mem_tables.iter().rev().find_map(|mem_table| {
+            mem_table
+                .get(key, read_options.sequence_number())
+                .transpose()
+        }) {
+            Some(value) => Some(value?),
+            None => ss_tables
+                .into_iter()
+                .rev()
+                .find_map(|ss_table| SSTable::get(ss_table, key, read_options).transpose())
+                .transpose()?,
         };
-        for ss_table in ss_tables {
-            if let Ok(Some(value)) = SSTable::get(ss_table, key) {
-                if value == TOMBSTONE {
-                    return Ok(None);
-                }
-                return Ok(Some(value));
-            }
-        }
-        Ok(None)
+        Ok(value.filter(|value| value != &TOMBSTONE))
     }
 
     pub fn delete(&self, key: RefKey) -> LiteDbResult<()> {
         self.set(key, &TOMBSTONE)
     }
 
+    pub fn delete_opt(&self, key: RefKey, write_options: &WriteOptions) -> LiteDbResult<()> {
+        self.set_opt(key, &TOMBSTONE, write_options)
+    }
+
     pub fn apply_batch(&self, operations: BatchOperations) -> LiteDbResult<()> {
+        self.apply_batch_opt(operations, &WriteOptions::default())
+    }
+
+    pub fn apply_batch_opt(
+        &self,
+        operations: BatchOperations,
+        write_options: &WriteOptions,
+    ) -> LiteDbResult<()> {
+        let mem_controller = self.mem_controller()?;
+        self.wait_for_write_stall(write_options)?;
+        let _pin_guard = mem_controller.pin_current_mem_table();
         self.mem_tables
-            .front()
+            .back()
             .expect("Expected a valid mem_table")
-            .apply_batch(operations)
+            .apply_batch(operations, write_options)
     }
 
     pub fn scan(
@@ -225,21 +753,118 @@ impl LiteDb {
         from: &Option<Key>,
         to: &Option<Key>,
     ) -> LiteDbResult<impl Iterator<Item = LiteDbResult<(Key, Value)>> + '_> {
-        let mut iterators = Vec::with_capacity(self.mem_tables.len() + self.ss_tables.len());
+        self.scan_opt(&ReadOptions {
+            iterate_lower_bound: from.clone(),
+            iterate_upper_bound: to.clone(),
+            ..ReadOptions::default()
+        })
+    }
+
+    /// Scans `[iterate_lower_bound, iterate_upper_bound)`.
+    pub fn scan_opt(
+        &self,
+        read_options: &ReadOptions,
+    ) -> LiteDbResult<impl Iterator<Item = LiteDbResult<(Key, Value)>> + '_> {
+        let (from, to) = (
+            &read_options.iterate_lower_bound,
+            &read_options.iterate_upper_bound,
+        );
+        let (mem_tables, ss_tables) = self.tables(read_options);
+        let mut iterators = Vec::with_capacity(mem_tables.len() + ss_tables.len());
 
-        // add ss_table from oldest to newest
-        for ss_table in self.ss_tables.iter() {
-            iterators.push(ss_table.scan(from, to));
+        // add ss_table from oldest to newest, skipping those out of range
+        for ss_table in ss_tables {
+            if ss_table.potentially_contains_range(from, to)? {
+                iterators.push(ss_table.scan_with_options(from, to, read_options));
+            }
         }
 
         // add mem_table from oldest to newest
-        for mem_table in self.mem_tables.iter() {
-            iterators.push(mem_table.scan(from, to));
+        for mem_table in mem_tables {
+            iterators.push(mem_table.scan_with_options(from, to, read_options));
         }
 
         CombineIterator::try_new(iterators)
     }
 
+    /// Takes a snapshot to read the current state of the database later on.
+    pub fn snapshot(&self) -> Snapshot {
+        // with writes held back, every entry numbered so far is in its mem_table
+        let _writes_guard = self
+            .mem_controller
+            .as_ref()
+            .map(|mem_controller| mem_controller.hold_writes());
+        let (mem_tables, ss_tables) = self.tables(&ReadOptions::default());
+        let sequence_number = mem_tables
+            .last()
+            .map_or(u64::MAX, |mem_table| mem_table.last_entry_sequence_number());
+        Snapshot {
+            mem_tables,
+            ss_tables,
+            sequence_number,
+        }
+    }
+
+    /// Returns the mem_tables and ss_tables to read, both from oldest to newest.
+    fn tables(&self, read_options: &ReadOptions) -> (Vec<Arc<MemTable>>, Vec<Arc<SSTable>>) {
+        if let Some(snapshot) = &read_options.snapshot {
+            return (snapshot.mem_tables.clone(), snapshot.ss_tables.clone());
+        }
+        // a flush or a compaction in flight would otherwise hide its data from the read
+        self.atomic_operation_executor.perform(|| {
+            let mem_tables = self
+                .mem_tables
+                .iter()
+                .map(|entry| entry.value().clone())
+                .collect();
+            let ss_tables = self
+                .ss_tables
+                .iter()
+                .map(|entry| entry.value().clone())
+                .collect();
+            (mem_tables, ss_tables)
+        })
+    }
+
+    /// Holds the write back while flushes or compactions fall behind.
+    fn wait_for_write_stall(&self, write_options: &WriteOptions) -> LiteDbResult<()> {
+        self.write_controller.wait(
+            &self.mem_tables,
+            &self.ss_tables,
+            write_options.no_slowdown,
+            || {
+                if let Some(compactor) = &self.compactor {
+                    compactor.trigger();
+                }
+            },
+        )
+    }
+
+    /// Returns the controller rotating the mem_tables written to, if writes are allowed.
+    fn mem_controller(&self) -> LiteDbResult<&MemTableController> {
+        self.mem_controller.as_ref().ok_or(LiteDbError::ReadOnly)
+    }
+
+    /// Returns an unpositioned bidirectional cursor over `[from, to)`.
+    pub fn cursor(&self, from: &Option<Key>, to: &Option<Key>) -> LiteDbResult<impl Cursor + '_> {
+        let (mem_tables, ss_tables) = self.tables(&ReadOptions::default());
+        let mut cursors = Vec::with_capacity(mem_tables.len() + ss_tables.len());
+
+        // add ss_table from oldest to newest, skipping those out of range
+        for ss_table in ss_tables {
+            if ss_table.potentially_contains_range(from, to)? {
+                cursors.push(ss_table.scan(from, to));
+            }
+        }
+
+        // add mem_table from oldest to newest
+        for mem_table in mem_tables {
+            cursors.push(mem_table.scan(from, to));
+        }
+
+        Ok(CombineCursor::new(cursors))
+    }
+
     pub fn options(&self) -> &LiteDbOptions {
         &self.options
     }
@@ -248,9 +873,263 @@ impl LiteDb {
         &self.path
     }
 
+    /// Returns the memory held by the mem_tables.
+    pub fn memory_usage(&self) -> MemoryUsage {
+        let (mem_tables, _) = self.tables(&ReadOptions::default());
+        MemoryUsage {
+            current_mem_table: mem_tables
+                .last()
+                .map_or(0, |mem_table| mem_table.size_bytes()),
+            mem_tables_total: mem_tables
+                .iter()
+                .map(|mem_table| mem_table.size_bytes())
+                .sum(),
+        }
+    }
+
+    /// Flushes the mem_tables written so far to ss_tables and waits for it.
+    pub fn flush(&self) -> LiteDbResult<()> {
+        self.mem_controller()?;
+        let current_mem_table = self
+            .mem_tables
+            .back()
+            .expect("Expected a valid mem_table")
+            .value()
+            .clone();
+        // an empty current mem_table is not rotated, only the ones before it are flushed
+        let first_unflushed_id = match current_mem_table.num_entries() {
+            0 => current_mem_table.id(),
+            _ => current_mem_table.id() + 1,
+        };
+        current_mem_table.request_flush();
+        self.wait_for_flush(first_unflushed_id)
+    }
+
+    /// Waits until the mem_tables older than `first_unflushed_id` are flushed,
+    /// fails if a flush fails meanwhile.
+    fn wait_for_flush(&self, first_unflushed_id: u64) -> LiteDbResult<()> {
+        self.background_error.wait_until(|| {
+            !matches!(
+                self.mem_tables.front(),
+                Some(entry) if entry.value().id() < first_unflushed_id
+            )
+        })
+    }
+
+    /// Creates a copy of the database in `target_dir` that `LiteDb::open` can use.
+    ///
+    /// The mem_tables are flushed first, then the tables are pinned: their ss_tables
+    /// are hard-linked, the wals are copied up to their last complete record and a
+    /// manifest listing the ss_tables is written. Compactions running meanwhile only
+    /// delete the files of their inputs once the links are made.
+    pub fn checkpoint<P: AsRef<Path>>(&self, target_dir: P) -> LiteDbResult<()> {
+        let target_path = target_dir.as_ref();
+        if target_path.exists() {
+            return Err(io::Error::new(
+                ErrorKind::AlreadyExists,
+                format!(
+                    "Checkpoint directory {} already exists",
+                    target_path.display()
+                ),
+            )
+            .into());
+        }
+        // read-only instances have nothing to flush, their wals are copied instead
+        if self.mem_controller.is_some() {
+            self.flush()?;
+        }
+
+        // the checkpoint only shows up once complete, only its own temp dir is removed
+        let temp_path = create_temp_dir_for(target_path)?;
+        let result = self
+            .write_checkpoint(&temp_path)
+            .and_then(|_| Ok(fs::rename(&temp_path, target_path)?));
+        if result.is_err() {
+            let _ = fs::remove_dir_all(&temp_path);
+        }
+        result
+    }
+
+    /// Links & copies the files of the tables into `temp_path`.
+    fn write_checkpoint(&self, temp_path: &Path) -> LiteDbResult<()> {
+        // the tables are pinned under the lock, their files are linked & copied after it
+        let deletion_guard = self.manifest.hold_deletions();
+        let (mem_tables, ss_tables) = self.tables(&ReadOptions::default());
+        let mut wal_ends = Vec::with_capacity(mem_tables.len());
+        for mem_table in &mem_tables {
+            wal_ends.extend(mem_table.wal_end()?);
+        }
+        let mut ss_table_files = BTreeSet::new();
+        let mut blob_files = BTreeSet::new();
+        for ss_table in &ss_tables {
+            link_or_copy_file(ss_table.path(), temp_path)?;
+            ss_table_files.insert(ss_table_file_name(ss_table));
+            let dir = ss_table
+                .path()
+                .parent()
+                .expect("Expected a database directory");
+            for file_id in ss_table.blob_references().keys() {
+                blob_files.insert(blob_file_path(dir, *file_id));
+            }
+        }
+        for blob_file in &blob_files {
+            link_or_copy_file(blob_file, temp_path)?;
+        }
+        // the current wal keeps growing, records written after the pin are left out
+        for (wal_file_path, end_offset) in &wal_ends {
+            copy_file_prefix(wal_file_path, temp_path, *end_offset)?;
+        }
+        drop(deletion_guard);
+        write_manifest(temp_path, &ss_table_files)
+    }
+
+    /// Adds ss_table files written by a `SstFileWriter` to the database.
+    ///
+    /// Every file is checked first. The files then get the ids following the current
+    /// mem_table, in the order of `paths` so that later files take precedence, and
+    /// are published all at once. The older mem_tables are flushed beforehand, as
+    /// their entries would otherwise hide the ingested ones, and compactions expect
+    /// every table to be newer than the ones below it.
+    ///
+    /// The database works on renumbered copies of the files, staged under a temp
+    /// name until a manifest lists them. A failed ingestion leaves no file behind
+    /// and the files given are only removed, with `move_files`, once it is durable.
+    pub fn ingest_external_files<P: AsRef<Path>>(
+        &self,
+        paths: &[P],
+        ingest_options: &IngestOptions,
+    ) -> LiteDbResult<()> {
+        let mem_controller = self.mem_controller()?;
+        if paths.is_empty() {
+            return Ok(());
+        }
+        let mut external_ss_tables = Vec::with_capacity(paths.len());
+        let mut key_ranges = Vec::with_capacity(paths.len());
+        for path in paths {
+            let ss_table = SSTable::open(path.as_ref().to_path_buf())?;
+            ss_table.verify()?;
+            key_ranges.push((
+                Some(ss_table.first_key().to_vec()),
+                Some(key_successor(ss_table.last_key())),
+            ));
+            external_ss_tables.push(ss_table);
+        }
+
+        // The overlap is checked with the writes held back, so that none lands in
+        // the range meanwhile. From the rotation on, writes go to a mem_table newer
+        // than the ingested files.
+        let first_id = mem_controller.rotate_reserving_ids(paths.len() as u64, || {
+            if ingest_options.allow_global_seqno {
+                return Ok(());
+            }
+            let (mem_tables, ss_tables) = self.tables(&ReadOptions::default());
+            for (path, (from, to)) in paths.iter().zip(&key_ranges) {
+                if any_holds_key_in(&mem_tables, from, to)?
+                    || any_holds_key_in(&ss_tables, from, to)?
+                {
+                    return Err(LiteDbError::IngestionOverlap(path.as_ref().to_path_buf()));
+                }
+            }
+            Ok(())
+        })?;
+        let first_unflushed_id = first_id + paths.len() as u64;
+
+        // the staged files are removed if the ingestion does not complete
+        let mut staged_files = Vec::with_capacity(paths.len());
+        let mut listed_in_manifest = false;
+        let mut stage = || -> LiteDbResult<Vec<Arc<SSTable>>> {
+            for (id, ss_table) in (first_id..).zip(&external_ss_tables) {
+                let staged_file = StagedFile::new(&self.path, id);
+                ss_table.write_renumbered(id, &staged_file.temp_path)?;
+                staged_files.push(staged_file);
+            }
+            self.wait_for_flush(first_unflushed_id)?;
+
+            // once the manifest lists the files, recovery renames the ones left staged
+            let pending_files = staged_files
+                .iter()
+                .map(|staged_file| {
+                    staged_file
+                        .path
+                        .file_name()
+                        .expect("Expected a file name")
+                        .to_string_lossy()
+                        .to_string()
+                })
+                .collect();
+            listed_in_manifest = true;
+            self.manifest.persist_with(
+                &self.ss_tables,
+                &self.atomic_operation_executor,
+                &pending_files,
+            )?;
+            let mut ingested_ss_tables = Vec::with_capacity(staged_files.len());
+            for staged_file in &staged_files {
+                fs::rename(&staged_file.temp_path, &staged_file.path)?;
+                let ss_table = SSTable::open_with_caches(
+                    staged_file.path.clone(),
+                    self.ss_table_caches.clone(),
+                )?;
+                ingested_ss_tables.push(Arc::new(ss_table));
+            }
+            Ok(ingested_ss_tables)
+        };
+        let ingested_ss_tables = match stage() {
+            Ok(ingested_ss_tables) => ingested_ss_tables,
+            Err(err) => {
+                // a manifest listing the files is replaced first, otherwise they are kept
+                // and recovery completes the ingestion
+                if !listed_in_manifest
+                    || self
+                        .manifest
+                        .persist(&self.ss_tables, &self.atomic_operation_executor)
+                        .is_ok()
+                {
+                    for staged_file in &staged_files {
+                        let _ = staged_file.discard();
+                    }
+                }
+                return Err(err);
+            }
+        };
+        self.atomic_operation_executor.perform(|| {
+            for ss_table in ingested_ss_tables {
+                self.ss_tables.insert(ss_table);
+            }
+        });
+        self.manifest
+            .persist(&self.ss_tables, &self.atomic_operation_executor)?;
+        if ingest_options.move_files {
+            for path in paths {
+                fs::remove_file(path)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the sequence number of the last write logged to the wal,
+    /// a point-in-time restore can stop right after it.
+    pub fn latest_sequence_number(&self) -> u64 {
+        self.mem_tables
+            .back()
+            .expect("Expected a valid mem_table")
+            .value()
+            .last_sequence_number()
+    }
+
+    /// Returns what replaying the wals on open had to drop.
+    pub fn wal_recovery_report(&self) -> &WalRecoveryReport {
+        &self.wal_recovery_report
+    }
+
     fn close(&mut self) {
-        self.mem_controller.stop();
-        self.compactor.stop();
+        if let Some(mem_controller) = &mut self.mem_controller {
+            mem_controller.stop();
+        }
+        if let Some(compactor) = &mut self.compactor {
+            compactor.stop();
+        }
+        let _ = self.dir_lock.release();
     }
 }
 
@@ -262,9 +1141,28 @@ impl Drop for LiteDb {
 
 #[cfg(test)]
 mod tests {
+    use std::{
+        collections::BTreeSet,
+        fs,
+        path::PathBuf,
+        sync::{
+            atomic::{AtomicBool, AtomicUsize, Ordering},
+            mpsc, Arc,
+        },
+        thread,
+        time::Duration,
+    };
+
     use tempfile::tempdir;
 
-    use crate::{batching::BatchOperations, error::LiteDbResult, options::LiteDbOptions, LiteDb};
+    use crate::{
+        batching::BatchOperations,
+        error::{LiteDbError, LiteDbResult},
+        manifest::{read_manifest, ss_table_file_name, write_manifest},
+        options::{IngestOptions, LiteDbOptions},
+        ss_table::SSTable,
+        Cursor, LiteDb, MemoryUsage, ReadOptions, SstFileWriter, WriteOptions,
+    };
 
     #[test]
     fn test_lite_db() -> LiteDbResult<()> {
@@ -288,6 +1186,7 @@ mod tests {
 
         {
             let db = LiteDb::open(&db_path, LiteDbOptions::for_test()).unwrap();
+            assert!(db.wal_recovery_report().is_clean());
             for i in 0..=1000 {
                 let k = format!("k_{:01$}", i, 3);
                 let v = db.get(k.as_bytes())?;
@@ -338,4 +1237,771 @@ mod tests {
         }
         Ok(())
     }
+
+    #[test]
+    fn test_lite_db_reverse_cursor() -> LiteDbResult<()> {
+        let temp_dir = tempdir()?;
+        let db_path = temp_dir.path().join("data");
+        let db = LiteDb::open(&db_path, LiteDbOptions::for_test()).unwrap();
+        for i in 0..100 {
+            let k = format!("k_{:01$}", i, 3);
+            let v = format!("v_{:01$}", i, 3);
+            db.set(k.as_bytes(), v.as_bytes())?;
+        }
+
+        // latest 5 entries before k_050
+        let mut cursor = db.cursor(&Some(b"k_010".to_vec()), &Some(b"k_050".to_vec()))?;
+        cursor.seek_to_last()?;
+        let mut keys = vec![];
+        while cursor.valid() && keys.len() < 5 {
+            keys.push(String::from_utf8(cursor.key().to_vec()).unwrap());
+            cursor.prev()?;
+        }
+        assert_eq!(keys, vec!["k_049", "k_048", "k_047", "k_046", "k_045"]);
+
+        cursor.seek_for_prev(b"k_011")?;
+        assert_eq!(cursor.key(), b"k_011");
+        cursor.prev()?;
+        assert_eq!(cursor.value(), b"v_010");
+        cursor.prev()?;
+        assert!(!cursor.valid());
+        Ok(())
+    }
+
+    #[test]
+    fn test_lite_db_read_write_options() -> LiteDbResult<()> {
+        let temp_dir = tempdir()?;
+        let db_path = temp_dir.path().join("data");
+        {
+            let db = LiteDb::open(&db_path, LiteDbOptions::for_test()).unwrap();
+            let sync = WriteOptions {
+                sync: true,
+                ..WriteOptions::default()
+            };
+            for i in 0..10 {
+                let k = format!("k_{:01$}", i, 3);
+                db.set_opt(k.as_bytes(), b"v1", &sync)?;
+            }
+
+            let snapshot = db.snapshot();
+            db.set(b"k_000", b"v2")?;
+            db.delete(b"k_001")?;
+            let no_wal = WriteOptions {
+                disable_wal: true,
+                ..WriteOptions::default()
+            };
+            db.set_opt(b"k_100", b"v1", &no_wal)?;
+
+            let read_options = ReadOptions {
+                snapshot: Some(snapshot),
+                iterate_lower_bound: Some(b"k_000".to_vec()),
+                iterate_upper_bound: Some(b"k_002".to_vec()),
+                ..ReadOptions::default()
+            };
+            assert_eq!(db.get_opt(b"k_000", &read_options)?, Some(b"v1".to_vec()));
+            assert_eq!(db.get_opt(b"k_100", &read_options)?, None);
+            let entries = db
+                .scan_opt(&read_options)?
+                .collect::<LiteDbResult<Vec<_>>>()?;
+            assert_eq!(
+                entries,
+                vec![
+                    (b"k_000".to_vec(), b"v1".to_vec()),
+                    (b"k_001".to_vec(), b"v1".to_vec())
+                ]
+            );
+
+            assert_eq!(db.get(b"k_000")?, Some(b"v2".to_vec()));
+            assert_eq!(db.get(b"k_001")?, None);
+            assert_eq!(db.get(b"k_100")?, Some(b"v1".to_vec()));
+        }
+
+        // the write that skipped the wal is lost
+        let db = LiteDb::open(&db_path, LiteDbOptions::for_test()).unwrap();
+        assert_eq!(db.get(b"k_000")?, Some(b"v2".to_vec()));
+        assert_eq!(db.get(b"k_100")?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_lite_db_get_newest_first() -> LiteDbResult<()> {
+        let temp_dir = tempdir()?;
+        let db = LiteDb::open(temp_dir.path(), LiteDbOptions::for_test())?;
+        // every round is flushed to its own ss_table, the last one only partly
+        for round in 0..3 {
+            for i in 0..200 {
+                let k = format!("k_{:01$}", i, 3);
+                db.set(k.as_bytes(), format!("v_{round}").as_bytes())?;
+            }
+        }
+        db.delete(b"k_000")?;
+
+        assert_eq!(db.get(b"k_000")?, None);
+        for i in 1..200 {
+            let k = format!("k_{:01$}", i, 3);
+            assert_eq!(db.get(k.as_bytes())?, Some(b"v_2".to_vec()));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_lite_db_scan_skips_tables_out_of_range() -> LiteDbResult<()> {
+        let temp_dir = tempdir()?;
+        let db_path = temp_dir.path().join("data");
+        {
+            let db = LiteDb::open(&db_path, LiteDbOptions::for_test())?;
+            // the first ss_table spans the scanned range without holding any key of it
+            db.set(b"a", b"skipped")?;
+            db.set(b"z", b"skipped")?;
+            db.flush()?;
+            db.set(b"m_1", b"v")?;
+            db.flush()?;
+        }
+
+        // reading the first ss_table now fails
+        for entry_result in fs::read_dir(&db_path)? {
+            let path = entry_result?.path();
+            let mut bytes = fs::read(&path)?;
+            let positions = (0..bytes.len().saturating_sub(6))
+                .filter(|pos| &bytes[*pos..*pos + 7] == b"skipped")
+                .collect::<Vec<_>>();
+            for pos in &positions {
+                bytes[*pos] = b'X';
+            }
+            if !positions.is_empty() {
+                fs::write(&path, bytes)?;
+            }
+        }
+
+        let db = LiteDb::open(&db_path, LiteDbOptions::for_test())?;
+        let entries = db
+            .scan(&Some(b"m".to_vec()), &Some(b"n".to_vec()))?
+            .collect::<LiteDbResult<Vec<_>>>()?;
+        assert_eq!(entries, vec![(b"m_1".to_vec(), b"v".to_vec())]);
+        let result = db
+            .scan(&None, &None)
+            .and_then(|iter| iter.collect::<LiteDbResult<Vec<_>>>());
+        assert!(matches!(result, Err(LiteDbError::CorruptedData)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_lite_db_reads_during_flush() -> LiteDbResult<()> {
+        let temp_dir = tempdir()?;
+        let db_path = temp_dir.path().join("data");
+        let db = LiteDb::open(
+            &db_path,
+            LiteDbOptions {
+                // keep compaction running alongside the flushes
+                level0_slowdown_writes_trigger: 4,
+                ..LiteDbOptions::for_test()
+            },
+        )?;
+        let num_keys = 300;
+        let num_rounds = 10;
+        // number of rounds fully written
+        let completed_rounds = AtomicUsize::new(0);
+
+        thread::scope(|scope| {
+            scope.spawn(|| {
+                for round in 0..num_rounds {
+                    for i in 0..num_keys {
+                        let k = format!("k_{:01$}", i, 3);
+                        let v = format!("v_{round}");
+                        db.set(k.as_bytes(), v.as_bytes()).unwrap();
+                    }
+                    completed_rounds.store(round + 1, Ordering::SeqCst);
+                }
+            });
+
+            for _ in 0..2 {
+                scope.spawn(|| {
+                    while completed_rounds.load(Ordering::SeqCst) < num_rounds {
+                        for i in 0..num_keys {
+                            let min_round = completed_rounds.load(Ordering::SeqCst);
+                            let k = format!("k_{:01$}", i, 3);
+                            let v = db.get(k.as_bytes()).unwrap();
+                            if min_round == 0 {
+                                continue;
+                            }
+                            // never older than the last round completed before the read
+                            let v = String::from_utf8(v.expect("Expected a value.")).unwrap();
+                            let round: usize = v.trim_start_matches("v_").parse().unwrap();
+                            assert!(round + 1 >= min_round, "{k} read at round {round}");
+                        }
+                    }
+                });
+            }
+        });
+
+        for i in 0..num_keys {
+            let k = format!("k_{:01$}", i, 3);
+            let expected_v = format!("v_{}", num_rounds - 1).as_bytes().to_vec();
+            assert_eq!(db.get(k.as_bytes())?, Some(expected_v));
+        }
+        assert_eq!(db.scan(&None, &None)?.count(), num_keys);
+        Ok(())
+    }
+
+    #[test]
+    fn test_lite_db_memory_usage() -> LiteDbResult<()> {
+        let temp_dir = tempdir()?;
+        let db_path = temp_dir.path().join("data");
+        let memory_usage = {
+            let db = LiteDb::open(&db_path, LiteDbOptions::for_test())?;
+            assert_eq!(db.memory_usage(), MemoryUsage::default());
+            db.set(b"k_1", b"v_1")?;
+            let memory_usage = db.memory_usage();
+            assert!(memory_usage.current_mem_table > 0);
+            assert_eq!(
+                memory_usage.current_mem_table,
+                memory_usage.mem_tables_total
+            );
+
+            // the overwritten version is held until the mem_table is flushed
+            db.set(b"k_1", b"v_2")?;
+            let overwritten_memory_usage = db.memory_usage();
+            assert_eq!(
+                overwritten_memory_usage.current_mem_table,
+                2 * memory_usage.current_mem_table
+            );
+            overwritten_memory_usage
+        };
+
+        let db = LiteDb::open(&db_path, LiteDbOptions::for_test())?;
+        assert_eq!(db.memory_usage(), memory_usage);
+        Ok(())
+    }
+
+    #[test]
+    fn test_lite_db_locked() -> LiteDbResult<()> {
+        let temp_dir = tempdir()?;
+        let db_path = temp_dir.path().join("data");
+        let db = LiteDb::open(&db_path, LiteDbOptions::for_test())?;
+        let result = LiteDb::open(&db_path, LiteDbOptions::for_test());
+        assert!(matches!(
+            result,
+            Err(LiteDbError::Locked { pid: Some(pid), .. }) if pid == std::process::id()
+        ));
+
+        // closing the database releases the lock
+        drop(db);
+        LiteDb::open(&db_path, LiteDbOptions::for_test())?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_lite_db_read_only() -> LiteDbResult<()> {
+        let temp_dir = tempdir()?;
+        let db_path = temp_dir.path().join("data");
+        {
+            let db = LiteDb::open(&db_path, LiteDbOptions::for_test())?;
+            for i in 0..500 {
+                let k = format!("k_{:01$}", i, 3);
+                db.set(k.as_bytes(), b"v")?;
+            }
+        }
+        let list_files = || -> LiteDbResult<Vec<(PathBuf, u64)>> {
+            let mut files = fs::read_dir(&db_path)?
+                .map(|entry| {
+                    let path = entry?.path();
+                    let len = fs::metadata(&path)?.len();
+                    Ok((path, len))
+                })
+                .collect::<LiteDbResult<Vec<_>>>()?;
+            files.sort();
+            Ok(files)
+        };
+        let files = list_files()?;
+
+        let db_1 = LiteDb::open_read_only(&db_path, LiteDbOptions::for_test())?;
+        let db_2 = LiteDb::open_read_only(&db_path, LiteDbOptions::for_test())?;
+        for i in 0..500 {
+            let k = format!("k_{:01$}", i, 3);
+            assert_eq!(db_1.get(k.as_bytes())?, Some(b"v".to_vec()));
+        }
+        assert!(matches!(db_2.set(b"k", b"v"), Err(LiteDbError::ReadOnly)));
+        assert!(matches!(db_2.delete(b"k"), Err(LiteDbError::ReadOnly)));
+        assert!(matches!(
+            LiteDb::open(&db_path, LiteDbOptions::for_test()),
+            Err(LiteDbError::Locked { pid: None, .. })
+        ));
+
+        drop(db_1);
+        drop(db_2);
+        assert_eq!(list_files()?, files);
+        LiteDb::open(&db_path, LiteDbOptions::for_test())?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_lite_db_secondary() -> LiteDbResult<()> {
+        let temp_dir = tempdir()?;
+        let primary_path = temp_dir.path().join("primary");
+        let secondary_path = temp_dir.path().join("secondary");
+        let primary = LiteDb::open(&primary_path, LiteDbOptions::for_test())?;
+        for i in 0..500 {
+            let k = format!("k_{:01$}", i, 3);
+            primary.set(k.as_bytes(), b"v")?;
+        }
+
+        let secondary =
+            LiteDb::open_as_secondary(&primary_path, &secondary_path, LiteDbOptions::for_test())?;
+        for i in 0..500 {
+            let k = format!("k_{:01$}", i, 3);
+            assert_eq!(secondary.get(k.as_bytes())?, Some(b"v".to_vec()));
+        }
+        assert!(matches!(
+            secondary.set(b"k", b"v"),
+            Err(LiteDbError::ReadOnly)
+        ));
+        assert!(matches!(
+            primary.try_catch_up_with_primary(),
+            Err(LiteDbError::NotSecondary)
+        ));
+
+        // the secondary sees the writes of the primary once it catches up
+        for i in 0..250 {
+            let k = format!("k_{:01$}", i, 3);
+            primary.delete(k.as_bytes())?;
+        }
+        assert_eq!(secondary.get(b"k_000")?, Some(b"v".to_vec()));
+        // the tables of the primary no longer change once it is closed
+        drop(primary);
+        let opened_ss_tables: Vec<_> = secondary
+            .ss_tables
+            .iter()
+            .map(|entry| entry.value().clone())
+            .collect();
+        secondary.try_catch_up_with_primary()?;
+        for i in 0..500 {
+            let k = format!("k_{:01$}", i, 3);
+            let expected_value = if i < 250 { None } else { Some(b"v".to_vec()) };
+            assert_eq!(secondary.get(k.as_bytes())?, expected_value);
+        }
+
+        // the ss_tables the primary kept are not opened again
+        let primary_files = read_manifest(&primary_path)?.unwrap();
+        let secondary_files: BTreeSet<_> = secondary
+            .ss_tables
+            .iter()
+            .map(|entry| ss_table_file_name(entry.value()))
+            .collect();
+        assert_eq!(secondary_files, primary_files);
+        for ss_table in &opened_ss_tables {
+            let file_name = ss_table_file_name(ss_table);
+            if let Some(entry) = secondary
+                .ss_tables
+                .iter()
+                .find(|entry| ss_table_file_name(entry.value()) == file_name)
+            {
+                assert!(Arc::ptr_eq(entry.value(), ss_table));
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_lite_db_checkpoint() -> LiteDbResult<()> {
+        let temp_dir = tempdir()?;
+        let db_path = temp_dir.path().join("data");
+        let checkpoint_path = temp_dir.path().join("checkpoint");
+        let db = LiteDb::open(&db_path, LiteDbOptions::for_test())?;
+        for i in 0..1000 {
+            let k = format!("k_{:01$}", i, 4);
+            db.set(k.as_bytes(), b"v")?;
+        }
+        // an unrelated directory next to the checkpoint is left alone
+        let unrelated_path = temp_dir.path().join("checkpoint.tmp");
+        fs::create_dir(&unrelated_path)?;
+        fs::write(unrelated_path.join("file"), b"unrelated")?;
+        db.checkpoint(&checkpoint_path)?;
+        assert!(db.checkpoint(&checkpoint_path).is_err());
+        assert_eq!(fs::read(unrelated_path.join("file"))?, b"unrelated");
+        // checkpoints whose names only differ by extension do not share a temp dir
+        thread::scope(|scope| {
+            let handles: Vec<_> = ["checkpoint.v1", "checkpoint.v2"]
+                .iter()
+                .map(|name| {
+                    let path = temp_dir.path().join(name);
+                    scope.spawn(|| db.checkpoint(path))
+                })
+                .collect();
+            handles
+                .into_iter()
+                .try_for_each(|handle| handle.join().unwrap())
+        })?;
+        let num_entries = fs::read_dir(temp_dir.path())?.count();
+        assert_eq!(num_entries, 5);
+
+        // writes after the checkpoint are not part of it
+        for i in 0..1000 {
+            let k = format!("k_{:01$}", i, 4);
+            db.set(k.as_bytes(), b"v2")?;
+        }
+        drop(db);
+        let checkpoint = LiteDb::open(&checkpoint_path, LiteDbOptions::for_test())?;
+        assert!(checkpoint.wal_recovery_report().is_clean());
+        for i in 0..1000 {
+            let k = format!("k_{:01$}", i, 4);
+            assert_eq!(checkpoint.get(k.as_bytes())?, Some(b"v".to_vec()));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_lite_db_checkpoint_during_writes() -> LiteDbResult<()> {
+        let temp_dir = tempdir()?;
+        let db = LiteDb::open(
+            temp_dir.path().join("data"),
+            LiteDbOptions {
+                // keep compaction running alongside the checkpoints
+                level0_slowdown_writes_trigger: 4,
+                ..LiteDbOptions::for_test()
+            },
+        )?;
+        let num_checkpoints = 4;
+        let checkpoint_paths = (0..num_checkpoints)
+            .map(|n| temp_dir.path().join(format!("checkpoint_{n}")))
+            .collect::<Vec<_>>();
+        let done = AtomicBool::new(false);
+
+        thread::scope(|scope| -> LiteDbResult<()> {
+            scope.spawn(|| {
+                let mut i = 0;
+                while !done.load(Ordering::SeqCst) {
+                    let k = format!("k_{:01$}", i, 6);
+                    db.set(k.as_bytes(), b"v").unwrap();
+                    i += 1;
+                }
+            });
+            for checkpoint_path in &checkpoint_paths {
+                thread::sleep(Duration::from_millis(20));
+                db.checkpoint(checkpoint_path)?;
+            }
+            done.store(true, Ordering::SeqCst);
+            Ok(())
+        })?;
+        drop(db);
+
+        // every checkpoint holds the keys written before some point, no torn record
+        for checkpoint_path in &checkpoint_paths {
+            let checkpoint = LiteDb::open(checkpoint_path, LiteDbOptions::for_test())?;
+            assert!(checkpoint.wal_recovery_report().is_clean());
+            let num_keys = checkpoint.scan(&None, &None)?.count();
+            for i in 0..num_keys {
+                let k = format!("k_{:01$}", i, 6);
+                assert_eq!(checkpoint.get(k.as_bytes())?, Some(b"v".to_vec()));
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_lite_db_ingest_external_files() -> LiteDbResult<()> {
+        let temp_dir = tempdir()?;
+        let options = LiteDbOptions::for_test();
+        let db = LiteDb::open(temp_dir.path().join("data"), options.clone())?;
+        for i in 0..1000 {
+            let k = format!("k_{:01$}", i, 4);
+            db.set(k.as_bytes(), b"v")?;
+        }
+        let external_path = temp_dir.path().join("external.sst");
+        let mut writer = SstFileWriter::create(&external_path, &options)?;
+        for i in 500..1500 {
+            let k = format!("k_{:01$}", i, 4);
+            writer.put(k.as_bytes(), b"external")?;
+        }
+        writer.finish()?;
+
+        // a failed ingestion leaves the mem_tables as they were
+        db.flush()?;
+        let current_id = db.mem_tables.back().unwrap().value().id();
+        let overlap_result = db.ingest_external_files(&[&external_path], &IngestOptions::default());
+        assert!(matches!(
+            overlap_result,
+            Err(LiteDbError::IngestionOverlap(path)) if path == external_path
+        ));
+        assert_eq!(db.mem_tables.len(), 1);
+        assert_eq!(db.mem_tables.back().unwrap().value().id(), current_id);
+        db.ingest_external_files(
+            &[&external_path],
+            &IngestOptions {
+                move_files: true,
+                allow_global_seqno: true,
+            },
+        )?;
+        assert!(!external_path.exists());
+
+        // writes made after the ingestion take precedence over it
+        db.set(b"k_1000", b"v2")?;
+        drop(db);
+        let db = LiteDb::open(temp_dir.path().join("data"), options)?;
+        for i in 0..1500 {
+            let k = format!("k_{:01$}", i, 4);
+            let expected_value: &[u8] = match i {
+                1000 => b"v2",
+                _ if i < 500 => b"v",
+                _ => b"external",
+            };
+            assert_eq!(db.get(k.as_bytes())?, Some(expected_value.to_vec()));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_lite_db_ingest_failure_cleanup() -> LiteDbResult<()> {
+        let temp_dir = tempdir()?;
+        let db_path = temp_dir.path().join("data");
+        let options = LiteDbOptions::for_test();
+        let db = LiteDb::open(&db_path, options.clone())?;
+        db.set(b"k_0000", b"v")?;
+        let external_path = temp_dir.path().join("external.sst");
+        let mut writer = SstFileWriter::create(&external_path, &options)?;
+        writer.put(b"k_0000", b"external")?;
+        writer.finish()?;
+        let file_names = |path: &PathBuf| -> LiteDbResult<Vec<_>> {
+            let mut file_names = fs::read_dir(path)?
+                .map(|entry| Ok(entry?.file_name()))
+                .collect::<LiteDbResult<Vec<_>>>()?;
+            file_names.sort();
+            Ok(file_names)
+        };
+        let db_files = file_names(&db_path)?;
+        let external_bytes = fs::read(&external_path)?;
+
+        // the flush the ingestion waits for fails, the file given is left as it was
+        db.background_error.record(LiteDbError::CorruptedData);
+        let ingest_result = db.ingest_external_files(
+            &[&external_path],
+            &IngestOptions {
+                move_files: true,
+                allow_global_seqno: true,
+            },
+        );
+        assert!(matches!(ingest_result, Err(LiteDbError::Background(_))));
+        assert_eq!(fs::read(&external_path)?, external_bytes);
+        assert_eq!(db.ss_tables.len(), 0);
+        // the wal of the rotated mem_table is all that changed
+        assert_eq!(file_names(&db_path)?.len(), db_files.len() + 1);
+        drop(db);
+
+        // files staged before a crash are removed on open, unless a manifest lists them
+        let temp_file_path = db_path.join(format!("{:01$}.ingest.tmp", 7, 20));
+        fs::copy(&external_path, &temp_file_path)?;
+        let listed_path = temp_dir.path().join("listed.sst");
+        let mut writer = SstFileWriter::create(&listed_path, &options)?;
+        writer.put(b"k_0001", b"listed")?;
+        writer.finish()?;
+        let listed_temp_file_path = db_path.join(format!("{:01$}.ingest.tmp", 8, 20));
+        SSTable::open(listed_path)?.write_renumbered(8, &listed_temp_file_path)?;
+        let mut ss_table_files = read_manifest(&db_path)?.unwrap();
+        ss_table_files.insert(format!("{:01$}_0.sst", 8, 20));
+        write_manifest(&db_path, &ss_table_files)?;
+        let db = LiteDb::open(&db_path, options)?;
+        assert!(!temp_file_path.exists());
+        assert!(!listed_temp_file_path.exists());
+        assert_eq!(db.get(b"k_0000")?, Some(b"v".to_vec()));
+        assert_eq!(db.get(b"k_0001")?, Some(b"listed".to_vec()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_lite_db_ingest_flushes_older_mem_tables() -> LiteDbResult<()> {
+        let temp_dir = tempdir()?;
+        let db_path = temp_dir.path().join("data");
+        let options = LiteDbOptions::for_test();
+        let db = LiteDb::open(&db_path, options.clone())?;
+        db.set(b"k_0000", b"v1")?;
+        db.flush()?;
+        // the files overlap no table, with the flushed one they are enough to compact
+        let mut external_paths = vec![];
+        for i in 1..4 {
+            let external_path = temp_dir.path().join(format!("external_{i}.sst"));
+            let mut writer = SstFileWriter::create(&external_path, &options)?;
+            let k = format!("k_{:01$}", i, 4);
+            writer.put(k.as_bytes(), b"external")?;
+            writer.finish()?;
+            external_paths.push(external_path);
+        }
+
+        thread::scope(|scope| -> LiteDbResult<()> {
+            // a flush held up at closing its wal holds the following ones back
+            let (held_sender, held_receiver) = mpsc::channel();
+            let manifest = &db.manifest;
+            scope.spawn(move || {
+                let _deletion_guard = manifest.hold_deletions();
+                held_sender.send(()).unwrap();
+                thread::sleep(Duration::from_millis(300));
+            });
+            held_receiver.recv().unwrap();
+            for i in 0..200 {
+                let k = format!("f_{:01$}", i, 4);
+                db.set(k.as_bytes(), b"v")?;
+            }
+            while db.ss_tables.len() < 2 {
+                thread::sleep(Duration::from_millis(1));
+            }
+            db.set(b"k_0000", b"v2")?;
+            db.ingest_external_files(&external_paths, &IngestOptions::default())?;
+
+            // a compaction merging the ingested files cannot get ahead of the older writes
+            let newest_ss_table_id = db.ss_tables.back().unwrap().value().id();
+            assert!(db
+                .mem_tables
+                .iter()
+                .all(|entry| entry.value().id() > newest_ss_table_id));
+            Ok(())
+        })?;
+        db.flush()?;
+        drop(db);
+        let db = LiteDb::open(&db_path, options)?;
+        assert_eq!(db.get(b"k_0000")?, Some(b"v2".to_vec()));
+        for i in 1..4 {
+            let k = format!("k_{:01$}", i, 4);
+            assert_eq!(db.get(k.as_bytes())?, Some(b"external".to_vec()));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_lite_db_background_error() -> LiteDbResult<()> {
+        let temp_dir = tempdir()?;
+        let db = LiteDb::open(temp_dir.path(), LiteDbOptions::for_test())?;
+        db.set(b"k", b"v")?;
+        db.flush()?;
+
+        // the flush and the writes fail instead of waiting for work that stopped
+        db.background_error.record(LiteDbError::CorruptedData);
+        assert!(matches!(db.flush(), Err(LiteDbError::Background(_))));
+        assert!(matches!(
+            db.set(b"k", b"v2"),
+            Err(LiteDbError::Background(_))
+        ));
+        assert_eq!(db.get(b"k")?, Some(b"v".to_vec()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_lite_db_write_buffer_limit() -> LiteDbResult<()> {
+        let temp_dir = tempdir()?;
+        let options = LiteDbOptions::for_test();
+        let db = LiteDb::open(temp_dir.path().join("data"), options.clone())?;
+        let no_slowdown = WriteOptions {
+            no_slowdown: true,
+            ..WriteOptions::default()
+        };
+
+        // with the flushes held up, writes stop once every write buffer is full
+        let deletion_guard = db.manifest.lock_deletions();
+        let mut num_written = 0;
+        for i in 0..3000 {
+            let k = format!("k_{:01$}", i, 4);
+            match db.set_opt(k.as_bytes(), b"v", &no_slowdown) {
+                Ok(()) => num_written += 1,
+                Err(LiteDbError::WriteStall) => break,
+                Err(err) => return Err(err),
+            }
+            assert!(db.mem_tables.len() <= options.max_write_buffer_number);
+        }
+        assert!(num_written < 3000);
+        drop(deletion_guard);
+
+        // they resume once the flushes go on
+        for i in num_written..3000 {
+            let k = format!("k_{:01$}", i, 4);
+            db.set(k.as_bytes(), b"v")?;
+            assert!(db.mem_tables.len() <= options.max_write_buffer_number);
+        }
+        for i in 0..3000 {
+            let k = format!("k_{:01$}", i, 4);
+            assert_eq!(db.get(k.as_bytes())?, Some(b"v".to_vec()));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_lite_db_compaction_crash_recovery() -> LiteDbResult<()> {
+        let temp_dir = tempdir()?;
+        let db_path = temp_dir.path().join("data");
+        let inputs_path = temp_dir.path().join("inputs");
+        fs::create_dir_all(&inputs_path)?;
+        let db = LiteDb::open(&db_path, LiteDbOptions::for_test())?;
+        for round in 0..4 {
+            for i in 0..20 {
+                let k = format!("k_{:01$}", i, 4);
+                match round == 3 && i < 10 {
+                    true => db.delete(k.as_bytes())?,
+                    false => db.set(k.as_bytes(), format!("v{round}").as_bytes())?,
+                }
+            }
+            db.flush()?;
+        }
+        let input_files = db
+            .ss_tables
+            .iter()
+            .map(|entry| entry.value().path().to_path_buf())
+            .collect::<Vec<_>>();
+        assert_eq!(input_files.len(), 4);
+        for file in &input_files {
+            fs::copy(file, inputs_path.join(file.file_name().unwrap()))?;
+        }
+        db.compactor.as_ref().unwrap().trigger();
+        for _ in 0..500 {
+            if db.ss_tables.len() == 1 {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(db.ss_tables.len(), 1);
+        drop(db);
+
+        // a crash before the inputs got deleted leaves them next to the merged table
+        for file in &input_files {
+            fs::copy(inputs_path.join(file.file_name().unwrap()), file)?;
+        }
+        let db = LiteDb::open(&db_path, LiteDbOptions::for_test())?;
+        assert_eq!(db.ss_tables.len(), 1);
+        assert!(input_files.iter().all(|file| !file.exists()));
+        for i in 0..20 {
+            let k = format!("k_{:01$}", i, 4);
+            let expected_value = match i < 10 {
+                true => None,
+                false => Some(b"v3".to_vec()),
+            };
+            assert_eq!(db.get(k.as_bytes())?, expected_value);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_lite_db_max_open_files() -> LiteDbResult<()> {
+        let temp_dir = tempdir()?;
+        let options = LiteDbOptions {
+            max_open_files: Some(2),
+            ..LiteDbOptions::for_test()
+        };
+        let db = LiteDb::open(temp_dir.path(), options.clone())?;
+        for i in 0..2000 {
+            let k = format!("k_{:01$}", i, 4);
+            db.set(k.as_bytes(), b"v")?;
+        }
+        drop(db);
+
+        // the ss_tables are opened on first access only, the compactor
+        // may open a few of them meanwhile to check their bloom filters
+        let db = LiteDb::open(temp_dir.path(), options)?;
+        let table_cache = db.ss_table_caches.table_cache.clone().unwrap();
+        assert!(table_cache.num_open_files() <= 2);
+        assert_eq!(db.get(b"k_2000")?, None);
+        assert!(table_cache.num_open_files() <= 2);
+        for i in 0..2000 {
+            let k = format!("k_{:01$}", i, 4);
+            assert_eq!(db.get(k.as_bytes())?, Some(b"v".to_vec()));
+            assert!(table_cache.num_open_files() <= 2);
+        }
+        assert_eq!(db.scan(&None, &None)?.count(), 2000);
+        assert!(table_cache.num_open_files() <= 2);
+        Ok(())
+    }
 }
@@ -0,0 +1,88 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    hash::Hash,
+};
+
+/// Holds entries within a budget, the least recently used are evicted first.
+///
+/// Every entry is charged against the capacity, e.g. with its size in bytes.
+pub(crate) struct LruCache<K, V> {
+    capacity: usize,
+    usage: usize,
+    entries: HashMap<K, LruEntry<V>>,
+    /// Keys by last use, oldest first.
+    lru: BTreeMap<u64, K>,
+    last_use: u64,
+}
+
+struct LruEntry<V> {
+    value: V,
+    charge: usize,
+    last_use: u64,
+}
+
+impl<K: Copy + Eq + Hash, V: Clone> LruCache<K, V> {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            usage: 0,
+            entries: HashMap::new(),
+            lru: BTreeMap::new(),
+            last_use: 0,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Returns the sum of the charges of the entries.
+    pub fn usage(&self) -> usize {
+        self.usage
+    }
+
+    fn touch(&mut self) -> u64 {
+        self.last_use += 1;
+        self.last_use
+    }
+
+    pub fn get(&mut self, key: &K) -> Option<V> {
+        let last_use = self.touch();
+        let entry = self.entries.get_mut(key)?;
+        self.lru.remove(&entry.last_use);
+        entry.last_use = last_use;
+        self.lru.insert(last_use, *key);
+        Some(entry.value.clone())
+    }
+
+    /// Inserts the entry, then evicts the least recently used ones while over capacity.
+    pub fn insert(&mut self, key: K, value: V, charge: usize) {
+        let last_use = self.touch();
+        let entry = LruEntry {
+            value,
+            charge,
+            last_use,
+        };
+        if let Some(replaced) = self.entries.insert(key, entry) {
+            self.lru.remove(&replaced.last_use);
+            self.usage -= replaced.charge;
+        }
+        self.lru.insert(last_use, key);
+        self.usage += charge;
+
+        while self.usage > self.capacity {
+            let (_, evicted_key) = match self.lru.pop_first() {
+                Some(entry) => entry,
+                None => break,
+            };
+            self.remove(&evicted_key);
+        }
+    }
+
+    pub fn remove(&mut self, key: &K) {
+        if let Some(removed) = self.entries.remove(key) {
+            self.lru.remove(&removed.last_use);
+            self.usage -= removed.charge;
+        }
+    }
+}
@@ -0,0 +1,143 @@
+use std::{
+    collections::BTreeSet,
+    fs::{self, File},
+    io::{ErrorKind, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use bincode::{Decode, Encode};
+use crossbeam_skiplist::SkipSet;
+use parking_lot::{Mutex, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use crate::{
+    error::{LiteDbError, LiteDbResult},
+    ss_table::SSTable,
+    utils::{crc32, decode, encode_into_writer, AtomicOperationExecutor},
+};
+
+pub(crate) const MANIFEST_FILE_NAME: &str = "MANIFEST";
+const MANIFEST_TEMP_FILE_NAME: &str = "MANIFEST.tmp";
+const MANIFEST_VERSION: u32 = 1;
+
+#[derive(Debug, Encode, Decode)]
+struct ManifestContents {
+    version: u32,
+    ss_table_files: Vec<String>,
+}
+
+/// Lists the live ss_table files of a database directory.
+///
+/// A flush or a compaction only takes effect on disk once the manifest is
+/// rewritten, through a temp file renamed over it. On recovery, the ss_tables
+/// it does not list are either the inputs of a published compaction or the
+/// output of one that did not complete, they are deleted.
+#[derive(Debug)]
+pub(crate) struct Manifest {
+    dir: PathBuf,
+    // rewrites are serialized, so that the last one lists the latest tables
+    write_lock: Mutex<()>,
+    // the files no longer listed are deleted under the write guard
+    deletion_lock: RwLock<()>,
+}
+
+impl Manifest {
+    pub fn new(dir: PathBuf) -> Self {
+        Self {
+            dir,
+            write_lock: Mutex::new(()),
+            deletion_lock: RwLock::new(()),
+        }
+    }
+
+    /// Keeps the files of the tables from being deleted while the guard is held,
+    /// the ones published meanwhile included.
+    pub fn hold_deletions(&self) -> RwLockReadGuard<'_, ()> {
+        self.deletion_lock.read()
+    }
+
+    /// Held while deleting the files of unpublished tables, wals included.
+    pub fn lock_deletions(&self) -> RwLockWriteGuard<'_, ()> {
+        self.deletion_lock.write()
+    }
+
+    /// Rewrites the manifest with the ss_tables published at the time.
+    pub fn persist(
+        &self,
+        ss_tables: &SkipSet<Arc<SSTable>>,
+        atomic_operation_executor: &AtomicOperationExecutor,
+    ) -> LiteDbResult<()> {
+        self.persist_with(ss_tables, atomic_operation_executor, &BTreeSet::new())
+    }
+
+    /// Rewrites the manifest with the ss_tables published at the time and the
+    /// `pending_files` about to be, which recovery completes from their staged copy.
+    pub fn persist_with(
+        &self,
+        ss_tables: &SkipSet<Arc<SSTable>>,
+        atomic_operation_executor: &AtomicOperationExecutor,
+        pending_files: &BTreeSet<String>,
+    ) -> LiteDbResult<()> {
+        let _write_guard = self.write_lock.lock();
+        let mut ss_table_files = atomic_operation_executor.perform(|| {
+            ss_tables
+                .iter()
+                .map(|entry| ss_table_file_name(entry.value()))
+                .collect::<BTreeSet<_>>()
+        });
+        ss_table_files.extend(pending_files.iter().cloned());
+        write_manifest(&self.dir, &ss_table_files)
+    }
+}
+
+pub(crate) fn ss_table_file_name(ss_table: &SSTable) -> String {
+    ss_table
+        .path()
+        .file_name()
+        .expect("Expected an ss_table file name")
+        .to_string_lossy()
+        .to_string()
+}
+
+/// Writes a manifest listing `ss_table_files` into `dir`.
+pub(crate) fn write_manifest(dir: &Path, ss_table_files: &BTreeSet<String>) -> LiteDbResult<()> {
+    let contents = ManifestContents {
+        version: MANIFEST_VERSION,
+        ss_table_files: ss_table_files.iter().cloned().collect(),
+    };
+    let mut bytes = Vec::new();
+    encode_into_writer(&contents, &mut bytes)?;
+    let crc = crc32(&bytes, &[]);
+
+    let temp_file_path = dir.join(MANIFEST_TEMP_FILE_NAME);
+    let mut file = File::create(&temp_file_path)?;
+    file.write_all(&bytes)?;
+    file.write_all(&crc.to_le_bytes())?;
+    file.sync_all()?;
+    fs::rename(&temp_file_path, dir.join(MANIFEST_FILE_NAME))?;
+    // makes the rename durable
+    File::open(dir)?.sync_all()?;
+    Ok(())
+}
+
+/// Reads the ss_table files listed by the manifest of `dir`, None when it has none.
+pub(crate) fn read_manifest(dir: &Path) -> LiteDbResult<Option<BTreeSet<String>>> {
+    let bytes = match fs::read(dir.join(MANIFEST_FILE_NAME)) {
+        Ok(bytes) => bytes,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err.into()),
+    };
+    if bytes.len() < 4 {
+        return Err(LiteDbError::CorruptedData);
+    }
+    let (bytes, crc_bytes) = bytes.split_at(bytes.len() - 4);
+    let crc = u32::from_le_bytes(crc_bytes.try_into().expect("Expected 4 bytes"));
+    if crc32(bytes, &[]) != crc {
+        return Err(LiteDbError::CorruptedData);
+    }
+    let (contents, _): (ManifestContents, usize) = decode(bytes)?;
+    if contents.version != MANIFEST_VERSION {
+        return Err(LiteDbError::CorruptedData);
+    }
+    Ok(Some(contents.ss_table_files.into_iter().collect()))
+}
@@ -1,39 +1,79 @@
 use std::{
     cmp::Ordering,
-    fs::OpenOptions,
-    io::{BufWriter, Write},
+    fs,
+    io::ErrorKind,
+    mem,
     ops::Bound,
     path::PathBuf,
     sync::{
-        atomic::{AtomicUsize, Ordering as AtomicOrdering},
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering as AtomicOrdering},
         Arc,
     },
+    time::{Duration, Instant},
 };
 
-use bloomfilter::Bloom;
-use byteorder::{LittleEndian, WriteBytesExt};
-use crossbeam_skiplist::{map::Range, SkipMap};
-use memmap2::MmapOptions;
 use ouroboros::self_referencing;
 
 use crate::{
     batching::BatchOperations,
-    bloom_filter::BloomFilterState,
+    controller::RotationTrigger,
+    cursor::Cursor,
     error::LiteDbResult,
-    ss_table::{Offset, SSTable, SSTableMetadata, SSTableSparseIndex, SS_TABLE_FILE_EXTENSION},
-    utils::encode_into_writer,
-    wal::WriteAheadLogger,
+    mem_table_rep::{MemTableFactory, MemTableRep, MemTableRepCursor},
+    options::{ReadOptions, WriteOptions},
+    ss_table::{ss_table_file_path, SSTable, SSTableBuilder, SSTableOptions},
+    wal::{
+        sequence_number, wal_file_path, LogItem, WalRecoveryMode, WalRecoveryReport, WalSyncMode,
+        WriteAheadLogger,
+    },
+    wal_archive::{archive_wal, WalArchiveOptions},
+    write_buffer_manager::WriteBufferManager,
     KVIterator, Key, RefKey, RefValue, Scannable, Value,
 };
 
-pub(crate) type SkipMapRangeIterator<'a, K, V> = Range<'a, K, (Bound<K>, Bound<K>), K, V>;
+/// Estimated bytes an entry holds besides its key and value: for a skiplist, the node
+/// header, the key and value vectors, the sequence number and the average tower of
+/// next pointers.
+const ENTRY_OVERHEAD: usize =
+    2 * mem::size_of::<Vec<u8>>() + mem::size_of::<u64>() + 4 * mem::size_of::<usize>();
+
+/// Memory held by the mem_tables of a database.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MemoryUsage {
+    /// Bytes of the mem_table handling writes.
+    pub current_mem_table: usize,
+    /// Bytes of every mem_table, the ones being flushed included.
+    pub mem_tables_total: usize,
+}
+
+/// Settings shared by every mem_table of the database.
+#[derive(Debug, Default, Clone)]
+pub(crate) struct MemTableOptions {
+    pub wal_sync_mode: WalSyncMode,
+    pub wal_recovery_mode: WalRecoveryMode,
+    pub rotation_trigger: Option<RotationTrigger>,
+    pub memtable_factory: MemTableFactory,
+    pub write_buffer_manager: Option<WriteBufferManager>,
+    /// Replays the wals without opening them for writes nor cutting them.
+    pub read_only: bool,
+    /// Archives the wals of flushed mem_tables instead of deleting them.
+    pub wal_archive: Option<WalArchiveOptions>,
+}
 
 #[derive(Debug)]
 pub(crate) struct MemTable {
     id: u64,
-    entries: SkipMap<Key, Value>,
+    entries: Box<dyn MemTableRep>,
+    // the number of entries written so far, every version of a key included
+    num_versions: AtomicU64,
     size_bytes: AtomicUsize,
-    wal: WriteAheadLogger,
+    // read-only mem_tables have no wal
+    wal: Option<WriteAheadLogger>,
+    rotation_trigger: Option<RotationTrigger>,
+    write_buffer_manager: Option<WriteBufferManager>,
+    wal_archive: Option<WalArchiveOptions>,
+    memory_released: AtomicBool,
+    created_at: Instant,
     dir: PathBuf,
 }
 
@@ -58,155 +98,283 @@ impl PartialEq for MemTable {
 }
 
 impl MemTable {
-    pub(crate) fn open(dir: PathBuf, id: u64) -> LiteDbResult<Self> {
-        let wal = WriteAheadLogger::open(dir.clone(), id)?;
-        let data = SkipMap::new();
-        for item_result in wal.iter() {
-            let item = item_result?;
-            data.insert(item.key, item.value);
-        }
-        Ok(Self {
+    pub(crate) fn open(dir: PathBuf, id: u64, options: &MemTableOptions) -> LiteDbResult<Self> {
+        Self::recover(dir, id, options).map(|(mem_table, _)| mem_table)
+    }
+
+    /// Opens a mem_table and reports what replaying its wal had to drop.
+    pub(crate) fn recover(
+        dir: PathBuf,
+        id: u64,
+        options: &MemTableOptions,
+    ) -> LiteDbResult<(Self, WalRecoveryReport)> {
+        let entries = options.memtable_factory.create();
+        let num_versions = AtomicU64::new(0);
+        let size_bytes = AtomicUsize::new(0);
+        let apply = |item: LogItem| {
+            let sequence_number = next_sequence_number(id, &num_versions);
+            insert_entry(
+                entries.as_ref(),
+                &size_bytes,
+                item.key,
+                sequence_number,
+                item.value,
+            );
+        };
+        let (wal, report) = if options.read_only {
+            let report = WriteAheadLogger::replay(&dir, id, options.wal_recovery_mode, apply)?;
+            (None, report)
+        } else {
+            let (wal, report) = WriteAheadLogger::recover(
+                dir.clone(),
+                id,
+                options.wal_sync_mode,
+                options.wal_recovery_mode,
+                apply,
+            )?;
+            (Some(wal), report)
+        };
+        let mem_table = Self {
             id,
-            entries: data,
-            size_bytes: AtomicUsize::new(0),
+            entries,
+            num_versions,
+            size_bytes,
             wal,
+            rotation_trigger: options.rotation_trigger.clone(),
+            write_buffer_manager: options.write_buffer_manager.clone(),
+            wal_archive: options.wal_archive,
+            memory_released: AtomicBool::new(false),
+            created_at: Instant::now(),
             dir,
-        })
+        };
+        if let Some(write_buffer_manager) = &mem_table.write_buffer_manager {
+            write_buffer_manager.reserve(mem_table.size_bytes());
+        }
+        mem_table.request_flush_if_needed();
+        Ok((mem_table, report))
     }
 
     pub fn ss_table_file_path(&self) -> PathBuf {
-        self.dir
-            .join(format!("{:01$}.{SS_TABLE_FILE_EXTENSION}", self.id, 20))
+        ss_table_file_path(&self.dir, self.id, 0)
     }
 
-    pub fn set(&self, key: RefKey, value: RefValue) -> LiteDbResult<()> {
-        self.wal.append(key, value)?;
-        self.size_bytes
-            .fetch_add(key.len() + value.len(), AtomicOrdering::SeqCst);
-        self.entries.insert(key.to_owned(), value.to_owned());
+    pub fn set(
+        &self,
+        key: RefKey,
+        value: RefValue,
+        write_options: &WriteOptions,
+    ) -> LiteDbResult<()> {
+        if let (false, Some(wal)) = (write_options.disable_wal, &self.wal) {
+            wal.append(key, value, write_options.sync)?;
+        }
+        self.insert(key.to_owned(), value.to_owned());
+        self.request_flush_if_needed();
         Ok(())
     }
 
-    pub fn get(&self, key: RefKey) -> LiteDbResult<Option<Value>> {
-        let value_opt = self.entries.get(key).map(|entry| entry.value().to_owned());
-        Ok(value_opt)
+    /// Returns the latest value of `key` written at or before `sequence_number`.
+    pub fn get(&self, key: RefKey, sequence_number: u64) -> LiteDbResult<Option<Value>> {
+        Ok(self.entries.get(key, sequence_number))
     }
 
-    pub fn apply_batch(&self, batch_ops: BatchOperations) -> LiteDbResult<()> {
-        self.wal.apply_batch(batch_ops.operations())?;
-        self.size_bytes
-            .fetch_add(batch_ops.size_bytes(), AtomicOrdering::SeqCst);
-        for operation in batch_ops.operations() {
-            self.entries
-                .insert(operation.0.to_owned(), operation.1.to_owned());
+    pub fn apply_batch(
+        &self,
+        batch_ops: BatchOperations,
+        write_options: &WriteOptions,
+    ) -> LiteDbResult<()> {
+        if let (false, Some(wal)) = (write_options.disable_wal, &self.wal) {
+            wal.apply_batch(batch_ops.operations(), write_options.sync)?;
+        }
+        for (key, value) in batch_ops.operations() {
+            self.insert(key.to_owned(), value.to_owned());
         }
+        self.request_flush_if_needed();
         Ok(())
     }
 
+    /// Persists the mem_table as a level 0 ss_table and removes its wal.
+    #[cfg(test)]
     pub fn save(
         &self,
-        bloom_filter_size_bytes: usize,
-        bloom_filter_item_count: usize,
-        sparse_index_range_size: usize,
+        bloom_bits_per_key: f64,
+        options: &SSTableOptions,
     ) -> LiteDbResult<Arc<SSTable>> {
-        // create & persist sparse.index
-        let mut index_entries: Vec<(Key, Offset)> = Vec::new();
-
-        // create & persist the bloom.filter
-        let mut bloom_filter: Bloom<Key> =
-            Bloom::new(bloom_filter_size_bytes, bloom_filter_item_count);
-
-        let segment_file = OpenOptions::new()
-            .read(true)
-            .write(true)
-            .create(true)
-            .open(self.ss_table_file_path())?;
-
-        // Loop through data:
-        // - append to segment_file,
-        // - update bloom_filter & index_entries
-        let mut writer = BufWriter::new(&segment_file);
-        let mut size_of_serialized_data = 0usize;
-        let mut last_num_bytes_written = 0usize;
-
-        for entry in self.entries.iter() {
-            let kv = (entry.key(), entry.value());
-            last_num_bytes_written = encode_into_writer(&kv, &mut writer)?;
-
-            bloom_filter.set(kv.0);
-            if (size_of_serialized_data == 0)
-                || (size_of_serialized_data % sparse_index_range_size == 0)
-            {
-                index_entries.push((kv.0.clone(), size_of_serialized_data));
-            }
-            size_of_serialized_data += last_num_bytes_written;
-        }
-        let last_key = self.entries.back().unwrap();
-        index_entries.push((last_key.key().clone(), size_of_serialized_data));
-
-        // create & persist meta.json
-        let first_key: (Key, Offset) = (self.entries.front().unwrap().key().clone(), 0);
-        let last_key: (Key, Offset) = (
-            last_key.key().clone(),
-            size_of_serialized_data - last_num_bytes_written,
-        );
-        let metadata = SSTableMetadata::new(
+        let ss_table = self.write_ss_table(bloom_bits_per_key, options)?;
+        self.close()?;
+        Ok(ss_table)
+    }
+
+    /// Persists the mem_table as a level 0 ss_table, the wal is kept.
+    pub fn write_ss_table(
+        &self,
+        bloom_bits_per_key: f64,
+        options: &SSTableOptions,
+    ) -> LiteDbResult<Arc<SSTable>> {
+        let mut builder = SSTableBuilder::new(
+            self.ss_table_file_path(),
             self.id,
-            first_key,
-            last_key,
-            self.size_bytes.load(AtomicOrdering::SeqCst),
-            self.entries.len(),
+            0,
+            self.entries.num_entries(),
+            bloom_bits_per_key,
+            options,
+        )?;
+        let mut cursor = self.entries.cursor(u64::MAX);
+        cursor.seek(Bound::Unbounded);
+        while cursor.valid() {
+            builder.add(cursor.key(), cursor.value())?;
+            cursor.next();
+        }
+        Ok(Arc::new(builder.finish()?))
+    }
+
+    pub fn is_full(&self, max_entries: usize, max_size_bytes: usize) -> bool {
+        let size_bytes = self.size_bytes.load(AtomicOrdering::SeqCst);
+        self.entries.num_entries() >= max_entries || size_bytes >= max_size_bytes
+    }
+
+    fn insert(&self, key: Key, value: Value) {
+        let sequence_number = next_sequence_number(self.id, &self.num_versions);
+        let num_bytes = insert_entry(
+            self.entries.as_ref(),
+            &self.size_bytes,
+            key,
+            sequence_number,
+            value,
         );
+        if let Some(write_buffer_manager) = &self.write_buffer_manager {
+            write_buffer_manager.reserve(num_bytes);
+        }
+    }
 
-        // append meta, index, bloom
-        encode_into_writer(&metadata, &mut writer)?;
+    /// Wakes the controller up once the mem_table is full or
+    /// the write buffer manager is over budget.
+    fn request_flush_if_needed(&self) {
+        if let Some(rotation_trigger) = &self.rotation_trigger {
+            rotation_trigger.notify_if_full(self);
+        }
+        if let Some(write_buffer_manager) = &self.write_buffer_manager {
+            write_buffer_manager.flush_if_needed();
+        }
+    }
 
-        let index = SSTableSparseIndex::from(index_entries);
-        encode_into_writer(&index, &mut writer)?;
+    /// Asks the controller to rotate & flush the mem_table even though it is not mature.
+    pub fn request_flush(&self) {
+        if let Some(rotation_trigger) = &self.rotation_trigger {
+            rotation_trigger.request_flush();
+        }
+    }
 
-        let bloom_filter_state = BloomFilterState::from(&bloom_filter);
-        encode_into_writer(&bloom_filter_state, &mut writer)?;
+    pub fn age(&self) -> Duration {
+        self.created_at.elapsed()
+    }
 
-        // append data size for offset calculation
-        writer.write_u64::<LittleEndian>(size_of_serialized_data as u64)?;
+    /// Returns the memory of the mem_table to the write buffer manager, only once.
+    fn release_memory(&self) {
+        if let Some(write_buffer_manager) = &self.write_buffer_manager {
+            if !self.memory_released.swap(true, AtomicOrdering::SeqCst) {
+                write_buffer_manager.free(self.size_bytes());
+            }
+        }
+    }
 
-        // flush segment_file
-        writer.flush()?;
-        segment_file.sync_all()?;
+    pub fn close(&self) -> LiteDbResult<()> {
+        // readers may still hold the mem_table, but it no longer takes writes
+        self.release_memory();
+        match (&self.wal, &self.wal_archive) {
+            (Some(wal), Some(wal_archive)) => archive_wal(&wal.file_path(), wal_archive),
+            (Some(wal), None) => wal.remove(),
+            (None, _) => Ok(()),
+        }
+    }
 
-        let file = unsafe {
-            MmapOptions::new()
-                .offset(0)
-                .len(size_of_serialized_data)
-                .map(&segment_file)
-                .unwrap()
-        };
+    /// Returns the sequence number of the last entry written to the mem_table,
+    /// reads at that sequence number ignore the entries written afterwards.
+    pub fn last_entry_sequence_number(&self) -> u64 {
+        sequence_number(self.id, self.num_versions.load(AtomicOrdering::SeqCst))
+    }
 
-        self.close()?;
-        Ok(Arc::new(SSTable::new(metadata, file, index, bloom_filter)))
+    /// Returns the sequence number of the last record logged to the wal.
+    pub fn last_sequence_number(&self) -> u64 {
+        match &self.wal {
+            Some(wal) => wal.last_sequence_number(),
+            None => sequence_number(self.id, 0),
+        }
     }
 
-    pub fn is_full(&self, max_entries: usize, max_size_bytes: usize) -> bool {
-        let size_bytes = self.size_bytes.load(AtomicOrdering::SeqCst);
-        self.entries.len() >= max_entries || size_bytes >= max_size_bytes
+    /// Returns the approximate bytes held by the entries.
+    pub fn size_bytes(&self) -> usize {
+        self.size_bytes.load(AtomicOrdering::SeqCst)
     }
 
-    pub fn close(&self) -> LiteDbResult<()> {
-        self.wal.remove()
+    /// Returns the wal file and the end of its last complete record, if it has one.
+    ///
+    /// Read-only mem_tables replayed their wal without opening it, nothing writes to it.
+    pub fn wal_end(&self) -> LiteDbResult<Option<(PathBuf, u64)>> {
+        match &self.wal {
+            Some(wal) => Ok(Some((wal.file_path(), wal.end_offset()?))),
+            None => {
+                let path = wal_file_path(&self.dir, self.id);
+                match fs::metadata(&path) {
+                    Ok(metadata) => Ok(Some((path, metadata.len()))),
+                    Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+                    Err(err) => Err(err.into()),
+                }
+            }
+        }
     }
 
     pub fn id(&self) -> u64 {
         self.id
     }
 
+    pub fn num_entries(&self) -> usize {
+        self.entries.num_entries()
+    }
+
     pub fn dir(&self) -> PathBuf {
         self.dir.clone()
     }
 }
 
+impl Drop for MemTable {
+    fn drop(&mut self) {
+        self.release_memory();
+    }
+}
+
+/// Numbers the entries of a mem_table in write order, across mem_tables too.
+fn next_sequence_number(id: u64, num_versions: &AtomicU64) -> u64 {
+    sequence_number(id, num_versions.fetch_add(1, AtomicOrdering::SeqCst) + 1)
+}
+
+/// Inserts a version of `key` and returns the bytes it takes.
+fn insert_entry(
+    entries: &dyn MemTableRep,
+    size_bytes: &AtomicUsize,
+    key: Key,
+    sequence_number: u64,
+    value: Value,
+) -> usize {
+    // older versions are held until the mem_table is flushed
+    let num_bytes = ENTRY_OVERHEAD + key.len() + value.len();
+    entries.insert(key, sequence_number, value);
+    size_bytes.fetch_add(num_bytes, AtomicOrdering::SeqCst);
+    num_bytes
+}
+
 impl Scannable for Arc<MemTable> {
-    fn scan(&self, from: &Option<Key>, to: &Option<Key>) -> KVIterator {
-        KVIterator::MemTable(MemTableIterator::new(self.clone(), from, to))
+    fn scan_with_options(
+        &self,
+        from: &Option<Key>,
+        to: &Option<Key>,
+        read_options: &ReadOptions,
+    ) -> KVIterator {
+        KVIterator::MemTable(MemTableIterator::new(
+            self.clone(),
+            from,
+            to,
+            read_options.sequence_number(),
+        ))
     }
 }
 
@@ -215,31 +383,121 @@ struct MemTableIterInner {
     mem_table: Arc<MemTable>,
     #[borrows(mem_table)]
     #[not_covariant]
-    range: SkipMapRangeIterator<'this, Key, Value>,
+    cursor: Box<dyn MemTableRepCursor + 'this>,
 }
 
+/// A cursor over a range `[from, to)` of a mem_table.
+///
+/// As an `Iterator`, it starts at the first entry of the range.
 pub(crate) struct MemTableIterator {
     inner: MemTableIterInner,
+    from: Option<Key>,
+    to: Option<Key>,
+    // set once the cursor moved out of the range
+    out_of_range: bool,
+    started: bool,
 }
 
 impl MemTableIterator {
-    pub fn new(mem_table: Arc<MemTable>, from: &Option<Key>, to: &Option<Key>) -> Self {
-        let range = match (from.clone(), to.clone()) {
-            (None, None) => (Bound::Unbounded, Bound::Unbounded),
-            (None, Some(last_key)) => (Bound::Unbounded, Bound::Excluded(last_key)),
-            (Some(first_key), None) => (Bound::Included(first_key), Bound::Unbounded),
-            (Some(first_key), Some(last_key)) => {
-                (Bound::Included(first_key), Bound::Excluded(last_key))
-            }
-        };
-
+    /// Reads the entries written at or before `sequence_number`.
+    pub fn new(
+        mem_table: Arc<MemTable>,
+        from: &Option<Key>,
+        to: &Option<Key>,
+        sequence_number: u64,
+    ) -> Self {
         let inner = MemTableIterInnerBuilder {
             mem_table,
-            range_builder: |mem_table| mem_table.entries.range(range),
+            cursor_builder: |mem_table| mem_table.entries.cursor(sequence_number),
         }
         .build();
 
-        Self { inner }
+        Self {
+            inner,
+            from: from.clone(),
+            to: to.clone(),
+            out_of_range: false,
+            started: false,
+        }
+    }
+
+    /// Moves the underlying cursor and invalidates it when it leaves the range.
+    fn move_cursor(&mut self, move_fn: impl FnOnce(&mut dyn MemTableRepCursor)) {
+        let (from, to) = (&self.from, &self.to);
+        self.out_of_range = self.inner.with_cursor_mut(|cursor| {
+            move_fn(cursor.as_mut());
+            if !cursor.valid() {
+                return false;
+            }
+            let key = cursor.key();
+            matches!(from, Some(first_key) if key < first_key.as_slice())
+                || matches!(to, Some(last_key) if key >= last_key.as_slice())
+        });
+    }
+}
+
+impl Cursor for MemTableIterator {
+    fn valid(&self) -> bool {
+        !self.out_of_range && self.inner.with_cursor(|cursor| cursor.valid())
+    }
+
+    fn seek_to_first(&mut self) -> LiteDbResult<()> {
+        let from = self.from.clone();
+        self.move_cursor(|cursor| match &from {
+            Some(first_key) => cursor.seek(Bound::Included(first_key.as_slice())),
+            None => cursor.seek(Bound::Unbounded),
+        });
+        Ok(())
+    }
+
+    fn seek_to_last(&mut self) -> LiteDbResult<()> {
+        let to = self.to.clone();
+        self.move_cursor(|cursor| match &to {
+            Some(last_key) => cursor.seek_for_prev(Bound::Excluded(last_key.as_slice())),
+            None => cursor.seek_for_prev(Bound::Unbounded),
+        });
+        Ok(())
+    }
+
+    fn seek(&mut self, key: RefKey) -> LiteDbResult<()> {
+        let target = match &self.from {
+            Some(first_key) if key < first_key.as_slice() => first_key.clone(),
+            _ => key.to_vec(),
+        };
+        self.move_cursor(|cursor| cursor.seek(Bound::Included(target.as_slice())));
+        Ok(())
+    }
+
+    fn seek_for_prev(&mut self, key: RefKey) -> LiteDbResult<()> {
+        if matches!(&self.to, Some(last_key) if key >= last_key.as_slice()) {
+            return self.seek_to_last();
+        }
+        self.move_cursor(|cursor| cursor.seek_for_prev(Bound::Included(key)));
+        Ok(())
+    }
+
+    fn next(&mut self) -> LiteDbResult<()> {
+        if self.valid() {
+            self.move_cursor(|cursor| cursor.next());
+        }
+        Ok(())
+    }
+
+    fn prev(&mut self) -> LiteDbResult<()> {
+        if self.valid() {
+            self.move_cursor(|cursor| cursor.prev());
+        }
+        Ok(())
+    }
+
+    fn key(&self) -> RefKey<'_> {
+        assert!(!self.out_of_range, "Expected a valid cursor.");
+        self.inner.with_cursor(|cursor| cursor.key())
+    }
+
+    fn value(&self) -> RefValue<'_> {
+        assert!(!self.out_of_range, "Expected a valid cursor.");
+        self.inner.with_cursor(|cursor| cursor.value())
     }
 }
 
@@ -247,11 +505,19 @@ impl Iterator for MemTableIterator {
     type Item = LiteDbResult<(Key, Value)>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.inner.with_range_mut(|range| {
-            range
-                .next()
-                .map(|entry| Ok((entry.key().clone(), entry.value().clone())))
-        })
+        let move_result = if self.started {
+            Cursor::next(self)
+        } else {
+            self.started = true;
+            self.seek_to_first()
+        };
+        if let Err(err) = move_result {
+            return Some(Err(err));
+        }
+        if !self.valid() {
+            return None;
+        }
+        Some(Ok((self.key().to_vec(), self.value().to_vec())))
     }
 }
 
@@ -259,7 +525,12 @@ impl Iterator for MemTableIterator {
 mod tests {
     use std::sync::Arc;
 
-    use crate::{mem_table::MemTable, Scannable};
+    use crate::{
+        mem_table::{MemTable, MemTableOptions, ENTRY_OVERHEAD},
+        mem_table_rep::MemTableFactory,
+        options::WriteOptions,
+        Scannable,
+    };
 
     use anyhow::Ok;
     use tempfile::tempdir;
@@ -268,7 +539,7 @@ mod tests {
     fn test_empty_mem_table() -> anyhow::Result<()> {
         let tempdir = tempdir()?;
         let dir = tempdir.path().to_path_buf();
-        let mem_table = Arc::new(MemTable::open(dir, 1).unwrap());
+        let mem_table = Arc::new(MemTable::open(dir, 1, &MemTableOptions::default()).unwrap());
         assert_eq!(mem_table.scan(&None, &None).count(), 0);
         Ok(())
     }
@@ -277,24 +548,90 @@ mod tests {
     fn test_mem_table() -> anyhow::Result<()> {
         let tempdir = tempdir()?;
         let dir = tempdir.path().to_path_buf();
-        let mem_table = Arc::new(MemTable::open(dir, 1)?);
+        let mem_table = Arc::new(MemTable::open(dir, 1, &MemTableOptions::default())?);
 
         for i in 0..=100 {
             let k = format!("k_{:01$}", i, 3);
             let v = format!("v_{:01$}", i, 3);
-            mem_table.set(k.as_bytes(), v.as_bytes())?;
+            mem_table.set(k.as_bytes(), v.as_bytes(), &WriteOptions::default())?;
         }
 
         for i in 0..=100 {
             let k = format!("k_{:01$}", i, 3);
-            let v = mem_table.get(k.as_bytes())?;
+            let v = mem_table.get(k.as_bytes(), u64::MAX)?;
             let expected_v = format!("v_{:01$}", i, 3).as_bytes().to_vec();
             assert_eq!(v, Some(expected_v));
         }
 
-        let unknown_v = mem_table.get(b"unknown")?;
+        let unknown_v = mem_table.get(b"unknown", u64::MAX)?;
         assert_eq!(unknown_v, None);
 
         Ok(())
     }
+
+    #[test]
+    fn test_mem_table_factories() -> anyhow::Result<()> {
+        let tempdir = tempdir()?;
+        for (id, memtable_factory) in [
+            MemTableFactory::Vector,
+            MemTableFactory::HashSkipList {
+                prefix_len: 4,
+                bucket_count: 8,
+            },
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            let options = MemTableOptions {
+                memtable_factory,
+                ..MemTableOptions::default()
+            };
+            let dir = tempdir.path().to_path_buf();
+            let mem_table = Arc::new(MemTable::open(dir, id as u64, &options)?);
+            for i in (0..=100).rev() {
+                let k = format!("k_{:01$}", i, 3);
+                mem_table.set(k.as_bytes(), b"v", &WriteOptions::default())?;
+            }
+
+            let from = Some(b"k_010".to_vec());
+            let to = Some(b"k_020".to_vec());
+            let keys = mem_table
+                .scan(&from, &to)
+                .map(|result| result.map(|(k, _)| k))
+                .collect::<Result<Vec<_>, _>>()?;
+            let expected_keys = (10..20)
+                .map(|i| format!("k_{:01$}", i, 3).into_bytes())
+                .collect::<Vec<_>>();
+            assert_eq!(keys, expected_keys, "{memtable_factory:?}");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_mem_table_size_bytes() -> anyhow::Result<()> {
+        let tempdir = tempdir()?;
+        let dir = tempdir.path().to_path_buf();
+        let options = MemTableOptions::default();
+        let entry_size = |k: &str, v: &str| ENTRY_OVERHEAD + k.len() + v.len();
+        let expected_size_bytes = {
+            let mem_table = MemTable::open(dir.clone(), 1, &options)?;
+            mem_table.set(b"k_1", b"v_1", &WriteOptions::default())?;
+            mem_table.set(b"k_2", b"v_2", &WriteOptions::default())?;
+            assert_eq!(mem_table.size_bytes(), 2 * entry_size("k_1", "v_1"));
+
+            // overwrites add a version of the key
+            mem_table.set(b"k_1", b"value_1", &WriteOptions::default())?;
+            mem_table.set(b"k_2", b"", &WriteOptions::default())?;
+            let expected_size_bytes =
+                2 * entry_size("k_1", "v_1") + entry_size("k_1", "value_1") + entry_size("k_2", "");
+            assert_eq!(mem_table.size_bytes(), expected_size_bytes);
+            assert_eq!(mem_table.num_entries(), 2);
+            expected_size_bytes
+        };
+
+        // replaying the wal restores the same size
+        let mem_table = MemTable::open(dir, 1, &options)?;
+        assert_eq!(mem_table.size_bytes(), expected_size_bytes);
+        Ok(())
+    }
 }
@@ -0,0 +1,690 @@
+use std::{
+    cmp::Ordering,
+    collections::{hash_map::DefaultHasher, HashSet},
+    fmt::Debug,
+    hash::{Hash, Hasher},
+    ops::Bound,
+    sync::{
+        atomic::{AtomicUsize, Ordering as AtomicOrdering},
+        Arc,
+    },
+};
+
+use crossbeam_skiplist::{map::Entry, SkipMap};
+use parking_lot::{Mutex, RwLock};
+
+use crate::{Key, RefKey, RefValue, Value};
+
+/// A version of a key, numbered in write order by its mem_table.
+///
+/// Versions are ordered by key, then from the newest to the oldest.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct InternalKey {
+    pub key: Key,
+    pub sequence_number: u64,
+}
+
+impl InternalKey {
+    pub fn new(key: Key, sequence_number: u64) -> Self {
+        Self {
+            key,
+            sequence_number,
+        }
+    }
+}
+
+impl Ord for InternalKey {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.key
+            .cmp(&other.key)
+            .then_with(|| other.sequence_number.cmp(&self.sequence_number))
+    }
+}
+
+impl PartialOrd for InternalKey {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// The in-memory structure holding the entries of a mem_table.
+///
+/// Every write adds a version of its key, so readers never miss a key
+/// being overwritten and snapshots read the versions older than them.
+pub(crate) trait MemTableRep: Send + Sync + Debug {
+    /// Inserts a version of `key`, returns whether the key had none yet.
+    fn insert(&self, key: Key, sequence_number: u64, value: Value) -> bool;
+    /// Returns the latest value of `key` written at or before `sequence_number`.
+    fn get(&self, key: RefKey, sequence_number: u64) -> Option<Value>;
+    /// Returns the number of distinct keys.
+    fn num_entries(&self) -> usize;
+    /// Returns an unpositioned cursor over the latest value of every key
+    /// written at or before `sequence_number`, in key order.
+    fn cursor(&self, sequence_number: u64) -> Box<dyn MemTableRepCursor + '_>;
+}
+
+/// A bidirectional cursor over the entries of a MemTableRep.
+pub(crate) trait MemTableRepCursor: Send {
+    fn valid(&self) -> bool;
+    /// Positions the cursor on the first entry within `bound`.
+    fn seek(&mut self, bound: Bound<RefKey>);
+    /// Positions the cursor on the last entry within `bound`.
+    fn seek_for_prev(&mut self, bound: Bound<RefKey>);
+    fn next(&mut self);
+    fn prev(&mut self);
+    fn key(&self) -> RefKey<'_>;
+    fn value(&self) -> RefValue<'_>;
+}
+
+/// A cursor over every version held by a MemTableRep.
+trait VersionCursor: Send {
+    fn valid(&self) -> bool;
+    /// Positions the cursor on the first version within `bound`.
+    fn seek(&mut self, bound: Bound<&InternalKey>);
+    /// Positions the cursor on the last version within `bound`.
+    fn seek_for_prev(&mut self, bound: Bound<&InternalKey>);
+    fn next(&mut self);
+    fn key(&self) -> &InternalKey;
+    fn value(&self) -> RefValue<'_>;
+}
+
+/// Exposes the latest version of every key written at or before `sequence_number`.
+struct VisibleVersions<C> {
+    versions: C,
+    sequence_number: u64,
+}
+
+impl<C: VersionCursor> VisibleVersions<C> {
+    fn new(versions: C, sequence_number: u64) -> Self {
+        Self {
+            versions,
+            sequence_number,
+        }
+    }
+
+    /// Moves forward to the first visible version, from a key boundary.
+    fn settle_forward(&mut self) {
+        while self.versions.valid() && self.versions.key().sequence_number > self.sequence_number {
+            let target = InternalKey::new(self.versions.key().key.clone(), self.sequence_number);
+            self.versions.seek(Bound::Included(&target));
+        }
+    }
+
+    /// Moves backward to the latest visible version of the key under the cursor,
+    /// or of a previous key when every version of it is too recent.
+    fn settle_backward(&mut self) {
+        while self.versions.valid() {
+            let key = self.versions.key().key.clone();
+            let target = InternalKey::new(key, self.sequence_number);
+            self.versions.seek(Bound::Included(&target));
+            if self.versions.valid() && self.versions.key().key == target.key {
+                return;
+            }
+            let target = InternalKey::new(target.key, u64::MAX);
+            self.versions.seek_for_prev(Bound::Excluded(&target));
+        }
+    }
+}
+
+impl<C: VersionCursor> MemTableRepCursor for VisibleVersions<C> {
+    fn valid(&self) -> bool {
+        self.versions.valid()
+    }
+
+    fn seek(&mut self, bound: Bound<RefKey>) {
+        match bound {
+            Bound::Included(key) => {
+                let target = InternalKey::new(key.to_vec(), self.sequence_number);
+                self.versions.seek(Bound::Included(&target));
+            }
+            // the oldest possible version of `key` comes last
+            Bound::Excluded(key) => {
+                self.versions
+                    .seek(Bound::Excluded(&InternalKey::new(key.to_vec(), 0)));
+            }
+            Bound::Unbounded => self.versions.seek(Bound::Unbounded),
+        }
+        self.settle_forward();
+    }
+
+    fn seek_for_prev(&mut self, bound: Bound<RefKey>) {
+        match bound {
+            Bound::Included(key) => {
+                self.versions
+                    .seek_for_prev(Bound::Included(&InternalKey::new(key.to_vec(), 0)));
+            }
+            // the newest possible version of `key` comes first
+            Bound::Excluded(key) => {
+                let target = InternalKey::new(key.to_vec(), u64::MAX);
+                self.versions.seek_for_prev(Bound::Excluded(&target));
+            }
+            Bound::Unbounded => self.versions.seek_for_prev(Bound::Unbounded),
+        }
+        self.settle_backward();
+    }
+
+    fn next(&mut self) {
+        if !self.versions.valid() {
+            return;
+        }
+        // skip the older versions of the current key
+        let key = self.versions.key().key.clone();
+        while self.versions.valid() && self.versions.key().key == key {
+            self.versions.next();
+        }
+        self.settle_forward();
+    }
+
+    fn prev(&mut self) {
+        if !self.versions.valid() {
+            return;
+        }
+        let target = InternalKey::new(self.versions.key().key.clone(), u64::MAX);
+        self.versions.seek_for_prev(Bound::Excluded(&target));
+        self.settle_backward();
+    }
+
+    fn key(&self) -> RefKey<'_> {
+        &self.versions.key().key
+    }
+
+    fn value(&self) -> RefValue<'_> {
+        self.versions.value()
+    }
+}
+
+/// Picks the MemTableRep of new mem_tables.
+#[non_exhaustive]
+#[derive(Clone, Copy, Debug, Default)]
+pub enum MemTableFactory {
+    /// Entries are kept sorted in a concurrent skiplist, it fits most workloads.
+    #[default]
+    SkipList,
+    /// Entries are appended to a vector and only sorted when read, it is meant for bulk
+    /// loads: reading keys as they get written sorts the vector over and over.
+    Vector,
+    /// Entries are spread over skiplists by their first `prefix_len` bytes,
+    /// it fits point lookups. Ordered reads merge every bucket.
+    HashSkipList {
+        prefix_len: usize,
+        bucket_count: usize,
+    },
+}
+
+impl MemTableFactory {
+    pub(crate) fn create(&self) -> Box<dyn MemTableRep> {
+        match self {
+            MemTableFactory::SkipList => Box::<SkipListRep>::default(),
+            MemTableFactory::Vector => Box::<VectorRep>::default(),
+            MemTableFactory::HashSkipList {
+                prefix_len,
+                bucket_count,
+            } => Box::new(HashSkipListRep::new(*prefix_len, *bucket_count)),
+        }
+    }
+}
+
+/// Returns the latest value of `key` in a skiplist of versions.
+fn get_version(
+    entries: &SkipMap<InternalKey, Value>,
+    key: RefKey,
+    sequence_number: u64,
+) -> Option<Value> {
+    let target = InternalKey::new(key.to_vec(), sequence_number);
+    entries
+        .lower_bound(Bound::Included(&target))
+        .filter(|entry| entry.key().key == key)
+        .map(|entry| entry.value().to_owned())
+}
+
+/// Returns whether a skiplist of versions holds any version of `key`.
+fn contains_key(entries: &SkipMap<InternalKey, Value>, key: RefKey) -> bool {
+    get_version(entries, key, u64::MAX).is_some()
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct SkipListRep {
+    entries: SkipMap<InternalKey, Value>,
+    // concurrent first writes of a key may both count it
+    num_keys: AtomicUsize,
+}
+
+impl MemTableRep for SkipListRep {
+    fn insert(&self, key: Key, sequence_number: u64, value: Value) -> bool {
+        let is_new_key = !contains_key(&self.entries, &key);
+        if is_new_key {
+            self.num_keys.fetch_add(1, AtomicOrdering::SeqCst);
+        }
+        self.entries
+            .insert(InternalKey::new(key, sequence_number), value);
+        is_new_key
+    }
+
+    fn get(&self, key: RefKey, sequence_number: u64) -> Option<Value> {
+        get_version(&self.entries, key, sequence_number)
+    }
+
+    fn num_entries(&self) -> usize {
+        self.num_keys.load(AtomicOrdering::SeqCst)
+    }
+
+    fn cursor(&self, sequence_number: u64) -> Box<dyn MemTableRepCursor + '_> {
+        let versions = SkipListCursor {
+            entries: &self.entries,
+            current: None,
+        };
+        Box::new(VisibleVersions::new(versions, sequence_number))
+    }
+}
+
+struct SkipListCursor<'a> {
+    entries: &'a SkipMap<InternalKey, Value>,
+    current: Option<Entry<'a, InternalKey, Value>>,
+}
+
+impl VersionCursor for SkipListCursor<'_> {
+    fn valid(&self) -> bool {
+        self.current.is_some()
+    }
+
+    fn seek(&mut self, bound: Bound<&InternalKey>) {
+        self.current = self.entries.lower_bound(bound);
+    }
+
+    fn seek_for_prev(&mut self, bound: Bound<&InternalKey>) {
+        self.current = self.entries.upper_bound(bound);
+    }
+
+    fn next(&mut self) {
+        self.current = self.current.as_ref().and_then(|entry| entry.next());
+    }
+
+    fn key(&self) -> &InternalKey {
+        self.current
+            .as_ref()
+            .expect("Expected a valid cursor.")
+            .key()
+    }
+
+    fn value(&self) -> RefValue<'_> {
+        self.current
+            .as_ref()
+            .expect("Expected a valid cursor.")
+            .value()
+    }
+}
+
+// sorts share the entries instead of copying them
+type VectorEntry = Arc<(InternalKey, Value)>;
+type SortedEntries = Arc<Vec<VectorEntry>>;
+
+/// Appends entries unsorted, they get sorted into the others when read.
+///
+/// Looking up a key written since the last sort, as well as creating a
+/// cursor, sorts the pending entries first.
+#[derive(Debug, Default)]
+pub(crate) struct VectorRep {
+    state: RwLock<VectorState>,
+    // one sort at a time, concurrent readers wait for it rather than repeat it
+    sort_lock: Mutex<()>,
+}
+
+#[derive(Debug, Default)]
+struct VectorState {
+    sorted: SortedEntries,
+    // in insertion order
+    unsorted: Vec<VectorEntry>,
+    // the keys of `unsorted`, so that lookups of other keys skip sorting
+    unsorted_keys: HashSet<Key>,
+    num_keys: usize,
+}
+
+/// Returns the position of the latest version of `key` written at or before `sequence_number`.
+fn find_version(entries: &[VectorEntry], key: RefKey, sequence_number: u64) -> Option<usize> {
+    let position = entries.partition_point(|entry| match entry.0.key.as_slice().cmp(key) {
+        Ordering::Equal => entry.0.sequence_number > sequence_number,
+        ordering => ordering == Ordering::Less,
+    });
+    Some(position)
+        .filter(|position| matches!(entries.get(*position), Some(entry) if entry.0.key == key))
+}
+
+impl VectorRep {
+    /// Sorts the pending entries, then merges them into the sorted ones. Writers are
+    /// only held back while the pending entries are listed and once the merge is ready.
+    fn sorted_entries(&self) -> SortedEntries {
+        let _sorting = self.sort_lock.lock();
+        let (base, mut pending) = {
+            let state = self.state.read();
+            if state.unsorted.is_empty() {
+                return state.sorted.clone();
+            }
+            (state.sorted.clone(), state.unsorted.clone())
+        };
+        let num_pending = pending.len();
+        pending.sort_by(|a, b| a.0.cmp(&b.0));
+        let mut merged = Vec::with_capacity(base.len() + num_pending);
+        let mut base_iter = base.iter().cloned().peekable();
+        for entry in pending {
+            while let Some(base_entry) = base_iter.next_if(|base_entry| base_entry.0 < entry.0) {
+                merged.push(base_entry);
+            }
+            merged.push(entry);
+        }
+        merged.extend(base_iter);
+
+        let mut state = self.state.write();
+        state.sorted = Arc::new(merged);
+        state.unsorted.drain(..num_pending);
+        let unsorted_keys = state
+            .unsorted
+            .iter()
+            .map(|entry| entry.0.key.clone())
+            .collect();
+        state.unsorted_keys = unsorted_keys;
+        state.sorted.clone()
+    }
+}
+
+impl MemTableRep for VectorRep {
+    fn insert(&self, key: Key, sequence_number: u64, value: Value) -> bool {
+        let mut state = self.state.write();
+        let is_new_to_unsorted = state.unsorted_keys.insert(key.clone());
+        let is_new_key =
+            is_new_to_unsorted && find_version(&state.sorted, &key, u64::MAX).is_none();
+        if is_new_key {
+            state.num_keys += 1;
+        }
+        state
+            .unsorted
+            .push(Arc::new((InternalKey::new(key, sequence_number), value)));
+        is_new_key
+    }
+
+    fn get(&self, key: RefKey, sequence_number: u64) -> Option<Value> {
+        let entries = {
+            let state = self.state.read();
+            if state.unsorted_keys.contains(key) {
+                None
+            } else {
+                Some(state.sorted.clone())
+            }
+        };
+        let entries = entries.unwrap_or_else(|| self.sorted_entries());
+        find_version(&entries, key, sequence_number).map(|position| entries[position].1.clone())
+    }
+
+    fn num_entries(&self) -> usize {
+        self.state.read().num_keys
+    }
+
+    fn cursor(&self, sequence_number: u64) -> Box<dyn MemTableRepCursor + '_> {
+        let versions = VectorCursor {
+            entries: self.sorted_entries(),
+            position: None,
+        };
+        Box::new(VisibleVersions::new(versions, sequence_number))
+    }
+}
+
+/// Reads the entries as they were sorted when the cursor was created.
+struct VectorCursor {
+    entries: SortedEntries,
+    position: Option<usize>,
+}
+
+impl VersionCursor for VectorCursor {
+    fn valid(&self) -> bool {
+        self.position.is_some()
+    }
+
+    fn seek(&mut self, bound: Bound<&InternalKey>) {
+        let position = match bound {
+            Bound::Included(key) => self.entries.partition_point(|entry| &entry.0 < key),
+            Bound::Excluded(key) => self.entries.partition_point(|entry| &entry.0 <= key),
+            Bound::Unbounded => 0,
+        };
+        self.position = Some(position).filter(|position| *position < self.entries.len());
+    }
+
+    fn seek_for_prev(&mut self, bound: Bound<&InternalKey>) {
+        let end = match bound {
+            Bound::Included(key) => self.entries.partition_point(|entry| &entry.0 <= key),
+            Bound::Excluded(key) => self.entries.partition_point(|entry| &entry.0 < key),
+            Bound::Unbounded => self.entries.len(),
+        };
+        self.position = end.checked_sub(1);
+    }
+
+    fn next(&mut self) {
+        self.position = self
+            .position
+            .map(|position| position + 1)
+            .filter(|position| *position < self.entries.len());
+    }
+
+    fn key(&self) -> &InternalKey {
+        &self.entries[self.position.expect("Expected a valid cursor.")].0
+    }
+
+    fn value(&self) -> RefValue<'_> {
+        &self.entries[self.position.expect("Expected a valid cursor.")].1
+    }
+}
+
+/// Spreads entries over skiplists by hashing their prefix.
+#[derive(Debug)]
+pub(crate) struct HashSkipListRep {
+    prefix_len: usize,
+    buckets: Vec<SkipMap<InternalKey, Value>>,
+    // concurrent first writes of a key may both count it
+    num_keys: AtomicUsize,
+}
+
+impl HashSkipListRep {
+    pub fn new(prefix_len: usize, bucket_count: usize) -> Self {
+        Self {
+            prefix_len,
+            buckets: (0..bucket_count.max(1)).map(|_| SkipMap::new()).collect(),
+            num_keys: AtomicUsize::new(0),
+        }
+    }
+
+    fn bucket(&self, key: RefKey) -> &SkipMap<InternalKey, Value> {
+        let prefix = &key[..key.len().min(self.prefix_len)];
+        let mut hasher = DefaultHasher::new();
+        prefix.hash(&mut hasher);
+        &self.buckets[(hasher.finish() % self.buckets.len() as u64) as usize]
+    }
+}
+
+impl MemTableRep for HashSkipListRep {
+    fn insert(&self, key: Key, sequence_number: u64, value: Value) -> bool {
+        let bucket = self.bucket(&key);
+        let is_new_key = !contains_key(bucket, &key);
+        if is_new_key {
+            self.num_keys.fetch_add(1, AtomicOrdering::SeqCst);
+        }
+        bucket.insert(InternalKey::new(key, sequence_number), value);
+        is_new_key
+    }
+
+    fn get(&self, key: RefKey, sequence_number: u64) -> Option<Value> {
+        get_version(self.bucket(key), key, sequence_number)
+    }
+
+    fn num_entries(&self) -> usize {
+        self.num_keys.load(AtomicOrdering::SeqCst)
+    }
+
+    fn cursor(&self, sequence_number: u64) -> Box<dyn MemTableRepCursor + '_> {
+        let versions = HashSkipListCursor {
+            buckets: &self.buckets,
+            current: None,
+        };
+        Box::new(VisibleVersions::new(versions, sequence_number))
+    }
+}
+
+/// Merges the buckets, every move looks the neighbouring version up in each of them.
+struct HashSkipListCursor<'a> {
+    buckets: &'a [SkipMap<InternalKey, Value>],
+    current: Option<Entry<'a, InternalKey, Value>>,
+}
+
+impl VersionCursor for HashSkipListCursor<'_> {
+    fn valid(&self) -> bool {
+        self.current.is_some()
+    }
+
+    fn seek(&mut self, bound: Bound<&InternalKey>) {
+        self.current = self
+            .buckets
+            .iter()
+            .filter_map(|bucket| bucket.lower_bound(bound))
+            .min_by(|a, b| a.key().cmp(b.key()));
+    }
+
+    fn seek_for_prev(&mut self, bound: Bound<&InternalKey>) {
+        self.current = self
+            .buckets
+            .iter()
+            .filter_map(|bucket| bucket.upper_bound(bound))
+            .max_by(|a, b| a.key().cmp(b.key()));
+    }
+
+    fn next(&mut self) {
+        if let Some(entry) = self.current.take() {
+            self.seek(Bound::Excluded(entry.key()));
+        }
+    }
+
+    fn key(&self) -> &InternalKey {
+        self.current
+            .as_ref()
+            .expect("Expected a valid cursor.")
+            .key()
+    }
+
+    fn value(&self) -> RefValue<'_> {
+        self.current
+            .as_ref()
+            .expect("Expected a valid cursor.")
+            .value()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{ops::Bound, sync::Arc};
+
+    use crate::mem_table_rep::{MemTableFactory, MemTableRep, VectorRep};
+
+    fn collect(rep: &dyn MemTableRep, sequence_number: u64) -> Vec<(Vec<u8>, Vec<u8>)> {
+        let mut cursor = rep.cursor(sequence_number);
+        let mut entries = vec![];
+        cursor.seek(Bound::Unbounded);
+        while cursor.valid() {
+            entries.push((cursor.key().to_vec(), cursor.value().to_vec()));
+            cursor.next();
+        }
+        entries
+    }
+
+    #[test]
+    fn test_mem_table_reps() {
+        for factory in [
+            MemTableFactory::SkipList,
+            MemTableFactory::Vector,
+            MemTableFactory::HashSkipList {
+                prefix_len: 3,
+                bucket_count: 4,
+            },
+        ] {
+            let rep = factory.create();
+            for (i, sequence_number) in (0..100).rev().zip(1..) {
+                let k = format!("k{}_{:03}", i % 5, i);
+                assert!(rep.insert(k.into_bytes(), sequence_number, b"v1".to_vec()));
+            }
+            assert!(!rep.insert(b"k0_000".to_vec(), 101, b"v2".to_vec()));
+            assert_eq!(rep.num_entries(), 100, "{factory:?}");
+            assert_eq!(
+                rep.get(b"k0_000", u64::MAX),
+                Some(b"v2".to_vec()),
+                "{factory:?}"
+            );
+            assert_eq!(rep.get(b"k0_001", u64::MAX), None, "{factory:?}");
+
+            // entries come out sorted with the latest value of every key
+            let entries = collect(rep.as_ref(), u64::MAX);
+            assert_eq!(entries.len(), 100, "{factory:?}");
+            assert!(entries.windows(2).all(|pair| pair[0].0 < pair[1].0));
+            assert_eq!(entries[0], (b"k0_000".to_vec(), b"v2".to_vec()));
+
+            // older sequence numbers read older versions and miss later keys
+            assert_eq!(rep.get(b"k0_000", 100), Some(b"v1".to_vec()), "{factory:?}");
+            assert_eq!(rep.get(b"k0_000", 99), None, "{factory:?}");
+            let entries = collect(rep.as_ref(), 100);
+            assert_eq!(entries.len(), 100, "{factory:?}");
+            assert_eq!(entries[0], (b"k0_000".to_vec(), b"v1".to_vec()));
+            let entries = collect(rep.as_ref(), 50);
+            assert_eq!(entries.len(), 50, "{factory:?}");
+            let mut cursor = rep.cursor(50);
+            cursor.seek(Bound::Included(b"k0_045"));
+            assert_eq!(cursor.key(), b"k0_050");
+            cursor.seek_for_prev(Bound::Included(b"k1_046"));
+            assert_eq!(cursor.key(), b"k0_095");
+            cursor.seek_for_prev(Bound::Unbounded);
+            assert_eq!(cursor.key(), b"k4_099");
+            cursor.prev();
+            assert_eq!(cursor.key(), b"k4_094");
+
+            // bounded seeks in both directions
+            let mut cursor = rep.cursor(u64::MAX);
+            cursor.seek(Bound::Excluded(b"k0_000"));
+            assert_eq!(cursor.key(), b"k0_005");
+            cursor.prev();
+            assert_eq!(cursor.key(), b"k0_000");
+            cursor.prev();
+            assert!(!cursor.valid());
+            cursor.seek_for_prev(Bound::Included(b"k1_050"));
+            assert_eq!(cursor.key(), b"k1_046");
+            cursor.seek_for_prev(Bound::Excluded(b"k1_046"));
+            assert_eq!(cursor.key(), b"k1_041");
+            cursor.seek_for_prev(Bound::Unbounded);
+            assert_eq!(cursor.key(), b"k4_099");
+            cursor.next();
+            assert!(!cursor.valid());
+        }
+    }
+
+    #[test]
+    fn test_vector_rep_sorts_pending_entries_on_read() {
+        let rep = VectorRep::default();
+        for (i, sequence_number) in (0..10).zip(1..) {
+            let k = format!("k_{i}");
+            rep.insert(k.into_bytes(), sequence_number, b"v1".to_vec());
+        }
+        assert_eq!(collect(&rep, u64::MAX).len(), 10);
+        assert!(rep.state.read().unsorted.is_empty());
+
+        // keys written since the last sort are sorted in when looked up
+        rep.insert(b"k_1".to_vec(), 11, b"v2".to_vec());
+        rep.insert(b"k_10".to_vec(), 12, b"v2".to_vec());
+        assert_eq!(rep.get(b"k_2", u64::MAX), Some(b"v1".to_vec()));
+        assert_eq!(rep.state.read().unsorted.len(), 2);
+        let sorted = rep.state.read().sorted.clone();
+        assert_eq!(rep.get(b"k_1", u64::MAX), Some(b"v2".to_vec()));
+        assert!(rep.state.read().unsorted.is_empty());
+
+        // the entries sorted before are shared, not copied
+        let resorted = rep.state.read().sorted.clone();
+        assert_eq!(resorted.len(), 12);
+        assert!(Arc::ptr_eq(&sorted[0], &resorted[0]));
+        assert!(rep.state.read().unsorted_keys.is_empty());
+
+        // overwrites are not counted as new keys
+        assert_eq!(rep.num_entries(), 11);
+        assert_eq!(rep.get(b"k_1", 10), Some(b"v1".to_vec()));
+    }
+}
@@ -1,41 +1,180 @@
-use crate::{compactor::CompactorPolicyConfig, controller::MemTableControllerPolicyConfig};
+use crate::{
+    block_cache::BlockCache,
+    bloom_filter::BloomFilter,
+    compactor::CompactorPolicyConfig,
+    controller::MemTableControllerPolicyConfig,
+    mem_table_rep::MemTableFactory,
+    snapshot::Snapshot,
+    wal::{WalRecoveryMode, WalSyncMode},
+    wal_archive::WalArchiveOptions,
+    write_buffer_manager::WriteBufferManager,
+    Key,
+};
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Debug)]
 pub struct LiteDbOptions {
-    pub bloom_filter_size_bytes: usize,
-    pub bloom_filter_item_count: usize,
+    /// Bits of bloom filter per ss_table entry, 10 gives a ~1% false positive rate.
+    pub bloom_bits_per_key: usize,
+    /// Total bloom filter memory in bytes, split across levels to minimize lookup I/O.
+    /// When set, it takes over `bloom_bits_per_key`. A filter is sized when its ss_table
+    /// is flushed or compacted, the others keep theirs as the levels grow.
+    pub bloom_filter_memory_budget: Option<usize>,
     pub sparse_index_range_size: usize,
+    /// Persists a range filter in every flushed or compacted ss_table so that short
+    /// range scans skip the ss_tables holding no key in range. Files written by a
+    /// `SstFileWriter` have none until compacted.
+    pub range_filter: bool,
     pub mem_table_controller_policy: MemTableControllerPolicyConfig,
+    /// Structure holding the entries of new mem_tables.
+    pub memtable_factory: MemTableFactory,
     pub compactor_policy: CompactorPolicyConfig,
+    pub wal_sync_mode: WalSyncMode,
+    /// How replaying a damaged wal on open is handled.
+    pub wal_recovery_mode: WalRecoveryMode,
+    /// Maximum number of mem_tables, the current one included, at least 2. Writes
+    /// stop when the current mem_table is full and no more can be created.
+    pub max_write_buffer_number: usize,
+    /// Number of level 0 ss_tables from which writes are slowed down.
+    pub level0_slowdown_writes_trigger: usize,
+    /// Number of level 0 ss_tables from which writes stop until compaction catches up.
+    /// At least the number of ss_tables a compaction merges, 4 with `SizeTiered`.
+    pub level0_stop_writes_trigger: usize,
+    /// Bounds the mem_table memory of every database sharing it.
+    pub write_buffer_manager: Option<WriteBufferManager>,
+    /// Keeps the wals of flushed mem_tables for point-in-time restores.
+    pub wal_archive: Option<WalArchiveOptions>,
+    /// Values longer than this are kept in blob files, ss_tables only point at them
+    /// so that compactions do not rewrite them.
+    pub min_blob_size: Option<usize>,
+    /// Splits the index of new ss_tables in partitions of about this many bytes,
+    /// only the top level stays in memory and the partitions are read when needed.
+    pub index_partition_size: Option<usize>,
+    /// Keeps the recently read index partitions in memory, can be shared by several databases.
+    pub block_cache: Option<BlockCache>,
+    /// Keeps at most this many ss_tables open, the others only keep their key
+    /// range in memory and are opened on first access. All stay open when None.
+    pub max_open_files: Option<usize>,
 }
 
 impl Default for LiteDbOptions {
     fn default() -> Self {
         Self {
-            bloom_filter_size_bytes: 3_000_000, // 3MB
-            bloom_filter_item_count: 100_000_000,
+            bloom_bits_per_key: 10,
+            bloom_filter_memory_budget: None,
             sparse_index_range_size: 1_000,
+            range_filter: false,
             mem_table_controller_policy: MemTableControllerPolicyConfig::SizeTiered {
                 max_entries: 500_000,
                 max_size_bytes: 3_000_000, // 3MB
             },
+            memtable_factory: MemTableFactory::SkipList,
             compactor_policy: CompactorPolicyConfig::SizeTiered,
+            wal_sync_mode: WalSyncMode::None,
+            wal_recovery_mode: WalRecoveryMode::PointInTime,
+            max_write_buffer_number: 2,
+            level0_slowdown_writes_trigger: 20,
+            level0_stop_writes_trigger: 36,
+            write_buffer_manager: None,
+            wal_archive: None,
+            min_blob_size: None,
+            index_partition_size: None,
+            block_cache: None,
+            max_open_files: None,
         }
     }
 }
 
 impl LiteDbOptions {
+    /// Returns the `bloom_bits_per_key` needed to reach a target false positive rate.
+    pub fn bloom_bits_per_key_for_fp_rate(fp_rate: f64) -> usize {
+        BloomFilter::bits_per_key_for_fp_rate(fp_rate)
+    }
+
     #[cfg(test)]
     pub fn for_test() -> Self {
         Self {
-            bloom_filter_size_bytes: 3_000_000, // 3MB
-            bloom_filter_item_count: 100_000_000,
+            bloom_bits_per_key: 10,
+            bloom_filter_memory_budget: None,
             sparse_index_range_size: 40,
+            range_filter: true,
             mem_table_controller_policy: MemTableControllerPolicyConfig::SizeTiered {
                 max_entries: 200,
                 max_size_bytes: 7000,
             },
+            memtable_factory: MemTableFactory::SkipList,
             compactor_policy: CompactorPolicyConfig::SizeTiered,
+            wal_sync_mode: WalSyncMode::None,
+            wal_recovery_mode: WalRecoveryMode::PointInTime,
+            max_write_buffer_number: 2,
+            level0_slowdown_writes_trigger: 20,
+            level0_stop_writes_trigger: 36,
+            write_buffer_manager: None,
+            wal_archive: None,
+            min_blob_size: None,
+            index_partition_size: None,
+            block_cache: None,
+            max_open_files: None,
         }
     }
 }
+
+/// Settings of a single write.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct WriteOptions {
+    /// Syncs the wal before returning, whatever the `wal_sync_mode`.
+    pub sync: bool,
+    /// Skips the wal, the write is lost if the process stops before it is flushed.
+    pub disable_wal: bool,
+    /// Fails instead of waiting when writes are stalled.
+    pub no_slowdown: bool,
+}
+
+/// Settings of an ingestion of external ss_table files.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct IngestOptions {
+    /// Removes the files once ingested, the database keeps copies of them.
+    pub move_files: bool,
+    /// Lets the files overlap the data of the database, their entries then
+    /// take precedence. Without it, an overlap fails the ingestion.
+    pub allow_global_seqno: bool,
+}
+
+/// Settings of a single read.
+#[derive(Clone, Debug)]
+pub struct ReadOptions {
+    /// Reads the database as it was when the snapshot was taken.
+    pub snapshot: Option<Snapshot>,
+    /// Verifies the checksum of every ss_table entry read.
+    pub verify_checksums: bool,
+    /// When false, the ss_table pages loaded by the read are released once it
+    /// completes, so that one-off scans do not grow the resident memory.
+    pub fill_cache: bool,
+    /// Inclusive lower bound of `scan_opt`.
+    pub iterate_lower_bound: Option<Key>,
+    /// Exclusive upper bound of `scan_opt`.
+    pub iterate_upper_bound: Option<Key>,
+    /// Bytes of ss_table data to read ahead while scanning, 0 disables it.
+    pub readahead_size: usize,
+}
+
+impl Default for ReadOptions {
+    fn default() -> Self {
+        Self {
+            snapshot: None,
+            verify_checksums: true,
+            fill_cache: true,
+            iterate_lower_bound: None,
+            iterate_upper_bound: None,
+            readahead_size: 0,
+        }
+    }
+}
+
+impl ReadOptions {
+    /// Returns the sequence number bounding the mem_table entries to read.
+    pub(crate) fn sequence_number(&self) -> u64 {
+        self.snapshot
+            .as_ref()
+            .map_or(u64::MAX, |snapshot| snapshot.sequence_number)
+    }
+}
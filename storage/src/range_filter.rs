@@ -0,0 +1,320 @@
+use bincode::{Decode, Encode};
+
+use crate::{Key, RefKey};
+
+// every this many prefixes, one is stored whole for lookups to start from
+const RESTART_INTERVAL: usize = 16;
+
+/// A succinct range filter in the spirit of SuRF-Base.
+///
+/// Every key is truncated to the shortest prefix that still distinguishes
+/// it from its neighbours, these are the leaves of the pruned trie of the keys.
+/// Truncated keys keep the key order, so a range query only looks at the
+/// first truncated key that may stand for a key at or after the lower bound.
+/// The answer can be a false positive but never a false negative.
+///
+/// Prefixes are front coded into a single buffer, each one only stores what
+/// differs from the previous one, the shared part being the path down the trie.
+#[derive(Debug, Default)]
+pub(crate) struct RangeFilter {
+    // per prefix: the length shared with the previous one and the length
+    // of the remaining bytes as varints, then the remaining bytes
+    data: Vec<u8>,
+    // the offsets of the prefixes stored whole
+    restarts: Vec<u32>,
+}
+
+impl RangeFilter {
+    /// Returns false when no key of the filter lies in `[from, to)`.
+    pub fn may_contain_range(&self, from: &Option<Key>, to: &Option<Key>) -> bool {
+        let candidate = match from {
+            None => self.prefixes(0).next_prefix().map(<[u8]>::to_vec),
+            Some(first_key) => {
+                // the first prefix at or after the bound follows the last restart before it
+                let restart = self
+                    .restarts
+                    .partition_point(|offset| self.restart_prefix(*offset) < first_key.as_slice())
+                    .saturating_sub(1);
+                let mut prefixes = self.prefixes(restart);
+                let mut previous: Option<Key> = None;
+                loop {
+                    match prefixes.next_prefix() {
+                        Some(prefix) if prefix < first_key.as_slice() => {
+                            previous = Some(prefix.to_vec());
+                        }
+                        next => {
+                            // a truncated key prefixing the bound may stand for a larger key
+                            break match previous {
+                                Some(previous) if first_key.starts_with(&previous) => {
+                                    Some(previous)
+                                }
+                                _ => next.map(<[u8]>::to_vec),
+                            };
+                        }
+                    }
+                }
+            }
+        };
+        match (candidate, to) {
+            (None, _) => false,
+            (Some(_), None) => true,
+            (Some(prefix), Some(last_key)) => prefix < *last_key,
+        }
+    }
+
+    /// Returns the prefixes from the `restart`-th prefix stored whole.
+    fn prefixes(&self, restart: usize) -> Prefixes<'_> {
+        let offset = self
+            .restarts
+            .get(restart)
+            .map_or(self.data.len(), |offset| *offset as usize);
+        Prefixes {
+            data: &self.data,
+            offset,
+            prefix: vec![],
+        }
+    }
+
+    fn restart_prefix(&self, offset: u32) -> RefKey<'_> {
+        let mut offset = offset as usize;
+        let _shared_len = read_varint(&self.data, &mut offset);
+        let suffix_len = read_varint(&self.data, &mut offset);
+        &self.data[offset..offset + suffix_len]
+    }
+}
+
+/// Decodes the prefixes of a `RangeFilter` in order.
+struct Prefixes<'a> {
+    data: &'a [u8],
+    offset: usize,
+    prefix: Key,
+}
+
+impl Prefixes<'_> {
+    fn next_prefix(&mut self) -> Option<RefKey<'_>> {
+        if self.offset >= self.data.len() {
+            return None;
+        }
+        let shared_len = read_varint(self.data, &mut self.offset);
+        let suffix_len = read_varint(self.data, &mut self.offset);
+        self.prefix.truncate(shared_len);
+        self.prefix
+            .extend_from_slice(&self.data[self.offset..self.offset + suffix_len]);
+        self.offset += suffix_len;
+        Some(&self.prefix)
+    }
+}
+
+/// Front codes prefixes added in ascending order into a `RangeFilter`.
+#[derive(Default)]
+struct PrefixWriter {
+    range_filter: RangeFilter,
+    num_prefixes: usize,
+    previous_prefix: Key,
+}
+
+impl PrefixWriter {
+    fn add(&mut self, prefix: RefKey) {
+        let shared_len = match self.num_prefixes % RESTART_INTERVAL {
+            0 => {
+                let offset = self.range_filter.data.len() as u32;
+                self.range_filter.restarts.push(offset);
+                0
+            }
+            _ => common_prefix_len(&self.previous_prefix, prefix),
+        };
+        let data = &mut self.range_filter.data;
+        write_varint(shared_len, data);
+        write_varint(prefix.len() - shared_len, data);
+        data.extend_from_slice(&prefix[shared_len..]);
+        self.previous_prefix.truncate(shared_len);
+        self.previous_prefix
+            .extend_from_slice(&prefix[shared_len..]);
+        self.num_prefixes += 1;
+    }
+
+    fn finish(self) -> RangeFilter {
+        self.range_filter
+    }
+}
+
+/// Builds a `RangeFilter` from keys added in ascending order.
+#[derive(Default)]
+pub(crate) struct RangeFilterBuilder {
+    prefixes: PrefixWriter,
+    previous_key: Option<Key>,
+    previous_common_prefix_len: usize,
+}
+
+impl RangeFilterBuilder {
+    pub fn add(&mut self, key: RefKey) {
+        if let Some(previous_key) = self.previous_key.take() {
+            let common_prefix_len = common_prefix_len(&previous_key, key);
+            self.add_truncated(&previous_key, common_prefix_len);
+            self.previous_common_prefix_len = common_prefix_len;
+        }
+        self.previous_key = Some(key.to_vec());
+    }
+
+    pub fn finish(mut self) -> RangeFilter {
+        if let Some(previous_key) = self.previous_key.take() {
+            self.add_truncated(&previous_key, 0);
+        }
+        self.prefixes.finish()
+    }
+
+    /// Keeps one byte past the longest prefix shared with a neighbour.
+    fn add_truncated(&mut self, key: RefKey, next_common_prefix_len: usize) {
+        let len = self.previous_common_prefix_len.max(next_common_prefix_len) + 1;
+        self.prefixes.add(&key[..len.min(key.len())]);
+    }
+}
+
+fn common_prefix_len(a: RefKey, b: RefKey) -> usize {
+    a.iter().zip(b.iter()).take_while(|(x, y)| x == y).count()
+}
+
+fn write_varint(mut value: usize, data: &mut Vec<u8>) {
+    while value >= 0x80 {
+        data.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    data.push(value as u8);
+}
+
+fn read_varint(data: &[u8], offset: &mut usize) -> usize {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let byte = data[*offset];
+        *offset += 1;
+        value |= ((byte & 0x7f) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return value;
+        }
+        shift += 7;
+    }
+}
+
+/// The persisted form of a `RangeFilter`.
+///
+/// Each prefix is front coded against the previous one, without restarts.
+#[derive(Encode, Decode)]
+pub(crate) struct RangeFilterState {
+    // (length shared with the previous prefix, remaining bytes)
+    prefixes: Vec<(u32, Vec<u8>)>,
+}
+
+impl From<&RangeFilter> for RangeFilterState {
+    fn from(range_filter: &RangeFilter) -> Self {
+        let mut prefixes = vec![];
+        let mut previous = vec![];
+        let mut iter = range_filter.prefixes(0);
+        while let Some(prefix) = iter.next_prefix() {
+            let shared_len = common_prefix_len(&previous, prefix);
+            prefixes.push((shared_len as u32, prefix[shared_len..].to_vec()));
+            previous = prefix.to_vec();
+        }
+        Self { prefixes }
+    }
+}
+
+impl From<RangeFilterState> for RangeFilter {
+    fn from(state: RangeFilterState) -> Self {
+        let mut writer = PrefixWriter::default();
+        let mut prefix = vec![];
+        for (shared_len, suffix) in state.prefixes {
+            prefix.truncate(shared_len as usize);
+            prefix.extend_from_slice(&suffix);
+            writer.add(&prefix);
+        }
+        writer.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RangeFilter, RangeFilterBuilder, RangeFilterState, RESTART_INTERVAL};
+
+    fn prefixes(range_filter: &RangeFilter) -> Vec<Vec<u8>> {
+        let mut prefixes = vec![];
+        let mut iter = range_filter.prefixes(0);
+        while let Some(prefix) = iter.next_prefix() {
+            prefixes.push(prefix.to_vec());
+        }
+        prefixes
+    }
+
+    fn range(from: &str, to: &str) -> (Option<Vec<u8>>, Option<Vec<u8>>) {
+        let bound = |s: &str| (!s.is_empty()).then(|| s.as_bytes().to_vec());
+        (bound(from), bound(to))
+    }
+
+    #[test]
+    fn test_range_filter() {
+        let mut builder = RangeFilterBuilder::default();
+        for key in ["apple", "apricot", "banana", "blueberry", "cherry"] {
+            builder.add(key.as_bytes());
+        }
+        let range_filter = builder.finish();
+        assert_eq!(
+            prefixes(&range_filter),
+            vec![
+                b"app".to_vec(),
+                b"apr".to_vec(),
+                b"ba".to_vec(),
+                b"bl".to_vec(),
+                b"c".to_vec()
+            ]
+        );
+
+        // the filter survives encoding
+        let range_filter = RangeFilter::from(RangeFilterState::from(&range_filter));
+
+        let may_contain = |from: &str, to: &str| {
+            let (from, to) = range(from, to);
+            range_filter.may_contain_range(&from, &to)
+        };
+        assert!(may_contain("", ""));
+        assert!(may_contain("apple", "apple0"));
+        assert!(may_contain("b", "bb"));
+        assert!(may_contain("a", "b"));
+        assert!(may_contain("cherry", ""));
+        assert!(!may_contain("", "a"));
+        assert!(!may_contain("aq", "az"));
+        assert!(!may_contain("bm", "c"));
+        assert!(!may_contain("d", ""));
+    }
+
+    #[test]
+    fn test_range_filter_lookups_start_from_restarts() {
+        let keys = (0..1000)
+            .map(|i| format!("key_{:05}", i * 7))
+            .collect::<Vec<_>>();
+        let mut builder = RangeFilterBuilder::default();
+        for key in &keys {
+            builder.add(key.as_bytes());
+        }
+        let range_filter = builder.finish();
+        assert_eq!(range_filter.restarts.len(), 1000 / RESTART_INTERVAL + 1);
+        let keys_len: usize = keys.iter().map(String::len).sum();
+        assert!(range_filter.data.len() < keys_len / 2);
+
+        // the same answers as a binary search over every prefix
+        let range_filter = RangeFilter::from(RangeFilterState::from(&range_filter));
+        let all_prefixes = prefixes(&range_filter);
+        for i in 0..7000 {
+            let (from, to) = range(&format!("key_{:05}", i), &format!("key_{:05}", i + 1));
+            let (first_key, last_key) = (from.clone().unwrap(), to.clone().unwrap());
+            let idx = all_prefixes.partition_point(|prefix| *prefix < first_key);
+            let candidate = match idx.checked_sub(1) {
+                Some(prev) if first_key.starts_with(&all_prefixes[prev]) => prev,
+                _ => idx,
+            };
+            let expected =
+                matches!(all_prefixes.get(candidate), Some(prefix) if *prefix < last_key);
+            assert_eq!(range_filter.may_contain_range(&from, &to), expected);
+            assert!(expected || i % 7 != 0);
+        }
+    }
+}
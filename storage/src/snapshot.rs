@@ -0,0 +1,16 @@
+use std::sync::Arc;
+
+use crate::{mem_table::MemTable, ss_table::SSTable};
+
+/// A frozen view of the database, reads through it ignore later writes.
+///
+/// Taking a snapshot only keeps the tables alive: the active mem_table
+/// keeps every version of its keys, reads of the snapshot skip the ones
+/// written after its sequence number.
+#[derive(Clone, Debug)]
+pub struct Snapshot {
+    // both from oldest to newest
+    pub(crate) mem_tables: Vec<Arc<MemTable>>,
+    pub(crate) ss_tables: Vec<Arc<SSTable>>,
+    pub(crate) sequence_number: u64,
+}
@@ -1,25 +1,46 @@
 use std::{
     cmp::Ordering,
-    fs::File,
-    io::{BufReader, Seek, SeekFrom},
+    collections::BTreeMap,
+    fs::{self, File, OpenOptions},
+    io::{BufReader, BufWriter, Seek, SeekFrom, Write},
     mem,
     path::{Path, PathBuf},
-    sync::Arc,
+    sync::{
+        atomic::{self, AtomicU64},
+        Arc,
+    },
 };
 
 use bincode::{Decode, Encode};
-use bloomfilter::Bloom;
-use byteorder::{LittleEndian, ReadBytesExt};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+#[cfg(unix)]
+use memmap2::{Advice, UncheckedAdvice};
 use memmap2::{Mmap, MmapOptions};
+use parking_lot::Mutex;
 
 use crate::{
-    bloom_filter::BloomFilterState,
+    blob::{
+        blob_index, blob_value, inline_value, map_blob_files, read_blob, BlobFileId,
+        BlobFileWriter, BlobIndex,
+    },
+    block_cache::BlockCache,
+    bloom_filter::{BloomFilter, BloomFilterPolicy},
+    cursor::Cursor,
     error::LiteDbResult,
-    utils::{decode, decode_from_reader},
-    KVIterator, Key, LiteDbError, RefKey, Scannable, Value, TOMBSTONE,
+    options::ReadOptions,
+    range_filter::{RangeFilter, RangeFilterBuilder, RangeFilterState},
+    table_cache::TableCache,
+    utils::{crc32, decode, decode_from_reader, encode_into_writer},
+    KVIterator, Key, LiteDbError, RefKey, RefValue, Scannable, Value,
 };
 
-pub(crate) const SS_TABLE_FILE_EXTENSION: &str = "sst";
+const SS_TABLE_FILE_EXTENSION: &str = "sst";
+const SS_TABLE_TEMP_FILE_EXTENSION: &str = "sst.tmp";
+/// Ends every ss_table file, after its format version.
+const SS_TABLE_MAGIC: u64 = 0x5353_5442_444c_534c;
+const SS_TABLE_FORMAT_VERSION: u32 = 1;
+/// data size (8 bytes), format version (4 bytes), magic (8 bytes), at the end of the footer.
+const SS_TABLE_TRAILER_SIZE: usize = 8 + 4 + 8;
 
 pub(crate) fn is_ss_table_file(path: &Path) -> bool {
     path.is_file()
@@ -29,39 +50,82 @@ pub(crate) fn is_ss_table_file(path: &Path) -> bool {
             .unwrap_or(false)
 }
 
-#[derive(Debug, Encode, Decode)]
+/// An entry of the data section: key, stored value and the crc32 of both.
+///
+/// A stored value is tagged, it holds either the value or a pointer into a blob file.
+type SSTableEntry = (Key, Value, u32);
+
+#[derive(Debug, Clone, Encode, Decode)]
 pub(crate) struct SSTableMetadata {
     id: u64,                  // unique id
+    level: usize,             // level in the lsm tree, flushed tables are at 0
     first_key: (Key, Offset), // smallest key
     last_key: (Key, Offset),  // greatest key
     total_size: usize,        // total size in bytes
     num_entries: usize,       // number of entries
+    // bytes pointed at in each blob file
+    blob_references: BTreeMap<BlobFileId, u64>,
 }
 
 impl SSTableMetadata {
     pub(crate) fn new(
         id: u64,
+        level: usize,
         first_key: (Key, Offset),
         last_key: (Key, Offset),
         total_size: usize,
         num_entries: usize,
+        blob_references: BTreeMap<BlobFileId, u64>,
     ) -> Self {
         Self {
             id,
+            level,
             first_key,
             last_key,
             total_size,
             num_entries,
+            blob_references,
         }
     }
 }
 
+/// Caches the ss_tables of a database go through.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct SSTableCaches {
+    pub block_cache: Option<BlockCache>,
+    /// Opens the ss_tables on first access, they all stay open without it.
+    pub table_cache: Option<TableCache>,
+}
+
+static NEXT_CACHE_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Returns an id keeping the cached parts of an ss_table apart from the
+/// others, including from a previous version of the same file.
+fn next_cache_id() -> u64 {
+    NEXT_CACHE_ID.fetch_add(1, atomic::Ordering::Relaxed)
+}
+
+/// An ss_table, its metadata stays in memory while its file may be closed.
 #[derive(Debug)]
 pub(crate) struct SSTable {
     metadata: SSTableMetadata,
+    path: PathBuf,
+    /// Contents kept open until the ss_table is dropped, either because there
+    /// is no table cache or because its file is about to be replaced.
+    pinned: Mutex<Option<Arc<SSTableContents>>>,
+    caches: SSTableCaches,
+    cache_id: u64,
+}
+
+/// What an open ss_table holds: its mapped files and its decoded footer.
+#[derive(Debug)]
+pub(crate) struct SSTableContents {
+    // data section, followed by the index partitions if any
     file: Mmap,
-    index: SSTableSparseIndex,
-    bloom_filter: Bloom<Key>,
+    index: SSTableIndex,
+    bloom_filter: BloomFilter,
+    range_filter: Option<RangeFilter>,
+    blob_files: BTreeMap<BlobFileId, Mmap>,
 }
 
 impl Ord for SSTable {
@@ -85,65 +149,280 @@ impl PartialEq for SSTable {
 }
 
 impl SSTable {
-    pub fn new(
+    pub fn open(path: PathBuf) -> LiteDbResult<Self> {
+        Self::open_with_caches(path, SSTableCaches::default())
+    }
+
+    /// Opens the ss_table through `caches`.
+    ///
+    /// With a table cache, only the metadata is read and the file is opened on first access.
+    pub fn open_with_caches(path: PathBuf, caches: SSTableCaches) -> LiteDbResult<Self> {
+        if caches.table_cache.is_none() {
+            let (metadata, contents) = SSTableContents::open(&path)?;
+            return Ok(Self::with_contents(metadata, path, contents, caches));
+        }
+        let (_, _, metadata) = read_metadata(&path, &File::open(&path)?)?;
+        Ok(SSTable {
+            metadata,
+            path,
+            pinned: Mutex::new(None),
+            caches,
+            cache_id: next_cache_id(),
+        })
+    }
+
+    /// Returns the ss_table of contents just written or read.
+    fn with_contents(
         metadata: SSTableMetadata,
-        file: Mmap,
-        index: SSTableSparseIndex,
-        bloom_filter: Bloom<Key>,
+        path: PathBuf,
+        contents: SSTableContents,
+        caches: SSTableCaches,
     ) -> Self {
-        Self {
+        let cache_id = next_cache_id();
+        let contents = Arc::new(contents);
+        let pinned = match &caches.table_cache {
+            Some(table_cache) => {
+                table_cache.insert(cache_id, contents);
+                None
+            }
+            None => Some(contents),
+        };
+        SSTable {
             metadata,
-            file,
-            index,
-            bloom_filter,
+            path,
+            pinned: Mutex::new(pinned),
+            caches,
+            cache_id,
         }
     }
 
-    pub fn open(path: PathBuf) -> LiteDbResult<Self> {
-        let segment_file = File::open(path)?;
-        let mut reader = BufReader::new(&segment_file);
-        reader
-            .seek(SeekFrom::End(-(mem::size_of::<u64>() as i64)))
-            .unwrap();
-        let size_of_serialized_data: u64 = reader.read_u64::<LittleEndian>()?;
+    /// Returns the contents of the ss_table, opening its file if it is closed.
+    fn contents(&self) -> LiteDbResult<Arc<SSTableContents>> {
+        if let Some(contents) = self.pinned.lock().as_ref() {
+            return Ok(contents.clone());
+        }
+        let table_cache = self
+            .caches
+            .table_cache
+            .as_ref()
+            .expect("Expected a table cache for an ss_table not kept open.");
+        table_cache.get_or_open(self.cache_id, || {
+            let (_, contents) = SSTableContents::open(&self.path)?;
+            Ok(contents)
+        })
+    }
+
+    /// Keeps the ss_table open until it is dropped.
+    ///
+    /// Its file can then be deleted or replaced while readers still hold it.
+    pub fn pin(&self) -> LiteDbResult<()> {
+        let contents = self.contents()?;
+        *self.pinned.lock() = Some(contents);
+        if let Some(table_cache) = &self.caches.table_cache {
+            table_cache.remove(self.cache_id);
+        }
+        Ok(())
+    }
 
-        reader.seek(SeekFrom::Start(size_of_serialized_data))?;
-        let metadata: SSTableMetadata = decode_from_reader(&mut reader)?;
+    pub fn id(&self) -> u64 {
+        self.metadata.id
+    }
 
-        let index: SSTableSparseIndex = decode_from_reader(&mut reader)?;
-        let bloom_filter_state: BloomFilterState = decode_from_reader(&mut reader)?;
+    pub fn level(&self) -> usize {
+        self.metadata.level
+    }
 
-        let file = unsafe {
-            MmapOptions::new()
-                .offset(0)
-                .len(size_of_serialized_data as usize)
-                .map(&segment_file)
-                .unwrap()
+    pub fn num_entries(&self) -> usize {
+        self.metadata.num_entries
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns the bytes pointed at in each blob file.
+    pub fn blob_references(&self) -> &BTreeMap<BlobFileId, u64> {
+        &self.metadata.blob_references
+    }
+
+    pub fn blob_file_size(&self, file_id: &BlobFileId) -> LiteDbResult<Option<u64>> {
+        let contents = self.contents()?;
+        Ok(contents
+            .blob_files
+            .get(file_id)
+            .map(|mmap| mmap.len() as u64))
+    }
+
+    pub fn read_blob(&self, index: &BlobIndex, verify_checksum: bool) -> LiteDbResult<Value> {
+        read_blob(&self.contents()?.blob_files, index, verify_checksum)
+    }
+
+    /// Iterates over the stored values, pointers into blob files are left as is.
+    pub fn scan_stored(table: Arc<SSTable>) -> KVIterator {
+        let mut iterator = SSTableIterator::new(table, &None, &None, &ReadOptions::default());
+        iterator.resolve_blobs = false;
+        KVIterator::SSTable(iterator)
+    }
+
+    pub fn first_key(&self) -> RefKey<'_> {
+        &self.metadata.first_key.0
+    }
+
+    pub fn last_key(&self) -> RefKey<'_> {
+        &self.metadata.last_key.0
+    }
+
+    /// Checks every entry against its checksum, that keys are strictly ascending
+    /// and that the metadata matches the data section.
+    pub fn verify(&self) -> LiteDbResult<()> {
+        let contents = self.contents()?;
+        let mut offset = 0;
+        let mut last_entry: Option<(Key, Offset)> = None;
+        let mut num_entries = 0;
+        while offset <= self.metadata.last_key.1 {
+            let ((key, value, checksum), num_bytes): (SSTableEntry, usize) =
+                decode(&contents.file[offset..])?;
+            if crc32(&key, &value) != checksum
+                || matches!(&last_entry, Some((last_key, _)) if *last_key >= key)
+            {
+                return Err(LiteDbError::CorruptedData);
+            }
+            if num_entries == 0 && key != self.metadata.first_key.0 {
+                return Err(LiteDbError::CorruptedData);
+            }
+            last_entry = Some((key, offset));
+            num_entries += 1;
+            offset += num_bytes;
+        }
+        if last_entry.as_ref() != Some(&self.metadata.last_key)
+            || num_entries != self.metadata.num_entries
+        {
+            return Err(LiteDbError::CorruptedData);
+        }
+        Ok(())
+    }
+
+    /// Writes a copy of the ss_table with a new id at level 0 to `path`.
+    ///
+    /// The data section is copied as is, only the footer changes. The ss_table
+    /// itself is left untouched.
+    pub fn write_renumbered(&self, id: u64, path: &Path) -> LiteDbResult<()> {
+        let contents = self.contents()?;
+        let mut metadata = self.metadata.clone();
+        metadata.id = id;
+        metadata.level = 0;
+        let segment_file = create_file(path)?;
+        let mut writer = BufWriter::new(&segment_file);
+        writer.write_all(&contents.file)?;
+        write_footer(
+            &mut writer,
+            &metadata,
+            &contents.index,
+            &contents.bloom_filter,
+            contents.range_filter.as_ref(),
+            contents.file.len(),
+        )?;
+        writer.flush()?;
+        drop(writer);
+        segment_file.sync_all()?;
+        Ok(())
+    }
+
+    /// Runs `f` on the sparse index, or on the last partition of it for which
+    /// `precedes` holds, the first one if none does.
+    fn with_index<R>(
+        &self,
+        contents: &SSTableContents,
+        precedes: impl Fn(&IndexPartitionHandle) -> bool,
+        f: impl FnOnce(&SSTableSparseIndex) -> R,
+    ) -> LiteDbResult<R> {
+        let handles = match &contents.index {
+            SSTableIndex::Whole(index) => return Ok(f(index)),
+            SSTableIndex::Partitioned(handles) => handles,
+        };
+        let handle = &handles[handles.partition_point(precedes).saturating_sub(1)];
+        let load = || -> LiteDbResult<SSTableSparseIndex> {
+            let block = contents
+                .file
+                .get(handle.offset..handle.offset + handle.len)
+                .ok_or(LiteDbError::CorruptedData)?;
+            Ok(decode(block)?.0)
         };
+        let partition = match &self.caches.block_cache {
+            Some(block_cache) => block_cache.get_or_load((self.cache_id, handle.offset), load)?,
+            None => Arc::new(load()?),
+        };
+        Ok(f(&partition))
+    }
 
-        Ok(SSTable {
-            metadata,
-            file,
-            index,
-            bloom_filter: bloom_filter_state.into(),
-        })
+    /// Returns the offset of the last indexed entry before `key`, scans for it start there.
+    fn index_offset(&self, contents: &SSTableContents, key: RefKey) -> LiteDbResult<Offset> {
+        self.with_index(
+            contents,
+            |handle| handle.first_key.as_slice() < key,
+            |index| index.get_offset(key).unwrap_or(0),
+        )
     }
 
-    pub fn id(&self) -> u64 {
-        self.metadata.id
+    /// Returns the offset of the last indexed entry before the one at `offset`.
+    fn index_offset_before(
+        &self,
+        contents: &SSTableContents,
+        offset: Offset,
+    ) -> LiteDbResult<Offset> {
+        self.with_index(
+            contents,
+            |handle| handle.first_offset < offset,
+            |index| {
+                let items = &index.items;
+                let idx = items
+                    .partition_point(|item| item.1 < offset)
+                    .saturating_sub(1);
+                items.get(idx).map(|item| item.1).unwrap_or(0)
+            },
+        )
     }
 
-    pub fn potentially_contains_key(&self, key: &Key) -> bool {
-        self.bloom_filter.check(key)
+    /// Returns false when the ss_table provably does not hold `key`.
+    ///
+    /// The key range is checked first, the file is only opened for keys within it.
+    pub fn potentially_contains_key(&self, key: RefKey) -> LiteDbResult<bool> {
+        if key < self.first_key() || key > self.last_key() {
+            return Ok(false);
+        }
+        Ok(self.contents()?.bloom_filter.check(key))
     }
 
-    pub fn get(table: Arc<SSTable>, key: RefKey) -> LiteDbResult<Option<Value>> {
-        let owned_key = key.to_vec();
-        if !table.potentially_contains_key(&owned_key) {
+    /// Returns false when the ss_table provably holds no key in `[from, to)`.
+    pub fn potentially_contains_range(
+        &self,
+        from: &Option<Key>,
+        to: &Option<Key>,
+    ) -> LiteDbResult<bool> {
+        let outside_key_range = matches!(from, Some(first_key) if *first_key > self.metadata.last_key.0)
+            || matches!(to, Some(last_key) if *last_key <= self.metadata.first_key.0);
+        if outside_key_range {
+            return Ok(false);
+        }
+        Ok(self
+            .contents()?
+            .range_filter
+            .as_ref()
+            .map(|range_filter| range_filter.may_contain_range(from, to))
+            .unwrap_or(true))
+    }
+
+    /// Returns the value of `key`, a deleted key yields the tombstone.
+    pub fn get(
+        table: Arc<SSTable>,
+        key: RefKey,
+        read_options: &ReadOptions,
+    ) -> LiteDbResult<Option<Value>> {
+        if !table.potentially_contains_key(key)? {
             return Ok(None);
         }
 
-        let iterator = SSTableIterator::new(table, &Some(owned_key), &None);
+        let iterator = SSTableIterator::new(table, &Some(key.to_vec()), &None, read_options);
         for result in iterator {
             let (k, v) = result?;
             if k.as_slice() > key {
@@ -151,16 +430,143 @@ impl SSTable {
             }
 
             if k == key {
-                return if v == TOMBSTONE {
-                    Ok(None)
-                } else {
-                    Ok(Some(v))
-                };
+                return Ok(Some(v));
             }
         }
 
         Ok(None)
     }
+
+    /// Writes the entries to a level 0 ss_table of `dir` through a mem_table.
+    #[cfg(test)]
+    pub fn create_for_test<K: AsRef<[u8]>, V: AsRef<[u8]>>(
+        dir: &Path,
+        id: u64,
+        entries: impl IntoIterator<Item = (K, V)>,
+        options: &SSTableOptions,
+    ) -> LiteDbResult<Arc<SSTable>> {
+        use crate::{
+            mem_table::{MemTable, MemTableOptions},
+            options::WriteOptions,
+        };
+
+        let mem_table = MemTable::open(dir.to_path_buf(), id, &MemTableOptions::default())?;
+        for (key, value) in entries {
+            mem_table.set(key.as_ref(), value.as_ref(), &WriteOptions::default())?;
+        }
+        mem_table.save(10.0, options)
+    }
+}
+
+impl Drop for SSTable {
+    fn drop(&mut self) {
+        if let Some(table_cache) = &self.caches.table_cache {
+            table_cache.remove(self.cache_id);
+        }
+    }
+}
+
+/// Reads where the footer of an ss_table file starts and the metadata opening it.
+///
+/// Fails unless the file ends with the trailer of the current format.
+fn read_metadata<'a>(
+    path: &Path,
+    segment_file: &'a File,
+) -> LiteDbResult<(BufReader<&'a File>, u64, SSTableMetadata)> {
+    let file_len = segment_file.metadata()?.len();
+    if file_len < SS_TABLE_TRAILER_SIZE as u64 {
+        return Err(LiteDbError::CorruptedData);
+    }
+    let mut reader = BufReader::new(segment_file);
+    reader.seek(SeekFrom::End(-(SS_TABLE_TRAILER_SIZE as i64)))?;
+    let size_of_serialized_data: u64 = reader.read_u64::<LittleEndian>()?;
+    let version = reader.read_u32::<LittleEndian>()?;
+    // ss_tables written before the trailer existed end with the data size
+    let version = match reader.read_u64::<LittleEndian>()? {
+        SS_TABLE_MAGIC => version,
+        _ => 0,
+    };
+    if version != SS_TABLE_FORMAT_VERSION {
+        return Err(LiteDbError::UnsupportedFormat {
+            path: path.to_path_buf(),
+            version,
+            supported: SS_TABLE_FORMAT_VERSION,
+        });
+    }
+    if size_of_serialized_data > file_len {
+        return Err(LiteDbError::CorruptedData);
+    }
+
+    reader.seek(SeekFrom::Start(size_of_serialized_data))?;
+    let metadata: SSTableMetadata = decode_from_reader(&mut reader)?;
+    Ok((reader, size_of_serialized_data, metadata))
+}
+
+impl SSTableContents {
+    /// Maps the ss_table file and its blob files, then decodes the rest of the footer.
+    fn open(path: &Path) -> LiteDbResult<(SSTableMetadata, Self)> {
+        let segment_file = File::open(path)?;
+        let (mut reader, size_of_serialized_data, metadata) = read_metadata(path, &segment_file)?;
+
+        let index: SSTableIndex = decode_from_reader(&mut reader)?;
+        let bloom_filter: BloomFilter = decode_from_reader(&mut reader)?;
+        let range_filter_state: Option<RangeFilterState> = decode_from_reader(&mut reader)?;
+
+        let file = unsafe {
+            MmapOptions::new()
+                .offset(0)
+                .len(size_of_serialized_data as usize)
+                .map(&segment_file)?
+        };
+        let blob_files = map_blob_files(dir_of(path), metadata.blob_references.keys())?;
+
+        let contents = SSTableContents {
+            file,
+            index,
+            bloom_filter,
+            range_filter: range_filter_state.map(RangeFilter::from),
+            blob_files,
+        };
+        Ok((metadata, contents))
+    }
+
+    /// Returns the value a stored value holds or points at.
+    fn resolve(&self, mut stored: Value, verify_checksum: bool) -> LiteDbResult<Value> {
+        match blob_index(&stored)? {
+            Some(index) => read_blob(&self.blob_files, &index, verify_checksum),
+            None => {
+                stored.remove(0);
+                Ok(stored)
+            }
+        }
+    }
+
+    /// Hints the OS to read `[offset, offset + len)` of the data section ahead.
+    fn will_need(&self, offset: Offset, len: usize) {
+        #[cfg(unix)]
+        {
+            let len = len.min(self.file.len().saturating_sub(offset));
+            if len > 0 {
+                // a failed hint only costs performance
+                let _ = self.file.advise_range(Advice::WillNeed, offset, len);
+            }
+        }
+    }
+
+    /// Releases the pages of `[offset, offset + len)` of the data section.
+    fn release(&self, offset: Offset, len: usize) {
+        #[cfg(unix)]
+        {
+            let len = len.min(self.file.len().saturating_sub(offset));
+            if len > 0 {
+                // The mapping is read only, dropped pages are read back from the file.
+                let _ = unsafe {
+                    self.file
+                        .unchecked_advise_range(UncheckedAdvice::DontNeed, offset, len)
+                };
+            }
+        }
+    }
 }
 
 impl TryFrom<PathBuf> for SSTable {
@@ -172,15 +578,350 @@ impl TryFrom<PathBuf> for SSTable {
 }
 
 impl Scannable for Arc<SSTable> {
-    fn scan(&self, from: &Option<Key>, to: &Option<Key>) -> KVIterator {
-        KVIterator::SSTable(SSTableIterator::new(self.clone(), from, to))
+    fn scan_with_options(
+        &self,
+        from: &Option<Key>,
+        to: &Option<Key>,
+        read_options: &ReadOptions,
+    ) -> KVIterator {
+        KVIterator::SSTable(SSTableIterator::new(self.clone(), from, to, read_options))
     }
 }
 
 pub(crate) type Offset = usize;
 
+/// Settings shared by every ss_table written by the database.
+#[derive(Debug, Clone)]
+pub(crate) struct SSTableOptions {
+    pub bloom_filter_policy: BloomFilterPolicy,
+    pub sparse_index_range_size: usize,
+    pub range_filter: bool,
+    /// Values longer than this go to a blob file.
+    pub min_blob_size: Option<usize>,
+    /// Splits the sparse index in partitions of about this many bytes.
+    pub index_partition_size: Option<usize>,
+    pub caches: SSTableCaches,
+}
+
+impl SSTableOptions {
+    #[cfg(test)]
+    pub fn for_test() -> Self {
+        Self {
+            bloom_filter_policy: BloomFilterPolicy::new(10, None),
+            sparse_index_range_size: 40,
+            range_filter: false,
+            min_blob_size: None,
+            index_partition_size: None,
+            caches: SSTableCaches::default(),
+        }
+    }
+}
+
+fn dir_of(path: &Path) -> &Path {
+    path.parent().expect("Expected a database directory")
+}
+
+/// The level is part of the name, so that the table merging others into the next level
+/// never takes the place of one of them.
+pub(crate) fn ss_table_file_path(dir: &Path, id: u64, level: usize) -> PathBuf {
+    dir.join(format!("{:01$}_{level}.{SS_TABLE_FILE_EXTENSION}", id, 20))
+}
+
+fn create_file(path: &Path) -> LiteDbResult<File> {
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(path)?;
+    Ok(file)
+}
+
+/// Appends meta, index, bloom, range filter, the data size for offset calculation,
+/// then the format version and the magic.
+fn write_footer<W: Write>(
+    writer: &mut W,
+    metadata: &SSTableMetadata,
+    index: &SSTableIndex,
+    bloom_filter: &BloomFilter,
+    range_filter: Option<&RangeFilter>,
+    size_of_serialized_data: usize,
+) -> LiteDbResult<()> {
+    encode_into_writer(metadata, writer)?;
+    encode_into_writer(index, writer)?;
+    encode_into_writer(bloom_filter, writer)?;
+    encode_into_writer(&range_filter.map(RangeFilterState::from), writer)?;
+    writer.write_u64::<LittleEndian>(size_of_serialized_data as u64)?;
+    writer.write_u32::<LittleEndian>(SS_TABLE_FORMAT_VERSION)?;
+    writer.write_u64::<LittleEndian>(SS_TABLE_MAGIC)?;
+    Ok(())
+}
+
+/// Writes sorted entries into a new ss_table file.
+///
+/// Entries are written to a temporary file that replaces `path` on `finish`,
+/// so a crash never leaves a partial ss_table behind.
+pub(crate) struct SSTableBuilder {
+    path: PathBuf,
+    temp_file_path: PathBuf,
+    writer: BufWriter<File>,
+    id: u64,
+    level: usize,
+    sparse_index_range_size: usize,
+    index_entries: Vec<(Key, Offset)>,
+    /// Built on `finish` when the number of entries is not known upfront.
+    bloom_filter: Option<BloomFilter>,
+    bloom_bits_per_key: f64,
+    range_filter: Option<RangeFilterBuilder>,
+    first_key: Option<Key>,
+    last_key: Option<(Key, Offset)>,
+    size_of_serialized_data: usize,
+    total_size: usize,
+    num_entries: usize,
+    min_blob_size: Option<usize>,
+    /// Created with the first value going to a blob file.
+    blob_writer: Option<BlobFileWriter>,
+    blob_references: BTreeMap<BlobFileId, u64>,
+    index_partition_size: Option<usize>,
+    caches: SSTableCaches,
+}
+
+impl SSTableBuilder {
+    pub fn new(
+        path: PathBuf,
+        id: u64,
+        level: usize,
+        expected_num_entries: usize,
+        bloom_bits_per_key: f64,
+        options: &SSTableOptions,
+    ) -> LiteDbResult<Self> {
+        let mut builder = Self::new_unsized(path, id, level, bloom_bits_per_key, options)?;
+        builder.bloom_filter = Some(BloomFilter::new(expected_num_entries, bloom_bits_per_key));
+        Ok(builder)
+    }
+
+    /// Creates a builder for an unknown number of entries, its bloom filter
+    /// is built from the written entries on `finish`.
+    pub fn new_unsized(
+        path: PathBuf,
+        id: u64,
+        level: usize,
+        bloom_bits_per_key: f64,
+        options: &SSTableOptions,
+    ) -> LiteDbResult<Self> {
+        let temp_file_path = path.with_extension(SS_TABLE_TEMP_FILE_EXTENSION);
+        let writer = BufWriter::new(create_file(&temp_file_path)?);
+        Ok(Self {
+            path,
+            temp_file_path,
+            writer,
+            id,
+            level,
+            sparse_index_range_size: options.sparse_index_range_size,
+            index_entries: Vec::new(),
+            bloom_filter: None,
+            bloom_bits_per_key,
+            range_filter: options.range_filter.then(RangeFilterBuilder::default),
+            first_key: None,
+            last_key: None,
+            size_of_serialized_data: 0,
+            total_size: 0,
+            num_entries: 0,
+            min_blob_size: options.min_blob_size,
+            blob_writer: None,
+            blob_references: BTreeMap::new(),
+            index_partition_size: options.index_partition_size,
+            caches: options.caches.clone(),
+        })
+    }
+
+    /// Appends an entry, keys must be added in ascending order.
+    pub fn add(&mut self, key: RefKey, value: RefValue) -> LiteDbResult<()> {
+        if !matches!(self.min_blob_size, Some(min_blob_size) if value.len() > min_blob_size) {
+            return self.add_stored(key, &inline_value(value));
+        }
+        let blob_writer = match self.blob_writer.as_mut() {
+            Some(blob_writer) => blob_writer,
+            None => {
+                let blob_writer =
+                    BlobFileWriter::create(dir_of(&self.path), (self.id, self.level))?;
+                self.blob_writer.insert(blob_writer)
+            }
+        };
+        let index = blob_writer.append(value)?;
+        self.add_stored(key, &blob_value(&index)?)
+    }
+
+    /// Appends an entry with a value as stored in another ss_table.
+    pub fn add_stored(&mut self, key: RefKey, value: RefValue) -> LiteDbResult<()> {
+        if let Some(index) = blob_index(value)? {
+            *self.blob_references.entry(index.file_id).or_default() += index.len;
+        }
+        let num_bytes_written =
+            encode_into_writer(&(key, value, crc32(key, value)), &mut self.writer)?;
+
+        if let Some(bloom_filter) = self.bloom_filter.as_mut() {
+            bloom_filter.set(key);
+        }
+        if let Some(range_filter) = self.range_filter.as_mut() {
+            range_filter.add(key);
+        }
+        if (self.size_of_serialized_data == 0)
+            || (self.size_of_serialized_data % self.sparse_index_range_size == 0)
+        {
+            self.index_entries
+                .push((key.to_vec(), self.size_of_serialized_data));
+        }
+        if self.first_key.is_none() {
+            self.first_key = Some(key.to_vec());
+        }
+        self.last_key = Some((key.to_vec(), self.size_of_serialized_data));
+        self.size_of_serialized_data += num_bytes_written;
+        // the tag of the stored value is not counted
+        self.total_size += key.len() + value.len() - 1;
+        self.num_entries += 1;
+        Ok(())
+    }
+
+    /// Writes the footer and publishes the ss_table file.
+    pub fn finish(mut self) -> LiteDbResult<SSTable> {
+        let (first_key, last_key) = match (self.first_key.take(), self.last_key.take()) {
+            (Some(first_key), Some(last_key)) => ((first_key, 0), last_key),
+            _ => return Err(LiteDbError::EmptySSTable),
+        };
+        self.index_entries
+            .push((last_key.0.clone(), self.size_of_serialized_data));
+
+        if let Some(blob_writer) = self.blob_writer.take() {
+            blob_writer.finish()?;
+        }
+        let index_entries = mem::take(&mut self.index_entries);
+        let (index, footer_offset) = match self.index_partition_size {
+            Some(partition_size) => self.write_index_partitions(index_entries, partition_size)?,
+            None => (
+                SSTableIndex::Whole(SSTableSparseIndex::from(index_entries)),
+                self.size_of_serialized_data,
+            ),
+        };
+        self.writer.flush()?;
+        let file = unsafe {
+            MmapOptions::new()
+                .offset(0)
+                .len(footer_offset)
+                .map(self.writer.get_ref())?
+        };
+        let bloom_filter = match self.bloom_filter.take() {
+            Some(bloom_filter) => bloom_filter,
+            None => {
+                let mut bloom_filter = BloomFilter::new(self.num_entries, self.bloom_bits_per_key);
+                let mut offset = 0;
+                while offset < self.size_of_serialized_data {
+                    let ((key, _, _), num_bytes): (SSTableEntry, usize) = decode(&file[offset..])?;
+                    bloom_filter.set(&key);
+                    offset += num_bytes;
+                }
+                bloom_filter
+            }
+        };
+
+        let metadata = SSTableMetadata::new(
+            self.id,
+            self.level,
+            first_key,
+            last_key,
+            self.total_size,
+            self.num_entries,
+            self.blob_references,
+        );
+        let range_filter = self.range_filter.map(RangeFilterBuilder::finish);
+        write_footer(
+            &mut self.writer,
+            &metadata,
+            &index,
+            &bloom_filter,
+            range_filter.as_ref(),
+            footer_offset,
+        )?;
+
+        // flush segment_file
+        self.writer.flush()?;
+        let segment_file = self.writer.into_inner().map_err(|err| err.into_error())?;
+        segment_file.sync_all()?;
+        fs::rename(&self.temp_file_path, &self.path)?;
+
+        let blob_files = map_blob_files(dir_of(&self.path), metadata.blob_references.keys())?;
+        let contents = SSTableContents {
+            file,
+            index,
+            bloom_filter,
+            range_filter,
+            blob_files,
+        };
+        Ok(SSTable::with_contents(
+            metadata,
+            self.path,
+            contents,
+            self.caches,
+        ))
+    }
+
+    /// Writes the sparse index after the data section, in partitions of about
+    /// `partition_size` bytes. Returns the index locating them and where they end.
+    fn write_index_partitions(
+        &mut self,
+        index_entries: Vec<(Key, Offset)>,
+        partition_size: usize,
+    ) -> LiteDbResult<(SSTableIndex, Offset)> {
+        let mut handles = vec![];
+        let mut offset = self.size_of_serialized_data;
+        let mut items = index_entries.into_iter().peekable();
+        while items.peek().is_some() {
+            let mut partition = vec![];
+            let mut size_bytes = 0;
+            while let Some(item) = items.next_if(|_| size_bytes < partition_size) {
+                size_bytes += SSTableSparseIndex::item_size_bytes(&item);
+                partition.push(item);
+            }
+            let (first_key, first_offset) = partition[0].clone();
+            let len = encode_into_writer(&SSTableSparseIndex::from(partition), &mut self.writer)?;
+            handles.push(IndexPartitionHandle {
+                first_key,
+                first_offset,
+                offset,
+                len,
+            });
+            offset += len;
+        }
+        Ok((SSTableIndex::Partitioned(handles), offset))
+    }
+
+    /// Returns the last key added so far.
+    pub fn last_key(&self) -> Option<RefKey<'_>> {
+        self.last_key.as_ref().map(|(key, _)| key.as_slice())
+    }
+}
+
+/// The index of a ss_table, kept in memory while it is open.
+#[derive(Debug, Encode, Decode)]
+enum SSTableIndex {
+    Whole(SSTableSparseIndex),
+    /// Locates the partitions of the sparse index, stored between the data
+    /// section and the footer and only read when needed.
+    Partitioned(Vec<IndexPartitionHandle>),
+}
+
+#[derive(Debug, Encode, Decode)]
+struct IndexPartitionHandle {
+    // first item of the partition
+    first_key: Key,
+    first_offset: Offset,
+    // location of the partition in the file
+    offset: Offset,
+    len: usize,
+}
+
 // Sparse index for the SSTable
-#[derive(Debug, Default, Encode, Decode)]
+#[derive(Debug, Default, PartialEq, Eq, Encode, Decode)]
 pub(crate) struct SSTableSparseIndex {
     items: Vec<(Key, Offset)>,
 }
@@ -192,6 +933,15 @@ impl From<Vec<(Key, Offset)>> for SSTableSparseIndex {
 }
 
 impl SSTableSparseIndex {
+    /// Returns the memory held by the index.
+    pub fn size_bytes(&self) -> usize {
+        self.items.iter().map(Self::item_size_bytes).sum()
+    }
+
+    fn item_size_bytes(item: &(Key, Offset)) -> usize {
+        item.0.len() + mem::size_of::<(Key, Offset)>()
+    }
+
     fn get_offset(&self, key: RefKey) -> Option<Offset> {
         let idx = match self
             .items
@@ -209,64 +959,261 @@ impl SSTableSparseIndex {
     }
 }
 
+/// A cursor over a range `[from, to)` of a ss_table.
+///
+/// As an `Iterator`, it starts at the first entry of the range.
 pub(crate) struct SSTableIterator {
     ss_table: Arc<SSTable>,
-    offset_opt: Option<usize>,
+    // opened on first access, kept while the iterator lives
+    contents: Option<Arc<SSTableContents>>,
     start_key_opt: Option<Key>,
     stop_key_opt: Option<Key>,
+    // offset & content of the current entry
+    offset: Offset,
+    current: Option<(Key, Value)>,
+    next_offset: Offset,
+    started: bool,
+    verify_checksums: bool,
+    // compactions copy the pointers into blob files as is
+    resolve_blobs: bool,
+    fill_cache: bool,
+    readahead_size: usize,
+    // end of the data read ahead so far
+    readahead_end: Offset,
+    // range of the data read so far, released on drop when not filling the cache
+    touched: Option<(Offset, Offset)>,
 }
 
 impl SSTableIterator {
-    pub fn new(ss_table: Arc<SSTable>, from: &Option<Key>, to: &Option<Key>) -> Self {
-        let offset_opt = if let Some(first_key) = from {
-            ss_table.index.get_offset(first_key)
-        } else {
-            Some(0)
-        };
+    pub fn new(
+        ss_table: Arc<SSTable>,
+        from: &Option<Key>,
+        to: &Option<Key>,
+        read_options: &ReadOptions,
+    ) -> Self {
         Self {
             ss_table,
-            offset_opt,
+            contents: None,
             start_key_opt: from.clone(),
             stop_key_opt: to.clone(),
+            offset: 0,
+            current: None,
+            next_offset: 0,
+            started: false,
+            verify_checksums: read_options.verify_checksums,
+            resolve_blobs: true,
+            fill_cache: read_options.fill_cache,
+            readahead_size: read_options.readahead_size,
+            readahead_end: 0,
+            touched: None,
         }
     }
-}
 
-impl Iterator for SSTableIterator {
-    type Item = LiteDbResult<(Key, Value)>;
+    /// Returns the contents of the ss_table, opening it on first access.
+    fn contents(&mut self) -> LiteDbResult<Arc<SSTableContents>> {
+        if let Some(contents) = &self.contents {
+            return Ok(contents.clone());
+        }
+        Ok(self.contents.insert(self.ss_table.contents()?).clone())
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
-        if let Some(offset) = self.offset_opt {
-            if offset > self.ss_table.metadata.last_key.1 {
-                return None;
+    /// Loads the entry starting at `offset` as the current entry.
+    fn load(&mut self, offset: Offset) -> LiteDbResult<()> {
+        self.current = None;
+        if offset > self.ss_table.metadata.last_key.1 {
+            return Ok(());
+        }
+        let contents = self.contents()?;
+        if self.readahead_size > 0 && offset >= self.readahead_end {
+            contents.will_need(offset, self.readahead_size);
+            self.readahead_end = offset + self.readahead_size;
+        }
+        let ((key, value, checksum), num_bytes): (SSTableEntry, usize) =
+            decode(&contents.file[offset..])?;
+        if self.verify_checksums && crc32(&key, &value) != checksum {
+            return Err(LiteDbError::CorruptedData);
+        }
+        let value = match self.resolve_blobs {
+            true => contents.resolve(value, self.verify_checksums)?,
+            false => value,
+        };
+        self.offset = offset;
+        self.next_offset = offset + num_bytes;
+        self.current = Some((key, value));
+        self.touched = match self.touched {
+            Some((start, end)) => Some((start.min(offset), end.max(self.next_offset))),
+            None => Some((offset, self.next_offset)),
+        };
+        Ok(())
+    }
+
+    /// Positions on the first entry with a key at or after `key`, ignoring bounds.
+    fn load_at_or_after(&mut self, key: RefKey) -> LiteDbResult<()> {
+        let contents = self.contents()?;
+        let mut offset = self.ss_table.index_offset(&contents, key)?;
+        // the entries skipped over are only decoded, their blobs are not read
+        while offset <= self.ss_table.metadata.last_key.1 {
+            let ((k, _, _), num_bytes): (SSTableEntry, usize) = decode(&contents.file[offset..])?;
+            if k.as_slice() >= key {
+                break;
             }
+            offset += num_bytes;
+        }
+        self.load(offset)
+    }
 
-            let mut running_offset = offset;
-            loop {
-                let ((k, v), num_bytes): ((Key, Value), usize) =
-                    match decode(&self.ss_table.file[running_offset..]) {
-                        Ok(value) => value,
-                        Err(err) => break Some(Err(err)),
-                    };
-
-                if let Some(start_key) = &self.start_key_opt {
-                    if k < *start_key {
-                        running_offset += num_bytes;
-                        continue;
-                    }
-                }
+    /// Returns the offset of the entry preceding the one at `offset`.
+    ///
+    /// Entries are variable-sized, so we start decoding from the closest
+    /// sparse index offset before `offset`.
+    fn previous_offset(&mut self, offset: Offset) -> LiteDbResult<Option<Offset>> {
+        if offset == 0 {
+            return Ok(None);
+        }
+        let contents = self.contents()?;
+        let mut running_offset = self.ss_table.index_offset_before(&contents, offset)?;
+        loop {
+            let (_, num_bytes): (SSTableEntry, usize) = decode(&contents.file[running_offset..])?;
+            if running_offset + num_bytes >= offset {
+                return Ok(Some(running_offset));
+            }
+            running_offset += num_bytes;
+        }
+    }
+
+    /// Invalidates the cursor when it moved past the upper bound.
+    fn check_upper_bound(&mut self) {
+        if let (Some((k, _)), Some(stop_key)) = (&self.current, &self.stop_key_opt) {
+            if k >= stop_key {
+                self.current = None;
+            }
+        }
+    }
+
+    /// Invalidates the cursor when it moved before the lower bound.
+    fn check_lower_bound(&mut self) {
+        if let (Some((k, _)), Some(start_key)) = (&self.current, &self.start_key_opt) {
+            if k < start_key {
+                self.current = None;
+            }
+        }
+    }
+}
+
+impl Cursor for SSTableIterator {
+    fn valid(&self) -> bool {
+        self.current.is_some()
+    }
+
+    fn seek_to_first(&mut self) -> LiteDbResult<()> {
+        match self.start_key_opt.clone() {
+            Some(start_key) => self.load_at_or_after(&start_key)?,
+            None => self.load(0)?,
+        }
+        self.check_upper_bound();
+        Ok(())
+    }
 
-                if let Some(stop_key) = &self.stop_key_opt {
-                    if k >= *stop_key {
-                        break None;
+    fn seek_to_last(&mut self) -> LiteDbResult<()> {
+        match self.stop_key_opt.clone() {
+            Some(stop_key) => {
+                self.load_at_or_after(&stop_key)?;
+                if self.current.is_some() {
+                    self.current = None;
+                    if let Some(offset) = self.previous_offset(self.offset)? {
+                        self.load(offset)?;
                     }
+                } else {
+                    self.load(self.ss_table.metadata.last_key.1)?;
                 }
+            }
+            None => self.load(self.ss_table.metadata.last_key.1)?,
+        }
+        self.check_lower_bound();
+        Ok(())
+    }
+
+    fn seek(&mut self, key: RefKey) -> LiteDbResult<()> {
+        match self.start_key_opt.clone() {
+            Some(start_key) if key < start_key.as_slice() => self.load_at_or_after(&start_key)?,
+            _ => self.load_at_or_after(key)?,
+        }
+        self.check_upper_bound();
+        Ok(())
+    }
 
-                self.offset_opt = Some(running_offset + num_bytes);
-                break Some(Ok((k, v)));
+    fn seek_for_prev(&mut self, key: RefKey) -> LiteDbResult<()> {
+        if matches!(&self.stop_key_opt, Some(stop_key) if key >= stop_key.as_slice()) {
+            return self.seek_to_last();
+        }
+        self.load_at_or_after(key)?;
+        match &self.current {
+            Some((k, _)) if k.as_slice() == key => (),
+            Some(_) => {
+                self.current = None;
+                if let Some(offset) = self.previous_offset(self.offset)? {
+                    self.load(offset)?;
+                }
             }
+            None => self.load(self.ss_table.metadata.last_key.1)?,
+        }
+        self.check_lower_bound();
+        Ok(())
+    }
+
+    fn next(&mut self) -> LiteDbResult<()> {
+        if self.current.is_none() {
+            return Ok(());
+        }
+        self.load(self.next_offset)?;
+        self.check_upper_bound();
+        Ok(())
+    }
+
+    fn prev(&mut self) -> LiteDbResult<()> {
+        if self.current.is_none() {
+            return Ok(());
+        }
+        self.current = None;
+        if let Some(offset) = self.previous_offset(self.offset)? {
+            self.load(offset)?;
+        }
+        self.check_lower_bound();
+        Ok(())
+    }
+
+    fn key(&self) -> RefKey<'_> {
+        &self.current.as_ref().expect("Expected a valid cursor.").0
+    }
+
+    fn value(&self) -> RefValue<'_> {
+        &self.current.as_ref().expect("Expected a valid cursor.").1
+    }
+}
+
+impl Iterator for SSTableIterator {
+    type Item = LiteDbResult<(Key, Value)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let move_result = if self.started {
+            Cursor::next(self)
         } else {
-            None
+            self.started = true;
+            self.seek_to_first()
+        };
+        if let Err(err) = move_result {
+            return Some(Err(err));
+        }
+        self.current.clone().map(Ok)
+    }
+}
+
+impl Drop for SSTableIterator {
+    fn drop(&mut self) {
+        if let (false, Some(contents), Some((start, end))) =
+            (self.fill_cache, &self.contents, self.touched)
+        {
+            contents.release(start, end - start);
         }
     }
 }
@@ -278,12 +1225,27 @@ mod tests {
     use anyhow::Ok;
     use tempfile::tempdir;
 
-    use crate::{mem_table::MemTable, ss_table::SSTable, Scannable};
+    use crate::{
+        cursor::Cursor,
+        mem_table::{MemTable, MemTableOptions},
+        options::{ReadOptions, WriteOptions},
+        ss_table::{SSTable, SSTableCaches, SSTableIndex, SSTableOptions},
+        table_cache::TableCache,
+        BlockCache, LiteDbError, Scannable,
+    };
 
     fn to_vec(s: &str) -> Vec<u8> {
         s.as_bytes().to_vec()
     }
 
+    fn ss_table_options() -> SSTableOptions {
+        SSTableOptions {
+            sparse_index_range_size: 300,
+            range_filter: true,
+            ..SSTableOptions::for_test()
+        }
+    }
+
     fn check_ss_table(ss_table: Arc<SSTable>, size_bytes: usize) -> anyhow::Result<()> {
         // check metadata
         assert_eq!(ss_table.metadata.id, 1);
@@ -293,30 +1255,38 @@ mod tests {
         matches!(&ss_table.metadata.last_key, (v, _) if v == &to_vec("k_999"));
 
         // check bloom_filter
-        assert!(ss_table.potentially_contains_key(&to_vec("k_000")));
-        assert!(ss_table.potentially_contains_key(&to_vec("k_012")));
-        assert!(!ss_table.potentially_contains_key(&to_vec("unknown")));
+        assert!(ss_table.potentially_contains_key(&to_vec("k_000"))?);
+        assert!(ss_table.potentially_contains_key(&to_vec("k_012"))?);
+        assert!(!ss_table.potentially_contains_key(&to_vec("unknown"))?);
+
+        // check range filter
+        assert!(ss_table.potentially_contains_range(&None, &None)?);
+        assert!(ss_table.potentially_contains_range(&Some(to_vec("k_5")), &Some(to_vec("k_6")))?);
+        assert!(!ss_table.potentially_contains_range(&Some(to_vec("a")), &Some(to_vec("k")))?);
+        assert!(!ss_table.potentially_contains_range(&Some(to_vec("k_9999")), &None)?);
+        assert!(!ss_table.potentially_contains_range(&Some(to_vec("k_1a")), &Some(to_vec("k_2")))?);
 
         // check sparse index
-        let all_index_keys_exist = ss_table
-            .index
-            .items
-            .iter()
-            .map(|(key, _)| ss_table.potentially_contains_key(key))
-            .all(|exists| exists);
-        assert!(all_index_keys_exist);
+        if let SSTableIndex::Whole(index) = &ss_table.contents()?.index {
+            let all_index_keys_exist = index
+                .items
+                .iter()
+                .map(|(key, _)| ss_table.potentially_contains_key(key).unwrap())
+                .all(|exists| exists);
+            assert!(all_index_keys_exist);
+        }
 
         // check get
         assert_eq!(
-            SSTable::get(ss_table.clone(), b"k_990")?,
+            SSTable::get(ss_table.clone(), b"k_990", &ReadOptions::default())?,
             Some(to_vec("v_990"))
         );
         assert_eq!(
-            SSTable::get(ss_table.clone(), b"k_020")?,
+            SSTable::get(ss_table.clone(), b"k_020", &ReadOptions::default())?,
             Some(to_vec("v_020"))
         );
         assert_eq!(
-            SSTable::get(ss_table.clone(), b"k_101")?,
+            SSTable::get(ss_table.clone(), b"k_101", &ReadOptions::default())?,
             Some(to_vec("v_101"))
         );
 
@@ -330,6 +1300,25 @@ mod tests {
             10
         );
 
+        // check reverse traversal
+        let mut cursor = ss_table.scan(&Some(to_vec("k_500")), &Some(to_vec("k_600")));
+        cursor.seek_to_last()?;
+        let mut num_entries = 0;
+        while cursor.valid() {
+            let expected_key = format!("k_{:01$}", 599 - num_entries, 3);
+            assert_eq!(cursor.key(), expected_key.as_bytes());
+            num_entries += 1;
+            cursor.prev()?;
+        }
+        assert_eq!(num_entries, 100);
+
+        cursor.seek_for_prev(b"k_5555")?;
+        assert_eq!(cursor.key(), b"k_555");
+        Cursor::next(&mut cursor)?;
+        assert_eq!(cursor.value(), b"v_556");
+        cursor.seek_for_prev(b"k_000")?;
+        assert!(!cursor.valid());
+
         Ok(())
     }
 
@@ -338,16 +1327,16 @@ mod tests {
         let tempdir = tempdir()?;
         let dir = tempdir.path().to_path_buf();
 
-        let mem_table = MemTable::open(dir, 1).unwrap();
+        let mem_table = MemTable::open(dir, 1, &MemTableOptions::default()).unwrap();
         let mut size_bytes = 0usize;
         for i in 0..1000 {
             let k = format!("k_{:01$}", i, 3);
             let v = format!("v_{:01$}", i, 3);
             size_bytes += k.len() + v.len();
-            mem_table.set(k.as_bytes(), v.as_bytes())?;
+            mem_table.set(k.as_bytes(), v.as_bytes(), &WriteOptions::default())?;
         }
 
-        let ss_table = mem_table.save(3_000_000, 1_000_000, 300)?;
+        let ss_table = mem_table.save(10.0, &ss_table_options())?;
         check_ss_table(ss_table, size_bytes)
     }
 
@@ -356,18 +1345,132 @@ mod tests {
         let tempdir = tempdir()?;
         let dir = tempdir.path().to_path_buf();
 
-        let mem_table = MemTable::open(dir, 1).unwrap();
+        let mem_table = MemTable::open(dir, 1, &MemTableOptions::default()).unwrap();
+        let mut size_bytes = 0usize;
+        for i in 0..1000 {
+            let k = format!("k_{:01$}", i, 3);
+            let v = format!("v_{:01$}", i, 3);
+            size_bytes += k.len() + v.len();
+            mem_table.set(k.as_bytes(), v.as_bytes(), &WriteOptions::default())?;
+        }
+        let file_path = mem_table.ss_table_file_path();
+        mem_table.save(10.0, &ss_table_options())?;
+
+        let ss_table = Arc::new(SSTable::open(file_path)?);
+        check_ss_table(ss_table, size_bytes)
+    }
+
+    #[test]
+    fn test_ss_table_partitioned_index() -> anyhow::Result<()> {
+        let tempdir = tempdir()?;
+        let dir = tempdir.path().to_path_buf();
+
+        let mem_table = MemTable::open(dir, 1, &MemTableOptions::default()).unwrap();
         let mut size_bytes = 0usize;
         for i in 0..1000 {
             let k = format!("k_{:01$}", i, 3);
             let v = format!("v_{:01$}", i, 3);
             size_bytes += k.len() + v.len();
-            mem_table.set(k.as_bytes(), v.as_bytes())?;
+            mem_table.set(k.as_bytes(), v.as_bytes(), &WriteOptions::default())?;
         }
         let file_path = mem_table.ss_table_file_path();
-        mem_table.save(3_000_000, 1_000_000, 300)?;
+        let block_cache = BlockCache::new(200);
+        let ss_table_options = SSTableOptions {
+            index_partition_size: Some(64),
+            caches: SSTableCaches {
+                block_cache: Some(block_cache.clone()),
+                table_cache: None,
+            },
+            ..ss_table_options()
+        };
+        let ss_table = mem_table.save(10.0, &ss_table_options)?;
+        assert!(matches!(
+            &ss_table.contents()?.index,
+            SSTableIndex::Partitioned(handles) if handles.len() > 1
+        ));
+        ss_table.verify()?;
+        check_ss_table(ss_table, size_bytes)?;
+        assert!(block_cache.usage() > 0 && block_cache.usage() <= block_cache.capacity());
 
+        // without a cache, the partitions are read on every lookup
         let ss_table = Arc::new(SSTable::open(file_path)?);
         check_ss_table(ss_table, size_bytes)
     }
+
+    #[test]
+    fn test_ss_table_checksums() -> anyhow::Result<()> {
+        let tempdir = tempdir()?;
+        let dir = tempdir.path().to_path_buf();
+
+        let mem_table = MemTable::open(dir, 1, &MemTableOptions::default()).unwrap();
+        for i in 0..100 {
+            let k = format!("k_{:01$}", i, 3);
+            let v = format!("v_{:01$}", i, 3);
+            mem_table.set(k.as_bytes(), v.as_bytes(), &WriteOptions::default())?;
+        }
+        let file_path = mem_table.ss_table_file_path();
+        mem_table.save(10.0, &ss_table_options())?;
+
+        // flip a byte of a value
+        let mut bytes = std::fs::read(&file_path)?;
+        let value_pos = bytes
+            .windows(5)
+            .position(|window| window == b"v_042")
+            .unwrap();
+        bytes[value_pos + 4] = b'X';
+        std::fs::write(&file_path, bytes)?;
+        let ss_table = Arc::new(SSTable::open(file_path)?);
+
+        let result = SSTable::get(ss_table.clone(), b"k_042", &ReadOptions::default());
+        assert!(matches!(result, Err(LiteDbError::CorruptedData)));
+        let read_options = ReadOptions {
+            verify_checksums: false,
+            fill_cache: false,
+            readahead_size: 4096,
+            ..ReadOptions::default()
+        };
+        assert_eq!(
+            SSTable::get(ss_table.clone(), b"k_042", &read_options)?,
+            Some(to_vec("v_04X"))
+        );
+        let scan = ss_table.scan_with_options(&None, &None, &read_options);
+        assert_eq!(scan.count(), 100);
+        Ok(())
+    }
+
+    #[test]
+    fn test_ss_table_format_version() -> anyhow::Result<()> {
+        let tempdir = tempdir()?;
+        let dir = tempdir.path().to_path_buf();
+
+        let mem_table = MemTable::open(dir, 1, &MemTableOptions::default()).unwrap();
+        mem_table.set(b"k", b"v", &WriteOptions::default())?;
+        let file_path = mem_table.ss_table_file_path();
+        mem_table.save(10.0, &ss_table_options())?;
+
+        // ss_tables written before the trailer existed end with the data size
+        let bytes = std::fs::read(&file_path)?;
+        std::fs::write(&file_path, &bytes[..bytes.len() - 12])?;
+        let result = SSTable::open(file_path.clone());
+        assert!(matches!(
+            result,
+            Err(LiteDbError::UnsupportedFormat {
+                version: 0,
+                supported: 1,
+                ..
+            })
+        ));
+        let table_cache_result = SSTable::open_with_caches(
+            file_path,
+            SSTableCaches {
+                block_cache: None,
+                table_cache: Some(TableCache::new(1)),
+            },
+        );
+        assert!(matches!(
+            table_cache_result,
+            Err(LiteDbError::UnsupportedFormat { version: 0, .. })
+        ));
+        Ok(())
+    }
 }
@@ -0,0 +1,149 @@
+use std::path::{Path, PathBuf};
+
+use crate::{
+    bloom_filter::BloomFilterPolicy,
+    error::{LiteDbError, LiteDbResult},
+    options::LiteDbOptions,
+    ss_table::{SSTableBuilder, SSTableCaches, SSTableOptions},
+    Key, RefKey, RefValue, TOMBSTONE,
+};
+
+/// Describes an ss_table file written by a `SstFileWriter`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExternalSstFileInfo {
+    pub path: PathBuf,
+    pub first_key: Key,
+    pub last_key: Key,
+    pub num_entries: usize,
+}
+
+/// Streams sorted entries into a new ss_table file, bypassing the wal & the mem_tables.
+///
+/// The file is laid out as the ss_tables flushed by a database with the same
+/// options, without a range filter, and can be ingested with
+/// `LiteDb::ingest_external_files`. Entries go straight to disk, what stays in
+/// memory still grows with the file: one key of the sparse index per
+/// `sparse_index_range_size` bytes of entries, then the bloom filter built on
+/// `finish`, about `bloom_bits_per_key` bits per entry.
+pub struct SstFileWriter {
+    builder: SSTableBuilder,
+    first_key: Option<Key>,
+}
+
+impl SstFileWriter {
+    pub fn create<P: AsRef<Path>>(path: P, options: &LiteDbOptions) -> LiteDbResult<Self> {
+        let ss_table_options = SSTableOptions {
+            bloom_filter_policy: BloomFilterPolicy::new(
+                options.bloom_bits_per_key,
+                options.bloom_filter_memory_budget,
+            ),
+            sparse_index_range_size: options.sparse_index_range_size,
+            // it would hold a prefix of every key in memory until the end
+            range_filter: false,
+            // an external file is ingested alone, its values stay inline
+            min_blob_size: None,
+            index_partition_size: None,
+            caches: SSTableCaches::default(),
+        };
+        // a memory budget is spread over the tables once ingested
+        let builder = SSTableBuilder::new_unsized(
+            PathBuf::from(path.as_ref()),
+            0,
+            0,
+            options.bloom_bits_per_key as f64,
+            &ss_table_options,
+        )?;
+        Ok(Self {
+            builder,
+            first_key: None,
+        })
+    }
+
+    /// Appends an entry, keys must be strictly ascending.
+    pub fn put(&mut self, key: RefKey, value: RefValue) -> LiteDbResult<()> {
+        if matches!(self.builder.last_key(), Some(last_key) if last_key >= key) {
+            return Err(LiteDbError::UnsortedKeys);
+        }
+        if self.first_key.is_none() {
+            self.first_key = Some(key.to_vec());
+        }
+        self.builder.add(key, value)
+    }
+
+    /// Appends a deletion of the key, it hides the older values once ingested.
+    pub fn delete(&mut self, key: RefKey) -> LiteDbResult<()> {
+        self.put(key, &TOMBSTONE)
+    }
+
+    /// Writes the footer and publishes the file, at least one entry is needed.
+    pub fn finish(self) -> LiteDbResult<ExternalSstFileInfo> {
+        let first_key = self.first_key.ok_or(LiteDbError::EmptySSTable)?;
+        let last_key = self
+            .builder
+            .last_key()
+            .map(|key| key.to_vec())
+            .ok_or(LiteDbError::EmptySSTable)?;
+        let ss_table = self.builder.finish()?;
+        Ok(ExternalSstFileInfo {
+            path: ss_table.path().to_path_buf(),
+            first_key,
+            last_key,
+            num_entries: ss_table.num_entries(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, sync::Arc};
+
+    use tempfile::tempdir;
+
+    use crate::{
+        bloom_filter::BloomFilterPolicy,
+        error::LiteDbError,
+        mem_table::{MemTable, MemTableOptions},
+        options::{LiteDbOptions, WriteOptions},
+        ss_table::SSTableOptions,
+        sst_file_writer::SstFileWriter,
+    };
+
+    #[test]
+    fn test_sst_file_writer() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+        let options = LiteDbOptions::for_test();
+        let path = temp_dir.path().join("external.sst");
+        let mut writer = SstFileWriter::create(&path, &options)?;
+        for i in 0..1000 {
+            let k = format!("k_{:01$}", i, 4);
+            writer.put(k.as_bytes(), b"v")?;
+        }
+        assert!(matches!(
+            writer.put(b"k_0000", b"v"),
+            Err(LiteDbError::UnsortedKeys)
+        ));
+        let info = writer.finish()?;
+        assert_eq!(info.first_key, b"k_0000".to_vec());
+        assert_eq!(info.last_key, b"k_0999".to_vec());
+        assert_eq!(info.num_entries, 1000);
+
+        // the same entries flushed from a mem_table without range filter give the same file
+        let mem_table = Arc::new(MemTable::open(
+            temp_dir.path().to_path_buf(),
+            0,
+            &MemTableOptions::default(),
+        )?);
+        for i in 0..1000 {
+            let k = format!("k_{:01$}", i, 4);
+            mem_table.set(k.as_bytes(), b"v", &WriteOptions::default())?;
+        }
+        let ss_table_options = SSTableOptions {
+            bloom_filter_policy: BloomFilterPolicy::new(options.bloom_bits_per_key, None),
+            sparse_index_range_size: options.sparse_index_range_size,
+            ..SSTableOptions::for_test()
+        };
+        let ss_table = mem_table.save(options.bloom_bits_per_key as f64, &ss_table_options)?;
+        assert_eq!(fs::read(&path)?, fs::read(ss_table.path())?);
+        Ok(())
+    }
+}
@@ -0,0 +1,64 @@
+use std::{fmt, sync::Arc};
+
+use parking_lot::Mutex;
+
+use crate::{error::LiteDbResult, lru_cache::LruCache, ss_table::SSTableContents};
+
+/// Keeps at most `max_open_files` ss_tables open, by their cache id.
+///
+/// An ss_table is opened on first access and closed once it is the least
+/// recently used one over the limit. Readers holding its contents keep it
+/// mapped until they are done.
+#[derive(Clone)]
+pub(crate) struct TableCache {
+    tables: Arc<Mutex<LruCache<u64, Arc<SSTableContents>>>>,
+}
+
+impl TableCache {
+    pub fn new(max_open_files: usize) -> Self {
+        Self {
+            tables: Arc::new(Mutex::new(LruCache::new(max_open_files))),
+        }
+    }
+
+    pub fn max_open_files(&self) -> usize {
+        self.tables.lock().capacity()
+    }
+
+    /// Returns the number of ss_tables open through the cache.
+    pub fn num_open_files(&self) -> usize {
+        self.tables.lock().usage()
+    }
+
+    /// Returns the contents of the open ss_table, or opens and caches it.
+    pub fn get_or_open<F>(&self, cache_id: u64, open: F) -> LiteDbResult<Arc<SSTableContents>>
+    where
+        F: FnOnce() -> LiteDbResult<SSTableContents>,
+    {
+        if let Some(contents) = self.tables.lock().get(&cache_id) {
+            return Ok(contents);
+        }
+        // opened without the lock, concurrent misses may open the same file twice
+        let contents = Arc::new(open()?);
+        self.insert(cache_id, contents.clone());
+        Ok(contents)
+    }
+
+    pub fn insert(&self, cache_id: u64, contents: Arc<SSTableContents>) {
+        self.tables.lock().insert(cache_id, contents, 1);
+    }
+
+    /// Closes the ss_table, unless readers still hold its contents.
+    pub fn remove(&self, cache_id: u64) {
+        self.tables.lock().remove(&cache_id);
+    }
+}
+
+impl fmt::Debug for TableCache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TableCache")
+            .field("max_open_files", &self.max_open_files())
+            .field("num_open_files", &self.num_open_files())
+            .finish()
+    }
+}
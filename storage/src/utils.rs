@@ -1,7 +1,12 @@
 use bincode::{config::Configuration, Decode, Encode};
 use crc::{Crc, CRC_32_ISCSI};
 use parking_lot::Mutex;
-use std::io::{Read, Write};
+use std::{
+    fs,
+    io::{self, ErrorKind, Read, Write},
+    path::{Path, PathBuf},
+    process,
+};
 
 use crate::LiteDbError;
 
@@ -25,6 +30,25 @@ pub(crate) fn decode_from_reader<T: Decode, R: Read>(reader: &mut R) -> Result<T
     Ok(decoded_value)
 }
 
+/// Creates an empty directory next to `target_path`, under a name no other
+/// caller gets, to build the content of `target_path` in before renaming it.
+pub(crate) fn create_temp_dir_for(target_path: &Path) -> io::Result<PathBuf> {
+    let parent = target_path.parent().unwrap_or_else(|| Path::new(""));
+    let name = target_path
+        .file_name()
+        .map_or_else(String::new, |name| name.to_string_lossy().to_string());
+    fs::create_dir_all(parent)?;
+    let mut attempt = 0;
+    loop {
+        let temp_path = parent.join(format!(".{name}.{}.{attempt}.tmp", process::id()));
+        match fs::create_dir(&temp_path) {
+            Ok(()) => return Ok(temp_path),
+            Err(err) if err.kind() == ErrorKind::AlreadyExists => attempt += 1,
+            Err(err) => return Err(err),
+        }
+    }
+}
+
 pub(crate) fn crc32(key: &[u8], value: &[u8]) -> u32 {
     let crc = Crc::<u32>::new(&CRC_32_ISCSI);
     let mut digest = crc.digest();
@@ -40,8 +64,8 @@ impl AtomicOperationExecutor {
         Self(Mutex::new(()))
     }
 
-    pub fn perform<F: Fn()>(&self, callback: F) {
+    pub fn perform<T, F: FnOnce() -> T>(&self, callback: F) -> T {
         let _mutex_guard = self.0.lock();
-        callback();
+        callback()
     }
 }
@@ -1,20 +1,31 @@
 use std::{
+    collections::VecDeque,
     fs::{self, File, OpenOptions},
-    io::{BufWriter, Seek, SeekFrom, Write},
+    io::{BufReader, BufWriter, Read, Write},
+    ops::Range,
     path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
 use bincode::{Decode, Encode};
-use parking_lot::RwLock;
+use byteorder::{ByteOrder, LittleEndian, WriteBytesExt};
+use crossbeam::{
+    channel::{bounded, tick, Sender},
+    select,
+};
+use parking_lot::{Condvar, Mutex, RwLock};
 
 use crate::{
     error::LiteDbResult,
-    utils::{crc32, decode_from_reader, encode_into_writer},
+    utils::{crc32, decode, encode_into_writer},
     Key, LiteDbError, RefKey, RefValue, Value,
 };
 
-use std::io::BufReader;
-
 pub(crate) const WAL_FILE_EXTENSION: &str = "log";
 
 pub(crate) fn is_mem_table_file(path: &Path) -> bool {
@@ -29,153 +40,876 @@ pub(crate) fn is_mem_table_file(path: &Path) -> bool {
 pub(crate) struct LogItem {
     pub key: Key,
     pub value: Value,
-    pub checksum: u32,
 }
 
 impl LogItem {
     fn new(key: Key, value: Value) -> Self {
-        let checksum = crc32(&key, &value);
-        Self {
-            key,
-            value,
-            checksum,
-        }
+        Self { key, value }
     }
+}
 
-    fn check(&self) -> bool {
-        self.checksum == crc32(&self.key, &self.value)
-    }
+/// A logical record, the entries of a single write.
+#[derive(Debug, Encode, Decode)]
+pub(crate) struct WalRecord {
+    /// Microseconds since the unix epoch when the write was logged.
+    pub timestamp: u64,
+    pub log_items: Vec<LogItem>,
+}
 
-    fn is_empty(&self) -> bool {
-        self.key.is_empty() && self.value.is_empty() && self.checksum == 0
+impl WalRecord {
+    fn new(log_items: Vec<LogItem>) -> Self {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_micros() as u64);
+        Self {
+            timestamp,
+            log_items,
+        }
     }
 }
 
+/// Orders the records of every wal: the wal id in the high bits and
+/// the position of the record in its wal, starting at 1, in the low ones.
+pub(crate) fn sequence_number(wal_id: u64, record_index: u64) -> u64 {
+    (wal_id << 32) | record_index
+}
+
 impl From<LogItem> for (Key, Value) {
     fn from(item: LogItem) -> Self {
         (item.key, item.value)
     }
 }
 
+/// Defines when appended wal records are synced to disk.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum WalSyncMode {
+    /// Records are handed to the OS, a power failure may lose them.
+    #[default]
+    None,
+    /// Every write waits for its own `fdatasync`.
+    EveryWrite,
+    /// Concurrent writers share a single `fdatasync`.
+    /// The syncing writer waits up to `max_delay` for others to join.
+    GroupCommit { max_delay: Duration },
+    /// A background thread syncs the wal at a fixed interval.
+    Periodic(Duration),
+}
+
+/// Defines how replay handles a damaged wal.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum WalRecoveryMode {
+    /// Any corruption fails the open, even a torn last record.
+    AbsoluteConsistency,
+    /// A corrupted tail is dropped, a corruption followed by valid records fails the open.
+    TolerateCorruptedTailRecords,
+    /// Replay stops at the first corruption, everything after it is dropped.
+    #[default]
+    PointInTime,
+    /// Corrupted records are dropped, replay goes on with the next valid record.
+    SkipAnyCorruptedRecords,
+}
+
+/// What replaying a wal had to drop.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct WalRecoveryReport {
+    /// Dropped records, a corrupted region counts as a single record.
+    pub num_records_dropped: usize,
+    pub num_bytes_dropped: u64,
+}
+
+impl WalRecoveryReport {
+    /// Returns true when nothing was dropped.
+    pub fn is_clean(&self) -> bool {
+        self.num_records_dropped == 0 && self.num_bytes_dropped == 0
+    }
+
+    pub(crate) fn merge(&mut self, other: &WalRecoveryReport) {
+        self.num_records_dropped += other.num_records_dropped;
+        self.num_bytes_dropped += other.num_bytes_dropped;
+    }
+}
+
+#[derive(Debug, Default)]
+struct GroupCommitState {
+    num_synced_writes: u64,
+    syncing: bool,
+}
+
+/// Syncs the wal file following a `WalSyncMode`.
+#[derive(Debug)]
+struct WalSyncer {
+    mode: WalSyncMode,
+    file: Arc<File>,
+    num_writes: AtomicU64,
+    dirty: Arc<AtomicBool>,
+    group_commit_state: Mutex<GroupCommitState>,
+    group_commit_condvar: Condvar,
+    periodic_task: Option<(Sender<()>, JoinHandle<()>)>,
+}
+
+impl WalSyncer {
+    fn new(mode: WalSyncMode, file: File) -> Self {
+        let file = Arc::new(file);
+        let dirty = Arc::new(AtomicBool::new(false));
+        let periodic_task = match mode {
+            WalSyncMode::Periodic(interval) => {
+                let (kill_signal_sender, kill_signal_receiver) = bounded(1);
+                let ticker = tick(interval);
+                let file = file.clone();
+                let dirty = dirty.clone();
+                let task_handle = thread::spawn(move || loop {
+                    select! {
+                        recv(ticker) -> _ => (),
+                        recv(kill_signal_receiver) -> _ => break,
+                    };
+                    if dirty.swap(false, Ordering::SeqCst) && file.sync_data().is_err() {
+                        dirty.store(true, Ordering::SeqCst);
+                    }
+                });
+                Some((kill_signal_sender, task_handle))
+            }
+            _ => None,
+        };
+        Self {
+            mode,
+            file,
+            num_writes: AtomicU64::new(0),
+            dirty,
+            group_commit_state: Mutex::new(GroupCommitState::default()),
+            group_commit_condvar: Condvar::new(),
+            periodic_task,
+        }
+    }
+
+    /// Records a write that was just flushed to the OS, returns its sequence.
+    /// Must be called while holding the wal file lock.
+    fn on_write(&self) -> u64 {
+        self.dirty.store(true, Ordering::SeqCst);
+        self.num_writes.fetch_add(1, Ordering::SeqCst) + 1
+    }
+
+    /// Makes the write of sequence `write_seq` durable according to the mode,
+    /// `force` makes it durable whatever the mode.
+    fn sync(&self, write_seq: u64, force: bool) -> LiteDbResult<()> {
+        match self.mode {
+            WalSyncMode::None | WalSyncMode::Periodic(_) if !force => Ok(()),
+            WalSyncMode::None | WalSyncMode::Periodic(_) | WalSyncMode::EveryWrite => {
+                self.file.sync_data().map_err(LiteDbError::from)
+            }
+            WalSyncMode::GroupCommit { max_delay } => self.group_commit(write_seq, max_delay),
+        }
+    }
+
+    /// The first waiting writer becomes the leader and syncs on behalf of
+    /// every write flushed so far, the others wait for it to complete.
+    fn group_commit(&self, write_seq: u64, max_delay: Duration) -> LiteDbResult<()> {
+        let mut state = self.group_commit_state.lock();
+        loop {
+            if state.num_synced_writes >= write_seq {
+                return Ok(());
+            }
+            if state.syncing {
+                self.group_commit_condvar.wait(&mut state);
+                continue;
+            }
+
+            state.syncing = true;
+            drop(state);
+            if !max_delay.is_zero() {
+                // give concurrent writers a chance to join this sync
+                thread::sleep(max_delay);
+            }
+            let num_writes = self.num_writes.load(Ordering::SeqCst);
+            let sync_result = self.file.sync_data();
+
+            state = self.group_commit_state.lock();
+            state.syncing = false;
+            if sync_result.is_ok() {
+                state.num_synced_writes = state.num_synced_writes.max(num_writes);
+            }
+            self.group_commit_condvar.notify_all();
+            sync_result?;
+        }
+    }
+}
+
+impl Drop for WalSyncer {
+    fn drop(&mut self) {
+        if let Some((kill_signal_sender, task_handle)) = self.periodic_task.take() {
+            kill_signal_sender.send(()).unwrap();
+            task_handle.join().unwrap();
+        }
+    }
+}
+
+/// Size of a wal block, a record that does not fit in what is left of
+/// a block is split into fragments.
+const BLOCK_SIZE: usize = 32 * 1024;
+/// checksum (4 bytes), payload length (2 bytes), record type (1 byte).
+const HEADER_SIZE: usize = 4 + 2 + 1;
+/// Starts every wal file, followed by its format version.
+const WAL_MAGIC: u32 = 0x4c57_414c;
+const WAL_FORMAT_VERSION: u32 = 1;
+/// magic (4 bytes), format version (4 bytes), at the start of the first block.
+const WAL_HEADER_SIZE: usize = 4 + 4;
+
+/// Fails unless the wal file starts with the header of the current format.
+fn check_wal_header(path: &Path, header: &[u8]) -> LiteDbResult<()> {
+    let magic = LittleEndian::read_u32(&header[0..4]);
+    // wals written before the header existed start with a record
+    let version = match magic {
+        WAL_MAGIC => LittleEndian::read_u32(&header[4..8]),
+        _ => 0,
+    };
+    if version != WAL_FORMAT_VERSION {
+        return Err(LiteDbError::UnsupportedFormat {
+            path: path.to_path_buf(),
+            version,
+            supported: WAL_FORMAT_VERSION,
+        });
+    }
+    Ok(())
+}
+
+/// The type of a physical record.
+///
+/// A logical record that fits in the current block is written as a single
+/// FULL record, otherwise it is split into FIRST, MIDDLE(s) and LAST.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum RecordType {
+    /// Zeroed space, used to pad the end of a block.
+    Zero = 0,
+    Full = 1,
+    First = 2,
+    Middle = 3,
+    Last = 4,
+}
+
+impl RecordType {
+    fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(RecordType::Zero),
+            1 => Some(RecordType::Full),
+            2 => Some(RecordType::First),
+            3 => Some(RecordType::Middle),
+            4 => Some(RecordType::Last),
+            _ => None,
+        }
+    }
+}
+
+/// The checksum of a record covers its length, its type and its payload.
+fn record_checksum(header: &[u8], payload: &[u8]) -> u32 {
+    crc32(&header[4..HEADER_SIZE], payload)
+}
+
+/// Writes logical records as physical records framed in fixed-size blocks.
+#[derive(Debug)]
+struct LogWriter {
+    writer: BufWriter<File>,
+    block_offset: usize,
+}
+
+impl LogWriter {
+    fn new(file: File, file_len: u64) -> LiteDbResult<Self> {
+        // a wal torn before its header was complete holds no record
+        if file_len < WAL_HEADER_SIZE as u64 {
+            file.set_len(0)?;
+            let mut log_writer = Self {
+                writer: BufWriter::new(file),
+                block_offset: WAL_HEADER_SIZE,
+            };
+            log_writer.writer.write_u32::<LittleEndian>(WAL_MAGIC)?;
+            log_writer
+                .writer
+                .write_u32::<LittleEndian>(WAL_FORMAT_VERSION)?;
+            log_writer.flush()?;
+            return Ok(log_writer);
+        }
+        let mut log_writer = Self {
+            writer: BufWriter::new(file),
+            block_offset: (file_len % BLOCK_SIZE as u64) as usize,
+        };
+        // The last block may end with a torn record, appending behind it
+        // would get the new records dropped along with it on replay.
+        if log_writer.block_offset != 0 {
+            log_writer.pad_block()?;
+            log_writer.flush()?;
+        }
+        Ok(log_writer)
+    }
+
+    fn add_record(&mut self, data: &[u8]) -> LiteDbResult<()> {
+        let mut remaining = data;
+        let mut is_first = true;
+        loop {
+            if BLOCK_SIZE - self.block_offset < HEADER_SIZE {
+                self.pad_block()?;
+            }
+            let available = BLOCK_SIZE - self.block_offset - HEADER_SIZE;
+            let fragment_len = remaining.len().min(available);
+            let is_last = fragment_len == remaining.len();
+            let record_type = match (is_first, is_last) {
+                (true, true) => RecordType::Full,
+                (true, false) => RecordType::First,
+                (false, true) => RecordType::Last,
+                (false, false) => RecordType::Middle,
+            };
+            self.write_physical_record(record_type, &remaining[..fragment_len])?;
+            remaining = &remaining[fragment_len..];
+            is_first = false;
+            if is_last {
+                return Ok(());
+            }
+        }
+    }
+
+    fn write_physical_record(
+        &mut self,
+        record_type: RecordType,
+        payload: &[u8],
+    ) -> LiteDbResult<()> {
+        let mut header = [0u8; HEADER_SIZE];
+        LittleEndian::write_u16(&mut header[4..6], payload.len() as u16);
+        header[6] = record_type as u8;
+        let checksum = record_checksum(&header, payload);
+        LittleEndian::write_u32(&mut header[0..4], checksum);
+        self.writer.write_all(&header)?;
+        self.writer.write_all(payload)?;
+        self.block_offset += HEADER_SIZE + payload.len();
+        Ok(())
+    }
+
+    /// Fills the rest of the current block with zeros.
+    fn pad_block(&mut self) -> LiteDbResult<()> {
+        let padding = vec![0u8; BLOCK_SIZE - self.block_offset];
+        self.writer.write_all(&padding)?;
+        self.block_offset = 0;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> LiteDbResult<()> {
+        self.writer.flush().map_err(LiteDbError::from)
+    }
+}
+
 #[derive(Debug)]
 pub(crate) struct WriteAheadLogger {
     id: u64,
-    file: RwLock<BufWriter<File>>,
+    writer: RwLock<LogWriter>,
+    /// Number of records in the wal, replayed ones included.
+    num_records: AtomicU64,
+    syncer: WalSyncer,
     dir: PathBuf,
 }
 
 impl WriteAheadLogger {
-    pub(crate) fn open(dir: PathBuf, id: u64) -> LiteDbResult<Self> {
+    pub(crate) fn open(dir: PathBuf, id: u64, sync_mode: WalSyncMode) -> LiteDbResult<Self> {
         let log_file_path = wal_file_path(&dir, id);
-        let file = if log_file_path.exists() {
-            OpenOptions::new()
-                .read(true)
-                .write(true)
-                .open(log_file_path)?
-        } else {
-            let file = OpenOptions::new()
-                .read(true)
-                .write(true)
-                .create(true)
-                .open(log_file_path)?;
-            file
-        };
+        let file = OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(log_file_path)?;
+        let file_len = file.metadata()?.len();
+
+        let syncer = WalSyncer::new(sync_mode, file.try_clone()?);
+        let writer = RwLock::new(LogWriter::new(file, file_len)?);
+        Ok(Self {
+            id,
+            writer,
+            num_records: AtomicU64::new(0),
+            syncer,
+            dir,
+        })
+    }
 
-        let file = RwLock::new(BufWriter::new(file));
-        Ok(Self { id, file, dir })
+    /// Replays the wal into `apply` following `recovery_mode`, then opens it.
+    ///
+    /// When replay stops at a corruption, the wal is cut right after the
+    /// last replayed record, new records would be dropped along with the
+    /// corrupted ones otherwise.
+    pub(crate) fn recover<F: FnMut(LogItem)>(
+        dir: PathBuf,
+        id: u64,
+        sync_mode: WalSyncMode,
+        recovery_mode: WalRecoveryMode,
+        apply: F,
+    ) -> LiteDbResult<(Self, WalRecoveryReport)> {
+        let log_file_path = wal_file_path(&dir, id);
+        let (report, valid_end, num_records) = replay_file(&log_file_path, recovery_mode, apply)?;
+        let stops_at_corruption = matches!(
+            recovery_mode,
+            WalRecoveryMode::TolerateCorruptedTailRecords | WalRecoveryMode::PointInTime
+        );
+        if stops_at_corruption && !report.is_clean() {
+            let file = OpenOptions::new().write(true).open(&log_file_path)?;
+            file.set_len(valid_end)?;
+            file.sync_all()?;
+        }
+        let wal = Self::open(dir, id, sync_mode)?;
+        wal.num_records.store(num_records, Ordering::SeqCst);
+        Ok((wal, report))
     }
 
-    pub(crate) fn append(&self, key: RefKey, value: RefValue) -> LiteDbResult<()> {
+    /// Replays the wal into `apply` following `recovery_mode`, leaving the file untouched.
+    pub(crate) fn replay<F: FnMut(LogItem)>(
+        dir: &Path,
+        id: u64,
+        recovery_mode: WalRecoveryMode,
+        apply: F,
+    ) -> LiteDbResult<WalRecoveryReport> {
+        replay_file(&wal_file_path(dir, id), recovery_mode, apply).map(|(report, _, _)| report)
+    }
+
+    /// Appends an entry, `sync` makes it durable before returning.
+    pub(crate) fn append(&self, key: RefKey, value: RefValue, sync: bool) -> LiteDbResult<()> {
         let log_item = LogItem::new(key.to_owned(), value.to_owned());
-        let mut file_lock_guard = self.file.write();
-        encode_into_writer(&log_item, &mut file_lock_guard.by_ref())?;
-        file_lock_guard.flush().map_err(LiteDbError::from)
+        self.write_record(&WalRecord::new(vec![log_item]), sync)
     }
 
-    pub(crate) fn apply_batch(&self, operations: &[(Key, Value)]) -> LiteDbResult<()> {
-        let mut file_lock_guard = self.file.write();
-        for operation in operations {
-            let log_item = LogItem::new(operation.0.clone(), operation.1.clone());
-            encode_into_writer(&log_item, &mut file_lock_guard.by_ref())?;
-        }
-        file_lock_guard.flush().map_err(LiteDbError::from)
+    /// Logs the batch as a single record, so a torn batch is dropped as a whole.
+    pub(crate) fn apply_batch(&self, operations: &[(Key, Value)], sync: bool) -> LiteDbResult<()> {
+        let log_items = operations
+            .iter()
+            .map(|(key, value)| LogItem::new(key.clone(), value.clone()))
+            .collect::<Vec<_>>();
+        self.write_record(&WalRecord::new(log_items), sync)
     }
 
-    pub(crate) fn iter(&self) -> WriteAheadLogIter {
-        let file = self
-            .file
-            .write()
-            .get_ref()
-            .try_clone()
-            .expect("Expected a valid file handle.");
-        WriteAheadLogIter::new(file)
+    /// Appends a record as is, its timestamp included.
+    pub(crate) fn append_record(&self, record: &WalRecord) -> LiteDbResult<()> {
+        self.write_record(record, false)
+    }
+
+    fn write_record(&self, record: &WalRecord, sync: bool) -> LiteDbResult<()> {
+        let mut payload = Vec::new();
+        encode_into_writer(record, &mut payload)?;
+        let write_seq = {
+            let mut writer_lock_guard = self.writer.write();
+            writer_lock_guard.add_record(&payload)?;
+            writer_lock_guard.flush()?;
+            self.num_records.fetch_add(1, Ordering::SeqCst);
+            self.syncer.on_write()
+        };
+        self.syncer.sync(write_seq, sync)
     }
 
     pub fn remove(&self) -> LiteDbResult<()> {
         fs::remove_file(self.file_path()).map_err(LiteDbError::from)
     }
 
+    /// Returns the length of the wal file, the end of its last complete record.
+    pub fn end_offset(&self) -> LiteDbResult<u64> {
+        // records are written & flushed under the write lock
+        let writer_lock_guard = self.writer.read();
+        Ok(writer_lock_guard.writer.get_ref().metadata()?.len())
+    }
+
     pub fn file_path(&self) -> PathBuf {
         wal_file_path(&self.dir, self.id)
     }
+
+    /// Returns the sequence number of the last record of the wal.
+    pub fn last_sequence_number(&self) -> u64 {
+        sequence_number(self.id, self.num_records.load(Ordering::SeqCst))
+    }
 }
 
-fn wal_file_path(dir: &Path, id: u64) -> PathBuf {
+pub(crate) fn wal_file_path(dir: &Path, id: u64) -> PathBuf {
     dir.join(format!("{:01$}.{WAL_FILE_EXTENSION}", id, 20))
 }
 
+/// Replays a wal file if it exists, returns the offset right after
+/// the last replayed record and the number of replayed records.
+fn replay_file<F: FnMut(LogItem)>(
+    log_file_path: &Path,
+    recovery_mode: WalRecoveryMode,
+    mut apply: F,
+) -> LiteDbResult<(WalRecoveryReport, u64, u64)> {
+    if !log_file_path.exists() {
+        return Ok((WalRecoveryReport::default(), 0, 0));
+    }
+    let mut iter = WriteAheadLogIter::open(log_file_path, recovery_mode)?;
+    for item_result in iter.by_ref() {
+        apply(item_result?);
+    }
+    Ok((iter.report(), iter.valid_end(), iter.num_records()))
+}
+
+/// A region of the wal skipped during replay.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct SkippedRegion {
+    /// File offset of the first skipped byte.
+    pub offset: u64,
+    pub num_bytes: u64,
+    pub reason: &'static str,
+}
+
+enum PhysicalRecord {
+    Record(RecordType, Range<usize>),
+    /// Nothing readable is left in the current block.
+    EndOfBlock,
+    Eof,
+}
+
+/// A logical record being assembled from its fragments.
+struct PartialRecord {
+    offset: u64,
+    end_offset: u64,
+    payload: Vec<u8>,
+}
+
+/// Replays the records of a wal.
+///
+/// A corrupted record makes the reader drop what is left of its block and
+/// resynchronize on the next one. Every skipped region is recorded, the
+/// recovery mode then decides whether the records after it are replayed.
 pub(crate) struct WriteAheadLogIter {
     reader: BufReader<File>,
+    recovery_mode: WalRecoveryMode,
+    block: Vec<u8>,
+    block_start: u64,
+    block_pos: usize,
+    eof: bool,
+    pending: VecDeque<LogItem>,
+    skipped: Vec<SkippedRegion>,
+    /// Number and size of the valid records dropped after a corruption.
+    dropped_records: (usize, u64),
+    /// File offset right after the last replayed record.
+    valid_end: u64,
+    num_records: u64,
+    failed: bool,
 }
 
 impl WriteAheadLogIter {
-    pub(crate) fn new(file: File) -> Self {
-        let mut reader = BufReader::new(file);
-        reader.seek(SeekFrom::Start(0)).unwrap();
-        Self { reader }
+    /// Opens the wal file, failing unless it is in the current format.
+    pub(crate) fn open(path: &Path, recovery_mode: WalRecoveryMode) -> LiteDbResult<Self> {
+        let mut iter = Self {
+            reader: BufReader::new(File::open(path)?),
+            recovery_mode,
+            block: Vec::with_capacity(BLOCK_SIZE),
+            block_start: 0,
+            block_pos: 0,
+            eof: false,
+            pending: VecDeque::new(),
+            skipped: Vec::new(),
+            dropped_records: (0, 0),
+            valid_end: 0,
+            num_records: 0,
+            failed: false,
+        };
+        iter.read_block()?;
+        match iter.block.len() >= WAL_HEADER_SIZE {
+            true => {
+                check_wal_header(path, &iter.block)?;
+                iter.block_pos = WAL_HEADER_SIZE;
+                iter.valid_end = WAL_HEADER_SIZE as u64;
+            }
+            // a wal torn before its header was complete holds no record
+            false => iter.block_pos = iter.block.len(),
+        }
+        Ok(iter)
     }
-}
 
-impl Iterator for WriteAheadLogIter {
-    type Item = Result<LogItem, LiteDbError>;
+    /// Returns what was dropped so far.
+    pub(crate) fn report(&self) -> WalRecoveryReport {
+        let (num_records, num_bytes) = self.dropped_records;
+        let skipped_bytes: u64 = self.skipped.iter().map(|region| region.num_bytes).sum();
+        WalRecoveryReport {
+            num_records_dropped: num_records + self.skipped.len(),
+            num_bytes_dropped: num_bytes + skipped_bytes,
+        }
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
-        let log_item_result = decode_from_reader::<LogItem, _>(&mut self.reader);
-        let log_item = match log_item_result {
-            Ok(log_item) => log_item,
-            Err(err) => {
-                return match err {
-                    LiteDbError::Decoding(_) => return None,
-                    _ => Some(Err(err)),
+    pub(crate) fn valid_end(&self) -> u64 {
+        self.valid_end
+    }
+
+    /// Returns the number of records replayed so far.
+    pub(crate) fn num_records(&self) -> u64 {
+        self.num_records
+    }
+
+    /// Returns the next logical record following the recovery mode.
+    pub(crate) fn next_record(&mut self) -> Option<LiteDbResult<WalRecord>> {
+        loop {
+            if self.failed {
+                return None;
+            }
+            let record = match self.read_record() {
+                Ok(record) => record,
+                Err(err) => {
+                    self.failed = true;
+                    return Some(Err(err));
+                }
+            };
+
+            if let Some(region) = self.skipped.first() {
+                let fails = match self.recovery_mode {
+                    WalRecoveryMode::AbsoluteConsistency => true,
+                    // valid records follow, the corruption is not a torn tail
+                    WalRecoveryMode::TolerateCorruptedTailRecords => record.is_some(),
+                    WalRecoveryMode::PointInTime | WalRecoveryMode::SkipAnyCorruptedRecords => {
+                        false
+                    }
+                };
+                if fails {
+                    self.failed = true;
+                    return Some(Err(LiteDbError::CorruptedWal {
+                        offset: region.offset,
+                        reason: region.reason,
+                    }));
                 }
             }
-        };
 
-        if log_item.is_empty() {
-            // end of stream
-            return None;
+            let (record_offset, payload) = record?;
+            if !self.skipped.is_empty() && self.recovery_mode == WalRecoveryMode::PointInTime {
+                self.dropped_records.0 += 1;
+                self.dropped_records.1 += self.file_offset() - record_offset;
+                continue;
+            }
+            self.valid_end = self.file_offset();
+            return match decode::<WalRecord>(&payload) {
+                Ok((wal_record, _)) => {
+                    self.num_records += 1;
+                    Some(Ok(wal_record))
+                }
+                Err(err) => {
+                    self.failed = true;
+                    Some(Err(err))
+                }
+            };
         }
+    }
 
-        if !log_item.check() {
-            return Some(Err(LiteDbError::CorruptedData));
+    /// Returns the regions skipped so far.
+    #[cfg(test)]
+    pub(crate) fn skipped(&self) -> &[SkippedRegion] {
+        &self.skipped
+    }
+
+    fn skip(&mut self, offset: u64, num_bytes: u64, reason: &'static str) {
+        if num_bytes > 0 {
+            self.skipped.push(SkippedRegion {
+                offset,
+                num_bytes,
+                reason,
+            });
         }
+    }
+
+    fn file_offset(&self) -> u64 {
+        self.block_start + self.block_pos as u64
+    }
+
+    fn read_block(&mut self) -> LiteDbResult<()> {
+        self.block_start += self.block.len() as u64;
+        self.block_pos = 0;
+        self.block.clear();
+        self.reader
+            .by_ref()
+            .take(BLOCK_SIZE as u64)
+            .read_to_end(&mut self.block)?;
+        self.eof = self.block.len() < BLOCK_SIZE;
+        Ok(())
+    }
+
+    /// Skips what is left of the current block.
+    fn drop_block(&mut self, reason: &'static str) {
+        let offset = self.file_offset();
+        let num_bytes = (self.block.len() - self.block_pos) as u64;
+        self.block_pos = self.block.len();
+        self.skip(offset, num_bytes, reason);
+    }
+
+    fn read_physical_record(&mut self) -> LiteDbResult<PhysicalRecord> {
+        if self.block.len() - self.block_pos < HEADER_SIZE {
+            if self.eof {
+                self.drop_block("truncated record header");
+                return Ok(PhysicalRecord::Eof);
+            }
+            // a block trailer too small for a header is padding
+            self.read_block()?;
+            return Ok(PhysicalRecord::EndOfBlock);
+        }
+
+        let header = &self.block[self.block_pos..self.block_pos + HEADER_SIZE];
+        let checksum = LittleEndian::read_u32(&header[0..4]);
+        let length = LittleEndian::read_u16(&header[4..6]) as usize;
+        let record_type = RecordType::from_u8(header[6]);
+        let payload_range = self.block_pos + HEADER_SIZE..self.block_pos + HEADER_SIZE + length;
+
+        if record_type == Some(RecordType::Zero) && length == 0 && checksum == 0 {
+            self.block_pos = self.block.len();
+            return Ok(PhysicalRecord::EndOfBlock);
+        }
+        if payload_range.end > self.block.len() {
+            if self.eof {
+                self.drop_block("truncated record");
+            } else {
+                self.drop_block("bad record length");
+            }
+            return Ok(PhysicalRecord::EndOfBlock);
+        }
+        // the length is not trustworthy either, resync on the next block
+        if record_checksum(header, &self.block[payload_range.clone()]) != checksum {
+            self.drop_block("checksum mismatch");
+            return Ok(PhysicalRecord::EndOfBlock);
+        }
+        match record_type {
+            Some(record_type) if record_type != RecordType::Zero => {
+                self.block_pos = payload_range.end;
+                Ok(PhysicalRecord::Record(record_type, payload_range))
+            }
+            _ => {
+                self.drop_block("unknown record type");
+                Ok(PhysicalRecord::EndOfBlock)
+            }
+        }
+    }
+
+    /// Assembles the next logical record from its fragments,
+    /// returns its file offset and its payload.
+    fn read_record(&mut self) -> LiteDbResult<Option<(u64, Vec<u8>)>> {
+        let mut partial_record: Option<PartialRecord> = None;
+        loop {
+            let record_offset = self.file_offset();
+            let (record_type, payload_range) = match self.read_physical_record()? {
+                PhysicalRecord::Record(record_type, payload_range) => (record_type, payload_range),
+                PhysicalRecord::EndOfBlock => continue,
+                PhysicalRecord::Eof => {
+                    if let Some(partial) = partial_record {
+                        self.skip_partial_record(partial);
+                    }
+                    return Ok(None);
+                }
+            };
+            let end_offset = self.file_offset();
+
+            match record_type {
+                RecordType::Full | RecordType::First => {
+                    if let Some(partial) = partial_record.take() {
+                        self.skip_partial_record(partial);
+                    }
+                    let payload = self.block[payload_range].to_vec();
+                    if record_type == RecordType::Full {
+                        return Ok(Some((record_offset, payload)));
+                    }
+                    partial_record = Some(PartialRecord {
+                        offset: record_offset,
+                        end_offset,
+                        payload,
+                    });
+                }
+                RecordType::Middle | RecordType::Last => match partial_record.as_mut() {
+                    Some(partial) => {
+                        partial
+                            .payload
+                            .extend_from_slice(&self.block[payload_range]);
+                        partial.end_offset = end_offset;
+                        if record_type == RecordType::Last {
+                            return Ok(
+                                partial_record.map(|partial| (partial.offset, partial.payload))
+                            );
+                        }
+                    }
+                    None => self.skip(
+                        record_offset,
+                        end_offset - record_offset,
+                        "missing start of fragmented record",
+                    ),
+                },
+                RecordType::Zero => unreachable!("Zero records are never returned."),
+            }
+        }
+    }
+
+    fn skip_partial_record(&mut self, partial: PartialRecord) {
+        self.skip(
+            partial.offset,
+            partial.end_offset - partial.offset,
+            "incomplete fragmented record",
+        );
+    }
+}
 
-        Some(Ok(log_item))
+impl Iterator for WriteAheadLogIter {
+    type Item = Result<LogItem, LiteDbError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(log_item) = self.pending.pop_front() {
+                return Some(Ok(log_item));
+            }
+            match self.next_record()? {
+                Ok(wal_record) => self.pending.extend(wal_record.log_items),
+                Err(err) => return Some(Err(err)),
+            }
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::WriteAheadLogger;
+    use std::{
+        fs::OpenOptions,
+        io::{Seek, SeekFrom, Write},
+        path::Path,
+        sync::Arc,
+        thread,
+        time::Duration,
+    };
+
+    use super::{WalRecoveryMode, WalSyncMode, WriteAheadLogIter, WriteAheadLogger, BLOCK_SIZE};
+    use crate::{error::LiteDbResult, LiteDbError};
     use anyhow::Ok;
     use tempfile::tempdir;
 
+    fn replay(
+        wal: &WriteAheadLogger,
+        recovery_mode: WalRecoveryMode,
+    ) -> anyhow::Result<WriteAheadLogIter> {
+        Ok(WriteAheadLogIter::open(&wal.file_path(), recovery_mode)?)
+    }
+
+    /// Writes `num_records` records and damages the wal at `offset`,
+    /// a negative offset cuts that many bytes off the end.
+    fn damaged_wal(dir: &Path, num_records: usize, offset: i64) -> anyhow::Result<()> {
+        let wal = WriteAheadLogger::open(dir.to_path_buf(), 1, WalSyncMode::None)?;
+        for i in 0..num_records {
+            let k = format!("k_{:05}", i);
+            wal.append(k.as_bytes(), b"v", false)?;
+        }
+        let mut file = OpenOptions::new().write(true).open(wal.file_path())?;
+        if offset < 0 {
+            let file_len = file.metadata()?.len();
+            file.set_len(file_len - offset.unsigned_abs())?;
+        } else {
+            file.seek(SeekFrom::Start(offset as u64))?;
+            file.write_all(b"garbage")?;
+        }
+        Ok(())
+    }
+
+    /// Returns the number of replayed records.
+    fn recover(dir: &Path, recovery_mode: WalRecoveryMode) -> LiteDbResult<usize> {
+        let mut num_replayed = 0;
+        WriteAheadLogger::recover(
+            dir.to_path_buf(),
+            1,
+            WalSyncMode::None,
+            recovery_mode,
+            |_| num_replayed += 1,
+        )
+        .map(|_| num_replayed)
+    }
+
     #[test]
     fn test_empty_wal() -> anyhow::Result<()> {
         let tempdir = tempdir()?;
         let dir = tempdir.path().to_path_buf();
-        let wal = WriteAheadLogger::open(dir, 1).unwrap();
-        assert_eq!(wal.iter().count(), 0);
+        let wal = WriteAheadLogger::open(dir, 1, WalSyncMode::None).unwrap();
+        assert_eq!(replay(&wal, WalRecoveryMode::default())?.count(), 0);
         Ok(())
     }
 
@@ -183,14 +917,14 @@ mod tests {
     fn test_wal() -> anyhow::Result<()> {
         let tempdir = tempdir()?;
         let dir = tempdir.path().to_path_buf();
-        let wal = WriteAheadLogger::open(dir, 1).unwrap();
+        let wal = WriteAheadLogger::open(dir, 1, WalSyncMode::None).unwrap();
         for i in 0..1000 {
             let k = format!("k_{}", i);
             let v = format!("v_{}", i);
-            wal.append(k.as_bytes(), v.as_bytes()).unwrap();
+            wal.append(k.as_bytes(), v.as_bytes(), false).unwrap();
         }
 
-        for (i, res) in wal.iter().enumerate() {
+        for (i, res) in replay(&wal, WalRecoveryMode::default())?.enumerate() {
             let log_item = res.unwrap();
             let expected = (
                 format!("k_{}", i).into_bytes(),
@@ -201,4 +935,192 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_wal_sync_modes() -> anyhow::Result<()> {
+        let modes = [
+            WalSyncMode::EveryWrite,
+            WalSyncMode::GroupCommit {
+                max_delay: Duration::from_millis(1),
+            },
+            WalSyncMode::Periodic(Duration::from_millis(5)),
+        ];
+        for mode in modes {
+            let tempdir = tempdir()?;
+            let dir = tempdir.path().to_path_buf();
+            let wal = Arc::new(WriteAheadLogger::open(dir, 1, mode).unwrap());
+
+            // concurrent writers all get acknowledged
+            let handles = (0..4)
+                .map(|t| {
+                    let wal = wal.clone();
+                    thread::spawn(move || {
+                        for i in 0..50 {
+                            let k = format!("k_{}_{}", t, i);
+                            wal.append(k.as_bytes(), b"v", false).unwrap();
+                        }
+                    })
+                })
+                .collect::<Vec<_>>();
+            for handle in handles {
+                handle.join().unwrap();
+            }
+            wal.apply_batch(&[(b"k".to_vec(), b"v".to_vec())], false)?;
+
+            assert_eq!(replay(&wal, WalRecoveryMode::default())?.count(), 201);
+            if let WalSyncMode::GroupCommit { .. } = mode {
+                let state = wal.syncer.group_commit_state.lock();
+                assert_eq!(state.num_synced_writes, 201);
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_wal_torn_writes() -> anyhow::Result<()> {
+        let tempdir = tempdir()?;
+        let dir = tempdir.path().to_path_buf();
+        let wal = WriteAheadLogger::open(dir.clone(), 1, WalSyncMode::None).unwrap();
+        for i in 0..3000 {
+            let k = format!("k_{:05}", i);
+            wal.append(k.as_bytes(), b"v", false).unwrap();
+        }
+        // spans several blocks
+        let large_value = vec![7u8; 2 * BLOCK_SIZE];
+        wal.append(b"large", &large_value, false).unwrap();
+        wal.append(b"last", b"v", false).unwrap();
+        let keys = |wal: &WriteAheadLogger| -> anyhow::Result<Vec<Vec<u8>>> {
+            let keys = replay(wal, WalRecoveryMode::SkipAnyCorruptedRecords)?
+                .map(|res| res.map(|item| item.key))
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(keys)
+        };
+        assert_eq!(keys(&wal)?.len(), 3002);
+
+        // corrupt a record of the first block, replay resumes on the next one
+        let mut file = OpenOptions::new().write(true).open(wal.file_path())?;
+        file.seek(SeekFrom::Start(1000))?;
+        file.write_all(b"garbage")?;
+        let mut iter = replay(&wal, WalRecoveryMode::SkipAnyCorruptedRecords)?;
+        let replayed = iter.by_ref().count();
+        assert!(replayed < 3002);
+        let skipped = iter.skipped().to_vec();
+        assert_eq!(skipped.len(), 2);
+        assert_eq!(skipped[0].reason, "checksum mismatch");
+        assert!(skipped[0].offset <= 1000);
+        assert_eq!(skipped[0].offset + skipped[0].num_bytes, BLOCK_SIZE as u64);
+        // the tail of the record that straddled the dropped block
+        assert_eq!(skipped[1].reason, "missing start of fragmented record");
+        assert_eq!(skipped[1].offset, BLOCK_SIZE as u64);
+        let replayed_keys = keys(&wal)?;
+        assert!(replayed_keys.contains(&b"large".to_vec()));
+        assert!(replayed_keys.contains(&b"last".to_vec()));
+
+        // tear the last record, the records appended after a reopen survive
+        let file_len = file.metadata()?.len();
+        file.set_len(file_len - 1)?;
+        drop(wal);
+        let wal = WriteAheadLogger::open(dir, 1, WalSyncMode::None).unwrap();
+        wal.append(b"after_reopen", b"v", false).unwrap();
+        let mut iter = replay(&wal, WalRecoveryMode::SkipAnyCorruptedRecords)?;
+        let replayed_keys = iter
+            .by_ref()
+            .map(|res| res.unwrap().key)
+            .collect::<Vec<_>>();
+        assert!(!replayed_keys.contains(&b"last".to_vec()));
+        assert_eq!(replayed_keys.last().unwrap(), b"after_reopen");
+        assert_eq!(iter.skipped().len(), 3);
+        assert_eq!(iter.skipped()[2].reason, "checksum mismatch");
+        Ok(())
+    }
+
+    #[test]
+    fn test_wal_format_version() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+        let wal_path = temp_dir.path().join(format!("{:020}.log", 1));
+
+        // wals written before the header existed start with a record
+        std::fs::write(&wal_path, [1u8; 64])?;
+        let result = recover(temp_dir.path(), WalRecoveryMode::default());
+        assert!(matches!(
+            result,
+            Err(LiteDbError::UnsupportedFormat {
+                version: 0,
+                supported: 1,
+                ..
+            })
+        ));
+
+        // a wal torn before its header was complete holds no record
+        std::fs::write(&wal_path, [0x4c, 0x41])?;
+        assert_eq!(recover(temp_dir.path(), WalRecoveryMode::default())?, 0);
+        let wal = WriteAheadLogger::open(temp_dir.path().to_path_buf(), 1, WalSyncMode::None)?;
+        wal.append(b"k", b"v", false)?;
+        drop(wal);
+        assert_eq!(
+            recover(temp_dir.path(), WalRecoveryMode::AbsoluteConsistency)?,
+            1
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_wal_recovery_modes() -> anyhow::Result<()> {
+        // a torn tail
+        for mode in [
+            WalRecoveryMode::TolerateCorruptedTailRecords,
+            WalRecoveryMode::PointInTime,
+            WalRecoveryMode::SkipAnyCorruptedRecords,
+        ] {
+            let temp_dir = tempdir()?;
+            damaged_wal(temp_dir.path(), 100, -1)?;
+            assert_eq!(recover(temp_dir.path(), mode)?, 99);
+        }
+        let temp_dir = tempdir()?;
+        damaged_wal(temp_dir.path(), 100, -1)?;
+        let result = recover(temp_dir.path(), WalRecoveryMode::AbsoluteConsistency);
+        assert!(matches!(result, Err(LiteDbError::CorruptedWal { .. })));
+
+        // a corruption in the middle, followed by valid records
+        for mode in [
+            WalRecoveryMode::AbsoluteConsistency,
+            WalRecoveryMode::TolerateCorruptedTailRecords,
+        ] {
+            let temp_dir = tempdir()?;
+            damaged_wal(temp_dir.path(), 3000, 1000)?;
+            let result = recover(temp_dir.path(), mode);
+            assert!(matches!(result, Err(LiteDbError::CorruptedWal { .. })));
+        }
+
+        let temp_dir = tempdir()?;
+        damaged_wal(temp_dir.path(), 3000, 1000)?;
+        let num_skip_any = recover(temp_dir.path(), WalRecoveryMode::SkipAnyCorruptedRecords)?;
+        assert!(num_skip_any > 1000 && num_skip_any < 3000);
+
+        let temp_dir = tempdir()?;
+        damaged_wal(temp_dir.path(), 3000, 1000)?;
+        let file_len = std::fs::metadata(temp_dir.path().join(format!("{:020}.log", 1)))?.len();
+        let (wal, report) = WriteAheadLogger::recover(
+            temp_dir.path().to_path_buf(),
+            1,
+            WalSyncMode::None,
+            WalRecoveryMode::PointInTime,
+            |_| (),
+        )?;
+        let num_point_in_time = replay(&wal, WalRecoveryMode::AbsoluteConsistency)?.count();
+        assert!(num_point_in_time < 100);
+        // the two corrupted regions and the valid records after them
+        assert_eq!(
+            report.num_records_dropped,
+            2 + num_skip_any - num_point_in_time
+        );
+        assert!(report.num_bytes_dropped > 0 && report.num_bytes_dropped < file_len);
+
+        // the dropped records were cut off, new records are replayed after a reopen
+        wal.append(b"after_recovery", b"v", false)?;
+        drop(wal);
+        let num_replayed = recover(temp_dir.path(), WalRecoveryMode::AbsoluteConsistency)?;
+        assert_eq!(num_replayed, num_point_in_time + 1);
+        Ok(())
+    }
 }
@@ -0,0 +1,393 @@
+use std::{
+    collections::BTreeSet,
+    fs::{self, File},
+    path::Path,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    error::{LiteDbError, LiteDbResult},
+    ss_table::is_ss_table_file,
+    wal::{
+        is_mem_table_file, sequence_number, wal_file_path, WalRecord, WalRecoveryMode, WalSyncMode,
+        WriteAheadLogIter, WriteAheadLogger,
+    },
+};
+
+const WAL_ARCHIVE_DIR_NAME: &str = "archive";
+
+/// Moves the wals of flushed mem_tables to the `archive` directory of the database
+/// instead of deleting them, archived wals are deleted following the retention.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct WalArchiveOptions {
+    /// Archived wals older than this are deleted.
+    pub max_age: Option<Duration>,
+    /// The oldest archived wals are deleted once they take more than this.
+    pub max_size_bytes: Option<u64>,
+}
+
+/// Where a point-in-time restore stops replaying the archived wals.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RestoreTarget {
+    /// Every archived record is replayed.
+    Latest,
+    /// The records up to this sequence number, included, are replayed.
+    SequenceNumber(u64),
+    /// The records logged up to this time, included, are replayed.
+    Timestamp(SystemTime),
+}
+
+impl RestoreTarget {
+    fn includes(&self, sequence_number: u64, record: &WalRecord) -> bool {
+        match self {
+            RestoreTarget::Latest => true,
+            RestoreTarget::SequenceNumber(target) => sequence_number <= *target,
+            RestoreTarget::Timestamp(target) => {
+                let target = target
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0, |duration| duration.as_micros() as u64);
+                record.timestamp <= target
+            }
+        }
+    }
+}
+
+/// Moves the wal into the archive directory next to it, then applies the retention.
+pub(crate) fn archive_wal(wal_path: &Path, options: &WalArchiveOptions) -> LiteDbResult<()> {
+    let dir = wal_path.parent().expect("Expected a database directory");
+    let archive_dir = dir.join(WAL_ARCHIVE_DIR_NAME);
+    fs::create_dir_all(&archive_dir)?;
+    let file_name = wal_path.file_name().expect("Expected a wal file name");
+    fs::rename(wal_path, archive_dir.join(file_name))?;
+    purge_archive(&archive_dir, options)
+}
+
+/// Deletes the archived wals past their age, then the oldest ones past the size limit.
+fn purge_archive(archive_dir: &Path, options: &WalArchiveOptions) -> LiteDbResult<()> {
+    // wal file names are zero padded ids, oldest first
+    let mut archived_wals = vec![];
+    for entry_result in fs::read_dir(archive_dir)? {
+        let path = entry_result?.path();
+        if is_mem_table_file(&path) {
+            let metadata = fs::metadata(&path)?;
+            archived_wals.push((path, metadata.len(), metadata.modified()?));
+        }
+    }
+    archived_wals.sort();
+
+    if let Some(max_age) = options.max_age {
+        let mut remaining_wals = vec![];
+        for (path, len, modified) in archived_wals {
+            if matches!(modified.elapsed(), Ok(age) if age > max_age) {
+                fs::remove_file(&path)?;
+            } else {
+                remaining_wals.push((path, len, modified));
+            }
+        }
+        archived_wals = remaining_wals;
+    }
+    if let Some(max_size_bytes) = options.max_size_bytes {
+        let mut total_size_bytes: u64 = archived_wals.iter().map(|(_, len, _)| len).sum();
+        for (path, len, _) in &archived_wals {
+            if total_size_bytes <= max_size_bytes {
+                break;
+            }
+            fs::remove_file(path)?;
+            total_size_bytes -= len;
+        }
+    }
+    Ok(())
+}
+
+/// Rewrites the wals of a restored database that no ss_table holds yet from
+/// the archived ones, keeping the records up to `target`.
+///
+/// The wal of a mem_table still being written when the database was backed up
+/// only holds a prefix of the archived one. Fails when the archive misses a wal
+/// needed to reach `target`, or when the backup already holds records past `target`.
+pub(crate) fn replay_archived_wals(
+    archive_dir: &Path,
+    db_dir: &Path,
+    target: &RestoreTarget,
+) -> LiteDbResult<()> {
+    // mem_tables are flushed in id order, ss_tables hold every wal up to the newest of them
+    let first_wal_id = file_ids(db_dir, is_ss_table_file)?
+        .last()
+        .map_or(0, |id| id + 1);
+    let archived_wal_ids = file_ids(archive_dir, is_mem_table_file)?;
+    let backed_up_wal_ids = file_ids(db_dir, is_mem_table_file)?
+        .into_iter()
+        .filter(|id| *id >= first_wal_id)
+        .collect::<Vec<_>>();
+
+    for wal_id in archived_wal_ids
+        .iter()
+        .rev()
+        .filter(|id| **id < first_wal_id)
+    {
+        let records = read_records(&wal_file_path(archive_dir, *wal_id))?;
+        if let Some(record) = records.last() {
+            if !target.includes(sequence_number(*wal_id, records.len() as u64), record) {
+                return Err(LiteDbError::RestoreTargetBeforeBackup);
+            }
+            break;
+        }
+    }
+
+    let wal_ids = backed_up_wal_ids
+        .iter()
+        .chain(archived_wal_ids.iter().filter(|id| **id >= first_wal_id))
+        .copied()
+        .collect::<BTreeSet<_>>();
+    let mut expected_wal_id = first_wal_id;
+    let mut truncated_wal_id = None;
+    let mut last_sequence_number = 0;
+    let mut target_reached = false;
+    for wal_id in wal_ids {
+        let archived_wal_path = wal_file_path(archive_dir, wal_id);
+        let wal_path = wal_file_path(db_dir, wal_id);
+        let mut backed_up_records = vec![];
+        if wal_path.exists() {
+            backed_up_records = read_records(&wal_path)?;
+            fs::remove_file(&wal_path)?;
+        }
+        if target_reached {
+            continue;
+        }
+        // the records logged after the backup into a wal whose archived copy is gone are lost
+        if let Some(wal_id) = truncated_wal_id {
+            return Err(LiteDbError::IncompleteWalArchive(wal_id));
+        }
+        // ids reserved for ingested files have no wal, their data cannot be replayed either
+        if wal_id != expected_wal_id {
+            return Err(LiteDbError::IncompleteWalArchive(expected_wal_id));
+        }
+        expected_wal_id = wal_id + 1;
+
+        let num_backed_up_records = backed_up_records.len();
+        let records = match archived_wal_path.exists() {
+            true => read_records(&archived_wal_path)?,
+            false => {
+                if backed_up_wal_ids.last() == Some(&wal_id) {
+                    truncated_wal_id = Some(wal_id);
+                }
+                backed_up_records
+            }
+        };
+        let wal = WriteAheadLogger::open(db_dir.to_path_buf(), wal_id, WalSyncMode::None)?;
+        for (index, record) in records.iter().enumerate() {
+            let sequence_number = sequence_number(wal_id, index as u64 + 1);
+            if !target.includes(sequence_number, record) {
+                if index < num_backed_up_records {
+                    return Err(LiteDbError::RestoreTargetBeforeBackup);
+                }
+                target_reached = true;
+                break;
+            }
+            wal.append_record(record)?;
+            last_sequence_number = sequence_number;
+        }
+        drop(wal);
+        File::open(&wal_path)?.sync_all()?;
+    }
+
+    if let (RestoreTarget::SequenceNumber(target), false) = (target, target_reached) {
+        let missing_wal_id = match truncated_wal_id {
+            Some(wal_id) if *target > last_sequence_number => Some(wal_id),
+            _ if *target > sequence_number(expected_wal_id, 0) => Some(expected_wal_id),
+            _ => None,
+        };
+        if let Some(wal_id) = missing_wal_id {
+            return Err(LiteDbError::IncompleteWalArchive(wal_id));
+        }
+    }
+    Ok(())
+}
+
+fn read_records(path: &Path) -> LiteDbResult<Vec<WalRecord>> {
+    let mut iter = WriteAheadLogIter::open(path, WalRecoveryMode::PointInTime)?;
+    let mut records = vec![];
+    while let Some(record_result) = iter.next_record() {
+        records.push(record_result?);
+    }
+    Ok(records)
+}
+
+/// Returns the ids of the files of `dir` matching `is_file`, ascending.
+fn file_ids(dir: &Path, is_file: fn(&Path) -> bool) -> LiteDbResult<Vec<u64>> {
+    let mut ids = vec![];
+    if !dir.exists() {
+        return Ok(ids);
+    }
+    for entry_result in fs::read_dir(dir)? {
+        let path = entry_result?.path();
+        if !is_file(&path) {
+            continue;
+        }
+        // ss_table names carry their level after the id
+        if let Some(id) = path
+            .file_stem()
+            .and_then(|stem| stem.to_string_lossy().split('_').next()?.parse().ok())
+        {
+            ids.push(id);
+        }
+    }
+    ids.sort_unstable();
+    Ok(ids)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs, thread,
+        time::{Duration, SystemTime},
+    };
+
+    use tempfile::tempdir;
+
+    use crate::{
+        backup::BackupEngine,
+        error::LiteDbError,
+        options::LiteDbOptions,
+        wal::{is_mem_table_file, wal_file_path},
+        wal_archive::{
+            file_ids, purge_archive, RestoreTarget, WalArchiveOptions, WAL_ARCHIVE_DIR_NAME,
+        },
+        LiteDb,
+    };
+
+    #[test]
+    fn test_point_in_time_restore() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+        let db_path = temp_dir.path().join("data");
+        let archive_path = db_path.join(WAL_ARCHIVE_DIR_NAME);
+        let backup_engine = BackupEngine::open(temp_dir.path().join("backup"))?;
+        let db = LiteDb::open(
+            &db_path,
+            LiteDbOptions {
+                wal_archive: Some(WalArchiveOptions::default()),
+                ..LiteDbOptions::for_test()
+            },
+        )?;
+        for i in 0..300 {
+            let k = format!("k_{:01$}", i, 3);
+            db.set(k.as_bytes(), b"v")?;
+        }
+        let backup_id = backup_engine.create_backup(&db)?;
+
+        // writes made after the backup are only found in the archived wals
+        for i in 0..50 {
+            let k = format!("k_{:01$}", i, 3);
+            db.set(k.as_bytes(), b"v2")?;
+        }
+        let sequence_number = db.latest_sequence_number();
+        thread::sleep(Duration::from_millis(5));
+        let timestamp = SystemTime::now();
+        thread::sleep(Duration::from_millis(5));
+        for i in 0..50 {
+            let k = format!("k_{:01$}", i, 3);
+            db.set(k.as_bytes(), b"v3")?;
+        }
+        db.flush()?;
+        drop(db);
+
+        for (name, target, expected_value) in [
+            (
+                "sequence",
+                RestoreTarget::SequenceNumber(sequence_number),
+                b"v2",
+            ),
+            ("timestamp", RestoreTarget::Timestamp(timestamp), b"v2"),
+            ("latest", RestoreTarget::Latest, b"v3"),
+        ] {
+            let restore_path = temp_dir.path().join(name);
+            backup_engine.restore_db_to_point_in_time(
+                backup_id,
+                &archive_path,
+                target,
+                &restore_path,
+            )?;
+            let db = LiteDb::open(&restore_path, LiteDbOptions::for_test())?;
+            for i in 0..300 {
+                let k = format!("k_{:01$}", i, 3);
+                let expected_value: &[u8] = if i < 50 { expected_value } else { b"v" };
+                assert_eq!(db.get(k.as_bytes())?, Some(expected_value.to_vec()));
+            }
+        }
+
+        // the oldest archived wals go first once over the size limit
+        let num_archived_wals = fs::read_dir(&archive_path)?.count();
+        let retention = WalArchiveOptions {
+            max_age: None,
+            max_size_bytes: Some(1),
+        };
+        purge_archive(&archive_path, &retention)?;
+        assert!(fs::read_dir(&archive_path)?.count() < num_archived_wals);
+        Ok(())
+    }
+
+    #[test]
+    fn test_point_in_time_restore_needs_the_archive_since_the_backup() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+        let db_path = temp_dir.path().join("data");
+        let archive_path = db_path.join(WAL_ARCHIVE_DIR_NAME);
+        let backup_engine = BackupEngine::open(temp_dir.path().join("backup"))?;
+        let db = LiteDb::open(
+            &db_path,
+            LiteDbOptions {
+                wal_archive: Some(WalArchiveOptions::default()),
+                ..LiteDbOptions::for_test()
+            },
+        )?;
+        for i in 0..100 {
+            let k = format!("k_{:01$}", i, 3);
+            db.set(k.as_bytes(), b"v")?;
+        }
+        let sequence_number = db.latest_sequence_number();
+        for i in 100..200 {
+            let k = format!("k_{:01$}", i, 3);
+            db.set(k.as_bytes(), b"v")?;
+        }
+        db.flush()?;
+        let backup_id = backup_engine.create_backup(&db)?;
+        for round in 0..3 {
+            let v = format!("v{round}");
+            db.set(b"k_000", v.as_bytes())?;
+            db.flush()?;
+        }
+        drop(db);
+
+        // the backup already holds the writes made after the target
+        let restore_path = temp_dir.path().join("before");
+        let result = backup_engine.restore_db_to_point_in_time(
+            backup_id,
+            &archive_path,
+            RestoreTarget::SequenceNumber(sequence_number),
+            &restore_path,
+        );
+        assert!(matches!(
+            result,
+            Err(LiteDbError::RestoreTargetBeforeBackup)
+        ));
+        assert!(!restore_path.exists());
+
+        // the retention removed a wal written after the backup
+        let archived_wal_ids = file_ids(&archive_path, is_mem_table_file)?;
+        let removed_wal_id = archived_wal_ids[archived_wal_ids.len() - 2];
+        fs::remove_file(wal_file_path(&archive_path, removed_wal_id))?;
+        let restore_path = temp_dir.path().join("gap");
+        let result = backup_engine.restore_db_to_point_in_time(
+            backup_id,
+            &archive_path,
+            RestoreTarget::Latest,
+            &restore_path,
+        );
+        assert!(matches!(
+            result,
+            Err(LiteDbError::IncompleteWalArchive(wal_id)) if wal_id == removed_wal_id
+        ));
+        assert!(!restore_path.exists());
+        assert_eq!(fs::read_dir(temp_dir.path())?.count(), 2);
+        Ok(())
+    }
+}
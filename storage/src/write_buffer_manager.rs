@@ -0,0 +1,186 @@
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Weak,
+    },
+};
+
+use crossbeam_skiplist::SkipSet;
+use parking_lot::Mutex;
+
+use crate::{controller::RotationTrigger, mem_table::MemTable};
+
+/// Bounds the memory of the mem_tables of every database sharing it.
+///
+/// Once the budget is exceeded, the largest mem_table still handling writes
+/// is flushed. Memory is released once mem_tables are flushed.
+#[derive(Clone)]
+pub struct WriteBufferManager {
+    inner: Arc<WriteBufferManagerInner>,
+}
+
+struct WriteBufferManagerInner {
+    buffer_size: usize,
+    memory_usage: AtomicUsize,
+    databases: Mutex<Vec<RegisteredDatabase>>,
+}
+
+struct RegisteredDatabase {
+    mem_tables: Weak<SkipSet<Arc<MemTable>>>,
+    rotation_trigger: RotationTrigger,
+}
+
+impl WriteBufferManager {
+    pub fn new(buffer_size: usize) -> Self {
+        Self {
+            inner: Arc::new(WriteBufferManagerInner {
+                buffer_size,
+                memory_usage: AtomicUsize::new(0),
+                databases: Mutex::new(vec![]),
+            }),
+        }
+    }
+
+    pub fn buffer_size(&self) -> usize {
+        self.inner.buffer_size
+    }
+
+    /// Returns the bytes held by the mem_tables of every database.
+    pub fn memory_usage(&self) -> usize {
+        self.inner.memory_usage.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn register(
+        &self,
+        mem_tables: &Arc<SkipSet<Arc<MemTable>>>,
+        rotation_trigger: RotationTrigger,
+    ) {
+        let mut databases = self.inner.databases.lock();
+        databases.retain(|database| database.mem_tables.strong_count() > 0);
+        databases.push(RegisteredDatabase {
+            mem_tables: Arc::downgrade(mem_tables),
+            rotation_trigger,
+        });
+    }
+
+    pub(crate) fn reserve(&self, num_bytes: usize) {
+        self.inner
+            .memory_usage
+            .fetch_add(num_bytes, Ordering::SeqCst);
+    }
+
+    pub(crate) fn free(&self, num_bytes: usize) {
+        let _ = self
+            .inner
+            .memory_usage
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |usage| {
+                Some(usage.saturating_sub(num_bytes))
+            });
+    }
+
+    /// Flushes the largest current mem_table once the budget is exceeded.
+    pub(crate) fn flush_if_needed(&self) {
+        if self.memory_usage() < self.inner.buffer_size {
+            return;
+        }
+        let databases = self.inner.databases.lock();
+        let mut mutable_memory = 0;
+        let mut largest: Option<(usize, &RotationTrigger)> = None;
+        for database in databases.iter() {
+            let mem_tables = match database.mem_tables.upgrade() {
+                Some(mem_tables) => mem_tables,
+                None => continue,
+            };
+            let size_bytes = mem_tables
+                .back()
+                .map_or(0, |entry| entry.value().size_bytes());
+            mutable_memory += size_bytes;
+            match largest {
+                Some((largest_size, _)) if largest_size >= size_bytes => (),
+                _ => largest = Some((size_bytes, &database.rotation_trigger)),
+            }
+        }
+        // the memory of mem_tables being flushed is about to be released,
+        // flushing more would only produce small ss_tables
+        if mutable_memory < self.inner.buffer_size / 2 {
+            return;
+        }
+        if let Some((_, rotation_trigger)) = largest {
+            rotation_trigger.request_flush();
+        }
+    }
+}
+
+impl fmt::Debug for WriteBufferManager {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WriteBufferManager")
+            .field("buffer_size", &self.buffer_size())
+            .field("memory_usage", &self.memory_usage())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use tempfile::tempdir;
+
+    use crate::{
+        controller::MemTableControllerPolicyConfig, error::LiteDbResult, options::LiteDbOptions,
+        write_buffer_manager::WriteBufferManager, LiteDb,
+    };
+
+    #[test]
+    fn test_write_buffer_manager() -> LiteDbResult<()> {
+        let temp_dir = tempdir()?;
+        let write_buffer_manager = WriteBufferManager::new(20_000);
+        let options = LiteDbOptions {
+            // the mem_tables never get full on their own
+            mem_table_controller_policy: MemTableControllerPolicyConfig::SizeTiered {
+                max_entries: 1_000_000,
+                max_size_bytes: 1_000_000,
+            },
+            write_buffer_manager: Some(write_buffer_manager.clone()),
+            ..LiteDbOptions::for_test()
+        };
+        let db_1 = LiteDb::open(temp_dir.path().join("db_1"), options.clone())?;
+        let db_2 = LiteDb::open(temp_dir.path().join("db_2"), options)?;
+
+        for i in 0..150 {
+            let k = format!("k_{:01$}", i, 3);
+            db_1.set(k.as_bytes(), b"v")?;
+        }
+        for i in 0..50 {
+            let k = format!("k_{:01$}", i, 3);
+            db_2.set(k.as_bytes(), b"v")?;
+        }
+        let memory_usage =
+            db_1.memory_usage().mem_tables_total + db_2.memory_usage().mem_tables_total;
+        assert_eq!(write_buffer_manager.memory_usage(), memory_usage);
+
+        // going over budget flushes the largest mem_table, the one of db_1
+        for i in 50..100 {
+            let k = format!("k_{:01$}", i, 3);
+            db_2.set(k.as_bytes(), b"v")?;
+        }
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while (db_1.ss_tables.is_empty()
+            || write_buffer_manager.memory_usage() >= write_buffer_manager.buffer_size())
+            && Instant::now() < deadline
+        {
+            thread::sleep(Duration::from_millis(5));
+        }
+        assert_eq!(db_1.ss_tables.len(), 1);
+        assert!(write_buffer_manager.memory_usage() < write_buffer_manager.buffer_size());
+        for i in 0..150 {
+            let k = format!("k_{:01$}", i, 3);
+            assert_eq!(db_1.get(k.as_bytes())?, Some(b"v".to_vec()));
+        }
+        Ok(())
+    }
+}
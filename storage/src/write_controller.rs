@@ -0,0 +1,222 @@
+use std::{sync::Arc, thread, time::Duration};
+
+use crossbeam_skiplist::SkipSet;
+
+use crate::{
+    background_error::BackgroundError,
+    controller::{MemTableController, MemTableControllerPolicy},
+    error::{LiteDbError, LiteDbResult},
+    mem_table::MemTable,
+    options::LiteDbOptions,
+    ss_table::SSTable,
+};
+
+/// Pause applied to every write while level 0 is above the slowdown trigger.
+const WRITE_SLOWDOWN_DELAY: Duration = Duration::from_millis(1);
+/// Interval at which a stopped write checks whether it may proceed.
+const WRITE_STOP_POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum WriteStallCondition {
+    Normal,
+    /// Level 0 is filling up, writes are slowed down so that compaction keeps up.
+    Delayed,
+    /// Writes wait until flushes or compactions make room.
+    Stopped,
+}
+
+/// Holds writes back when flushes or compactions fall behind.
+pub(crate) struct WriteController {
+    max_write_buffer_number: usize,
+    level0_slowdown_writes_trigger: usize,
+    level0_stop_writes_trigger: usize,
+    mem_table_policy: Arc<dyn MemTableControllerPolicy>,
+    background_error: BackgroundError,
+}
+
+impl WriteController {
+    pub fn new(options: &LiteDbOptions, background_error: BackgroundError) -> LiteDbResult<Self> {
+        // the current mem_table is only rotated while another write buffer is free
+        if options.max_write_buffer_number < 2 {
+            return Err(LiteDbError::PolicyError(
+                "max_write_buffer_number must be at least 2".to_string(),
+            ));
+        }
+        // fewer level 0 ss_tables than compaction merges would stop writes for good
+        let min_merge_width = options.compactor_policy.min_merge_width();
+        if options.level0_stop_writes_trigger < min_merge_width {
+            return Err(LiteDbError::PolicyError(format!(
+                "level0_stop_writes_trigger must be at least {min_merge_width}, \
+                 the number of level 0 ss_tables merged by a compaction"
+            )));
+        }
+        Ok(Self {
+            max_write_buffer_number: options.max_write_buffer_number,
+            level0_slowdown_writes_trigger: options.level0_slowdown_writes_trigger,
+            level0_stop_writes_trigger: options.level0_stop_writes_trigger,
+            mem_table_policy: MemTableController::create_policy(
+                &options.mem_table_controller_policy,
+            )?,
+            background_error,
+        })
+    }
+
+    pub fn condition(
+        &self,
+        mem_tables: &SkipSet<Arc<MemTable>>,
+        ss_tables: &SkipSet<Arc<SSTable>>,
+    ) -> WriteStallCondition {
+        // the current mem_table cannot be rotated without exceeding the write buffers
+        let write_buffers_full = mem_tables.len() >= self.max_write_buffer_number
+            && matches!(
+                mem_tables.back(),
+                Some(entry) if self.mem_table_policy.is_mature(entry.value())
+            );
+        let level0_tables = ss_tables
+            .iter()
+            .filter(|entry| entry.value().level() == 0)
+            .count();
+
+        if write_buffers_full || level0_tables >= self.level0_stop_writes_trigger {
+            WriteStallCondition::Stopped
+        } else if level0_tables >= self.level0_slowdown_writes_trigger {
+            WriteStallCondition::Delayed
+        } else {
+            WriteStallCondition::Normal
+        }
+    }
+
+    /// Waits until a write may proceed.
+    ///
+    /// With `no_slowdown`, a stalled write fails with `LiteDbError::WriteStall` instead.
+    /// Once a background flush or compaction failed, every write fails with its error.
+    /// `trigger_compaction` is called while level 0 is the cause of the stall.
+    pub fn wait(
+        &self,
+        mem_tables: &SkipSet<Arc<MemTable>>,
+        ss_tables: &SkipSet<Arc<SSTable>>,
+        no_slowdown: bool,
+        trigger_compaction: impl Fn(),
+    ) -> LiteDbResult<()> {
+        loop {
+            self.background_error.check()?;
+            let condition = self.condition(mem_tables, ss_tables);
+            if condition == WriteStallCondition::Normal {
+                return Ok(());
+            }
+            if no_slowdown {
+                return Err(LiteDbError::WriteStall);
+            }
+            trigger_compaction();
+            if condition == WriteStallCondition::Delayed {
+                thread::sleep(WRITE_SLOWDOWN_DELAY);
+                return Ok(());
+            }
+            thread::sleep(WRITE_STOP_POLL_INTERVAL);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crossbeam_skiplist::SkipSet;
+    use tempfile::tempdir;
+
+    use crate::{
+        background_error::BackgroundError,
+        controller::MemTableControllerPolicyConfig,
+        error::LiteDbError,
+        mem_table::{MemTable, MemTableOptions},
+        options::{LiteDbOptions, WriteOptions},
+        ss_table::SSTableOptions,
+        write_controller::{WriteController, WriteStallCondition},
+    };
+
+    #[test]
+    fn test_write_controller() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+        let dir = temp_dir.path().to_path_buf();
+        let background_error = BackgroundError::default();
+        let write_controller = WriteController::new(
+            &LiteDbOptions {
+                max_write_buffer_number: 2,
+                level0_slowdown_writes_trigger: 3,
+                level0_stop_writes_trigger: 4,
+                mem_table_controller_policy: MemTableControllerPolicyConfig::SizeTiered {
+                    max_entries: 10,
+                    max_size_bytes: 1_000_000,
+                },
+                ..LiteDbOptions::for_test()
+            },
+            background_error.clone(),
+        )?;
+
+        let mem_tables = SkipSet::new();
+        let ss_tables = SkipSet::new();
+        let new_mem_table = |id: u64| -> anyhow::Result<Arc<MemTable>> {
+            let mem_table = MemTable::open(dir.clone(), id, &MemTableOptions::default())?;
+            for i in 0..10 {
+                let k = format!("k_{i}");
+                mem_table.set(k.as_bytes(), b"v", &WriteOptions::default())?;
+            }
+            Ok(Arc::new(mem_table))
+        };
+        mem_tables.insert(new_mem_table(0)?);
+        assert_eq!(
+            write_controller.condition(&mem_tables, &ss_tables),
+            WriteStallCondition::Normal
+        );
+
+        // a full current mem_table with every write buffer in use stops writes
+        mem_tables.insert(new_mem_table(1)?);
+        assert_eq!(
+            write_controller.condition(&mem_tables, &ss_tables),
+            WriteStallCondition::Stopped
+        );
+        let result = write_controller.wait(&mem_tables, &ss_tables, true, || ());
+        assert!(matches!(result, Err(LiteDbError::WriteStall)));
+        mem_tables.pop_back();
+
+        // level 0 ss_tables slow writes down then stop them
+        for (id, expected_condition) in [
+            (2, WriteStallCondition::Normal),
+            (3, WriteStallCondition::Normal),
+            (4, WriteStallCondition::Delayed),
+            (5, WriteStallCondition::Stopped),
+        ] {
+            let ss_table = new_mem_table(id)?.save(10.0, &SSTableOptions::for_test())?;
+            ss_tables.insert(ss_table);
+            assert_eq!(
+                write_controller.condition(&mem_tables, &ss_tables),
+                expected_condition
+            );
+        }
+        let result = write_controller.wait(&mem_tables, &ss_tables, true, || ());
+        assert!(matches!(result, Err(LiteDbError::WriteStall)));
+
+        // a failed background work fails the writes rather than stalling them
+        while ss_tables.pop_back().is_some() {}
+        background_error.record(LiteDbError::CorruptedData);
+        let result = write_controller.wait(&mem_tables, &ss_tables, false, || ());
+        assert!(matches!(result, Err(LiteDbError::Background(_))));
+
+        // options under which writes would stall for good are rejected
+        for options in [
+            LiteDbOptions {
+                level0_stop_writes_trigger: 3,
+                ..LiteDbOptions::for_test()
+            },
+            LiteDbOptions {
+                max_write_buffer_number: 1,
+                ..LiteDbOptions::for_test()
+            },
+        ] {
+            let result = WriteController::new(&options, BackgroundError::default());
+            assert!(matches!(result, Err(LiteDbError::PolicyError(_))));
+        }
+
+        Ok(())
+    }
+}